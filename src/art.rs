@@ -0,0 +1,92 @@
+//! A local cache for the covers of the tracks. Some MPRIS clients can't download the covers from
+//! LMS, for example when it requires authentication. The covers are stored in a directory and
+//! exposed with `file://` URLs instead. The least recently used covers are removed when the cache
+//! grows over its maximum size.
+use crate::lms::LmsClient;
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::{
+    env,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::fs;
+
+#[derive(Clone)]
+pub struct ArtCache {
+    directory: PathBuf,
+    /// In bytes
+    max_size: u64,
+}
+
+impl ArtCache {
+    pub fn new(directory: PathBuf, max_size: u64) -> Self {
+        Self {
+            directory,
+            max_size,
+        }
+    }
+
+    /// `$XDG_CACHE_HOME/mprisqueeze`, falling back to `~/.cache/mprisqueeze`. The directory of an
+    /// instance is suffixed with its name.
+    pub fn default_directory(instance: Option<&str>) -> Result<PathBuf> {
+        let name = match instance {
+            Some(instance) => format!("mprisqueeze-{}", instance),
+            None => "mprisqueeze".to_string(),
+        };
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .map(|cache| cache.join(name))
+            .ok_or_else(|| anyhow!("Unable to find the cache directory"))
+    }
+
+    /// Get the path of the cover, downloading it if it is not in the cache yet
+    pub async fn get(&self, client: &LmsClient, cover_id: &str) -> Result<PathBuf> {
+        let file_name = cover_id
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect::<String>();
+        let path = self.directory.join(format!("{}.jpg", file_name));
+        if fs::try_exists(&path).await? {
+            // keep track of the last use for the eviction
+            std::fs::File::open(&path)?.set_modified(SystemTime::now())?;
+            return Ok(path);
+        }
+
+        let cover = client.get_cover(cover_id).await?;
+        fs::create_dir_all(&self.directory).await?;
+        let temporary_path = self.directory.join(format!(".{}.tmp", file_name));
+        fs::write(&temporary_path, cover).await?;
+        fs::rename(&temporary_path, &path).await?;
+        debug!("Cover {} cached in {:?}", cover_id, path);
+
+        self.evict().await?;
+        Ok(path)
+    }
+
+    async fn evict(&self) -> Result<()> {
+        let mut files = Vec::new();
+        let mut entries = fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                files.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+
+        let mut size = files.iter().map(|(_, len, _)| len).sum::<u64>();
+        files.sort_by_key(|(modified, _, _)| {
+            modified.duration_since(UNIX_EPOCH).unwrap_or_default()
+        });
+        for (_, len, path) in files {
+            if size <= self.max_size {
+                break;
+            }
+            debug!("Removing cover {:?} from the cache", path);
+            fs::remove_file(&path).await?;
+            size -= len;
+        }
+        Ok(())
+    }
+}
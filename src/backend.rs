@@ -0,0 +1,52 @@
+//! The presets of the player command for the audio backends, so that the full squeezelite command
+//! line doesn't have to be known.
+use anyhow::{anyhow, Error};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Pulse,
+    Alsa,
+    /// Through the ALSA plugin of PipeWire
+    PipeWire,
+    PortAudio,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pulse" => Ok(Backend::Pulse),
+            "alsa" => Ok(Backend::Alsa),
+            "pipewire" => Ok(Backend::PipeWire),
+            "portaudio" => Ok(Backend::PortAudio),
+            _ => Err(anyhow!(
+                "Expected pulse, alsa, pipewire or portaudio, got {}",
+                s
+            )),
+        }
+    }
+}
+
+impl Backend {
+    /// The player command and its arguments, with the placeholders
+    pub fn player_command(self) -> Vec<String> {
+        let (binary, output) = match self {
+            Backend::Pulse => ("squeezelite-pulse", None),
+            Backend::Alsa => ("squeezelite", None),
+            Backend::PipeWire => ("squeezelite", Some("pipewire")),
+            Backend::PortAudio => ("squeezelite-pa", None),
+        };
+        let mut command = vec![binary.to_string()];
+        if let Some(output) = output {
+            command.extend(["-o".to_string(), output.to_string()]);
+        }
+        command.extend(
+            ["-n", "{name}", "-s", "{server}"]
+                .iter()
+                .map(|arg| arg.to_string()),
+        );
+        command
+    }
+}
@@ -0,0 +1,56 @@
+//! The `watch` subcommand prints the changes of the state of the player on stdout, one line each,
+//! for the shell scripts to react to without a D-Bus client. A line is printed for the state found
+//! at startup, then on each change of the track, the playback status or the volume.
+use crate::{
+    mpris::playback_status,
+    state::{PlayerState, Track},
+};
+use anyhow::Result;
+use std::io::{self, Write};
+use tokio::sync::watch;
+
+pub async fn print(mut state: watch::Receiver<PlayerState>, json: bool) -> Result<()> {
+    // the first value is a placeholder until the poller fetched the state
+    state.changed().await?;
+    let mut last: Option<PlayerState> = None;
+    loop {
+        let new = state.borrow_and_update().clone();
+        if last.as_ref().is_none_or(|last| is_change(last, &new)) {
+            let line = if json {
+                serde_json::to_string(&new)?
+            } else {
+                format_line(&new)
+            };
+            let mut stdout = io::stdout().lock();
+            writeln!(stdout, "{}", line)?;
+            stdout.flush()?;
+            last = Some(new);
+        }
+        state.changed().await?;
+    }
+}
+
+/// The shuffle, the playlist name and the buffering are left out, as with the hooks
+fn is_change(last: &PlayerState, new: &PlayerState) -> bool {
+    last.track != new.track || last.mode != new.mode || last.volume != new.volume
+}
+
+/// The status, the volume, the artist, the album and the title, separated by tabs to be split with
+/// `cut` or `read`
+fn format_line(state: &PlayerState) -> String {
+    let track = state.track.as_ref();
+    let field = |get: fn(&Track) -> &Option<String>| {
+        track
+            .and_then(|track| get(track).as_deref())
+            .unwrap_or_default()
+            .replace(['\t', '\n'], " ")
+    };
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        playback_status(state.mode),
+        state.volume,
+        field(|track| &track.artist),
+        field(|track| &track.album),
+        field(|track| &track.title),
+    )
+}
@@ -0,0 +1,128 @@
+//! The `check` subcommand. It validates the environment without starting the player, and reports
+//! the result of each check.
+use crate::{
+    lms::{LmsClient, Timeouts},
+    Options,
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    env, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+use zbus::Connection;
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn add<T>(
+        &mut self,
+        name: &str,
+        result: Result<T>,
+        details: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => {
+                println!("[ok]      {}: {}", name, details(&value));
+                Some(value)
+            }
+            Err(error) => {
+                println!("[failed]  {}: {:#}", name, error);
+                self.failures += 1;
+                None
+            }
+        }
+    }
+
+    fn skip(&self, name: &str, reason: &str) {
+        println!("[skipped] {}: {}", name, reason);
+    }
+}
+
+pub async fn run(options: &Options) -> Result<()> {
+    let mut report = Report::default();
+
+    let player_command = options
+        .split_player_command()
+        .and_then(|(player_command, _)| find_executable(&player_command));
+    report.add("Player command", player_command, |path| {
+        format!("{}", path.display())
+    });
+
+    let server = options.server().await;
+    match report.add("Server", server, |(hostname, port, _)| {
+        format!("{}:{}", hostname, port)
+    }) {
+        Some((hostname, port, _)) => {
+            let credentials = options.credentials().await;
+            if let Some(credentials) =
+                report.add(
+                    "Credentials",
+                    credentials,
+                    |credentials| match credentials {
+                        Some(credentials) => format!("user {}", credentials.username),
+                        None => "none given".to_string(),
+                    },
+                )
+            {
+                let (client, mut recv) = LmsClient::new(
+                    hostname,
+                    port,
+                    credentials,
+                    options.server_flavor,
+                    Timeouts::default(),
+                    options.ip_family(),
+                    None,
+                );
+                // the actual cause of a failure is sent to the error channel
+                let version = client
+                    .get_version()
+                    .await
+                    .map_err(|error| recv.try_recv().unwrap_or(error));
+                report.add("Server reply", version, |version| {
+                    format!("version {}", version)
+                });
+            }
+        }
+        None => report.skip("Server reply", "the server is not available"),
+    }
+
+    let connection = Connection::session().await.map_err(|error| error.into());
+    report.add("Session bus", connection, |connection| {
+        connection
+            .unique_name()
+            .map_or_else(String::new, |name| format!("connected as {}", name))
+    });
+
+    if report.failures > 0 {
+        bail!("{} check(s) failed", report.failures);
+    }
+    Ok(())
+}
+
+/// Look for the command in the `PATH`, unless it is a path
+fn find_executable(command: &str) -> Result<PathBuf> {
+    let candidates: Vec<PathBuf> = if command.contains('/') {
+        vec![PathBuf::from(command)]
+    } else {
+        env::var_os("PATH")
+            .map(|path| {
+                env::split_paths(&path)
+                    .map(|dir| dir.join(command))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    candidates
+        .into_iter()
+        .find(|path| is_executable(path))
+        .ok_or_else(|| anyhow!("{} is not found or is not executable", command))
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
@@ -0,0 +1,89 @@
+//! The names of the library and of the saved playlists as shown in the menus. They are given in
+//! their composed Unicode form, as the tags of the files may hold decomposed accents, and sorted
+//! by the rules of the locale, `LC_COLLATE` or `LANG`, so that `Éric` comes along with `Eric` and
+//! `Åsa` after `Zoé` in Swedish, see `--server-order`.
+use icu_collator::{Collator, CollatorOptions};
+use icu_locid::Locale;
+use icu_normalizer::ComposingNormalizer;
+use log::debug;
+use std::env;
+
+/// The name in its composed form
+pub fn normalize(name: &str) -> String {
+    ComposingNormalizer::new_nfc().normalize(name)
+}
+
+/// Sort the items by their name with the rules of the locale
+pub fn sort_by_name<T>(items: &mut [T], name: impl Fn(&T) -> &str) {
+    let collator = collator(locale());
+    items.sort_by(|a, b| collator.compare(name(a), name(b)));
+}
+
+/// The collator of the locale, the root one when the locale is not set or not known
+fn collator(locale: Option<Locale>) -> Collator {
+    let locale = locale.unwrap_or_default();
+    Collator::try_new(&(&locale).into(), CollatorOptions::new())
+        .or_else(|error| {
+            debug!("No collation for the locale {}: {}", locale, error);
+            Collator::try_new(&Default::default(), CollatorOptions::new())
+        })
+        .expect("Unable to create the root collator")
+}
+
+/// The locale of the collation, from the first variable set among `LC_ALL`, `LC_COLLATE` and
+/// `LANG`. The POSIX names such as `sv_SE.UTF-8` are turned into `sv-SE`.
+fn locale() -> Option<Locale> {
+    let value = ["LC_ALL", "LC_COLLATE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())?;
+    let name = value.split(['.', '@']).next().unwrap_or_default();
+    if name == "C" || name == "POSIX" {
+        return None;
+    }
+    name.replace('_', "-")
+        .parse()
+        .inspect_err(|error| debug!("Unknown locale {}: {}", value, error))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(locale: &str, names: &[&'static str]) -> Vec<&'static str> {
+        let collator = collator(Some(locale.parse().unwrap()));
+        let mut names = names.to_vec();
+        names.sort_by(|a, b| collator.compare(a, b));
+        names
+    }
+
+    #[test]
+    fn accents_and_case_are_secondary() {
+        assert_eq!(
+            sorted("en", &["Zappa", "eric", "Éric", "Eric"]),
+            ["eric", "Eric", "Éric", "Zappa"]
+        );
+    }
+
+    #[test]
+    fn swedish_letters_come_after_z() {
+        assert_eq!(
+            sorted("sv-SE", &["Öberg", "Zorn", "Åsa", "Ärla"]),
+            ["Zorn", "Åsa", "Ärla", "Öberg"]
+        );
+    }
+
+    #[test]
+    fn spanish_enye_comes_after_n() {
+        assert_eq!(sorted("es", &["oso", "ñu", "nube"]), ["nube", "ñu", "oso"]);
+    }
+
+    #[test]
+    fn german_sharp_s_is_ss() {
+        assert_eq!(
+            sorted("de", &["Strasse", "Straße", "Strasze"]),
+            ["Strasse", "Straße", "Strasze"]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! The configuration file, in TOML. It holds the settings that don't fit well on the command line.
+//! By default it is read from `$XDG_CONFIG_HOME/mprisqueeze/config.toml` if it exists.
+use crate::{
+    failover::{ServerEntry, ServerStrategy},
+    lms::Timeouts,
+};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub hooks: Hooks,
+    pub timeouts: Timeouts,
+    /// Selected with `--profile`, indexed by their name
+    pub profiles: HashMap<String, Profile>,
+}
+
+/// Shell commands run on playback events. The metadata of the current track is passed in the
+/// environment variables `MPRISQUEEZE_*`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub track_change: Option<String>,
+    pub play: Option<String>,
+    pub pause: Option<String>,
+    pub stop: Option<String>,
+    pub volume_change: Option<String>,
+}
+
+/// A set of options, for example to use another server at the office. The options given on the
+/// command line take precedence.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub hostname: Option<String>,
+    pub port: Option<u16>,
+    /// The servers in order of preference, see `--server`
+    pub servers: Option<Vec<ServerEntry>>,
+    /// See `--server-strategy`
+    pub server_strategy: Option<ServerStrategy>,
+    pub player_name: Option<String>,
+    pub player_mac: Option<String>,
+    pub player_command: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Load the configuration from `path`, or from the default location if it is not given. The
+    /// file is only required to exist when its path is given explicitly.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        info!("Reading configuration from {:?}", path);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read the configuration file {:?}", path))?;
+        toml::from_str(&content)
+            .map_err(|error| anyhow!("Invalid configuration file {:?}: {}", path, error))
+    }
+}
+
+/// `$XDG_CONFIG_HOME/mprisqueeze/config.toml`, falling back to `~/.config/mprisqueeze/config.toml`
+fn default_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|config| config.join("mprisqueeze").join("config.toml"))
+}
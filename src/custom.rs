@@ -0,0 +1,634 @@
+//! The `org.mprisqueeze.Player1` D-Bus interface. It is served along with the MPRIS interfaces and
+//! exposes the features of LMS that MPRIS doesn't cover.
+use crate::{
+    collate,
+    lms::{all_pages, Enqueue, LibraryItem, LmsClient, Shuffle, StreamInfo, Tone},
+    mpris::{
+        bounded, cached_state, rename_player, sync_group_entries, to_fdo_error, to_mpris_time,
+        trace, MPRIS_PATH,
+    },
+    preferences::Preferences,
+    queue_time::QueueTime,
+    state::PlayerState,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc, watch};
+use zbus::{
+    fdo::{self, RequestNameFlags},
+    interface,
+    object_server::SignalEmitter,
+    proxy, Connection,
+};
+
+/// The number of items of the library fetched at once while browsing
+const BROWSE_PAGE: u64 = 500;
+
+/// An entry of the library when browsing: its path and its name
+type Entry = (String, String);
+
+/// The requests handled by the services of the main loop
+#[derive(Clone)]
+pub struct Requests {
+    /// Ask for the configuration to be reloaded
+    pub reload: mpsc::UnboundedSender<()>,
+    /// Arm, or disarm with `false`, the stop at the end of the current track
+    pub stop_after_current: mpsc::UnboundedSender<bool>,
+    /// Shut mprisqueeze down, on `Quit`
+    pub quit: mpsc::UnboundedSender<()>,
+    /// Continue the player process before playing, if it has been suspended while stopped. Only
+    /// set with `--suspend-when-stopped`.
+    pub resume: Option<mpsc::UnboundedSender<()>>,
+}
+
+pub struct CustomPlayer {
+    pub client: Arc<LmsClient>,
+    /// The current name of the player, shared with the services using it
+    pub player_name: watch::Sender<String>,
+    pub player_id: String,
+    /// The suffix of the bus name, if any
+    pub instance: Option<String>,
+    pub name_flags: Vec<RequestNameFlags>,
+    pub requests: Requests,
+    /// The default skips, in seconds
+    pub skip_forward: u32,
+    pub skip_backward: u32,
+    pub preferences: Arc<Preferences>,
+    /// Keep the order and the spelling of the names given by the server
+    pub server_order: bool,
+    /// The state as last polled, served while the circuit breaker is open
+    pub state: watch::Receiver<PlayerState>,
+    /// The stream details are not polled, the last ones read are served instead
+    pub last_stream_info: Mutex<StreamInfo>,
+}
+
+impl CustomPlayer {
+    async fn tone(&self, tone: Tone) -> fdo::Result<i64> {
+        self.client
+            .get_tone(self.player_id.clone(), tone)
+            .await
+            .map_err(to_fdo_error)
+    }
+
+    async fn set_tone(&self, tone: Tone, value: i64) -> zbus::Result<()> {
+        self.client
+            .set_tone(self.player_id.clone(), tone, value)
+            .await
+            .map_err(|error| to_fdo_error(error).into())
+    }
+
+    /// The number of tracks in the playlist and the position of the current one, starting at 1
+    async fn queue(&self) -> fdo::Result<(u64, u64)> {
+        let length = self
+            .client
+            .get_track_count(self.player_id.clone())
+            .await
+            .map_err(to_fdo_error)?;
+        if length == 0 {
+            return Ok((0, 0));
+        }
+        let index = self
+            .client
+            .get_index(self.player_id.clone())
+            .await
+            .map_err(to_fdo_error)?;
+        Ok((length, index + 1))
+    }
+
+    async fn queue_time(&self) -> fdo::Result<QueueTime> {
+        QueueTime::fetch(&self.client, &self.player_id)
+            .await
+            .map_err(to_fdo_error)
+    }
+
+    async fn stream_info(&self) -> fdo::Result<StreamInfo> {
+        if self.client.is_circuit_open() {
+            return Ok(self.last_stream_info.lock().unwrap().clone());
+        }
+        let info = self
+            .client
+            .get_stream_info(self.player_id.clone())
+            .await
+            .map_err(to_fdo_error)?;
+        *self.last_stream_info.lock().unwrap() = info.clone();
+        Ok(info)
+    }
+
+    /// The entries of the library below a path, see `Browse`
+    async fn entries(&self, path: &str) -> fdo::Result<Vec<Entry>> {
+        let invalid = || fdo::Error::InvalidArgs(format!("Invalid path {}", path));
+        if !path.starts_with('/') {
+            return Err(invalid());
+        }
+        let parent = path.trim_end_matches('/');
+        let ids = parent
+            .split('/')
+            .skip(1)
+            .map(|id| id.parse::<u64>().map_err(|_| invalid()))
+            .collect::<fdo::Result<Vec<_>>>()?;
+        let entries = match ids[..] {
+            [] => all_pages(|start| self.client.get_artists(start, BROWSE_PAGE))
+                .await
+                .map(|artists| {
+                    artists
+                        .into_iter()
+                        .map(|artist| (artist.id, artist.name))
+                        .collect::<Vec<_>>()
+                }),
+            [artist_id] => {
+                all_pages(|start| self.client.get_albums(Some(artist_id), start, BROWSE_PAGE))
+                    .await
+                    .map(|albums| {
+                        albums
+                            .into_iter()
+                            .map(|album| (album.id, album.name))
+                            .collect()
+                    })
+            }
+            [_, album_id] => {
+                all_pages(|start| self.client.get_tracks(album_id, start, BROWSE_PAGE))
+                    .await
+                    .map(|tracks| {
+                        tracks
+                            .into_iter()
+                            .map(|track| {
+                                let name = match track.track_number {
+                                    Some(number) => format!("{}. {}", number, track.title),
+                                    None => track.title,
+                                };
+                                (track.id, name)
+                            })
+                            .collect()
+                    })
+            }
+            _ => return Err(invalid()),
+        }
+        .map_err(to_fdo_error)?;
+        let mut entries = entries
+            .into_iter()
+            .map(|(id, name)| (format!("{}/{}", parent, id), name))
+            .collect::<Vec<_>>();
+        if !self.server_order {
+            for (_, name) in entries.iter_mut() {
+                *name = collate::normalize(name);
+            }
+            // the tracks stay in the order of the album
+            if ids.len() < 2 {
+                collate::sort_by_name(&mut entries, |(_, name)| name);
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn seek_relative(&self, seconds: i64) -> fdo::Result<()> {
+        self.client
+            .seek_relative(self.player_id.clone(), seconds)
+            .await
+            .map_err(to_fdo_error)
+    }
+}
+
+#[interface(name = "org.mprisqueeze.Player1")]
+impl CustomPlayer {
+    /// Send a command of the LMS CLI to the player, for example `["mixer", "volume", "?"]`, and
+    /// return its result as JSON
+    async fn raw_command(&self, params: Vec<String>) -> fdo::Result<String> {
+        bounded(&self.client, format!("RawCommand({:?})", params), async {
+            let result = self
+                .client
+                .raw(self.player_id.clone(), params.clone())
+                .await
+                .map_err(to_fdo_error)?;
+            serde_json::to_string(&result).map_err(|error| to_fdo_error(error.into()))
+        })
+        .await
+    }
+
+    /// Skip forward in the current track, by the default skip when `seconds` is 0
+    async fn skip_forward(&self, seconds: u32) -> fdo::Result<()> {
+        bounded(&self.client, format!("SkipForward({})", seconds), async {
+            let seconds = if seconds == 0 {
+                self.skip_forward
+            } else {
+                seconds
+            };
+            self.seek_relative(i64::from(seconds)).await
+        })
+        .await
+    }
+
+    /// Skip backward in the current track, by the default skip when `seconds` is 0
+    async fn skip_backward(&self, seconds: u32) -> fdo::Result<()> {
+        bounded(&self.client, format!("SkipBackward({})", seconds), async {
+            let seconds = if seconds == 0 {
+                self.skip_backward
+            } else {
+                seconds
+            };
+            self.seek_relative(-i64::from(seconds)).await
+        })
+        .await
+    }
+
+    /// Queue an item of the library by its id on LMS. `kind` is `album`, `artist` or `track`, and
+    /// `mode` is `load` to replace the playlist, `add` to append to it, or `insert` to play the
+    /// item after the current track.
+    async fn play_library_item(&self, kind: String, id: u64, mode: String) -> fdo::Result<()> {
+        bounded(
+            &self.client,
+            format!("PlayLibraryItem({}, {}, {})", kind, id, mode),
+            async {
+                let item = kind
+                    .parse::<LibraryItem>()
+                    .map_err(|error| fdo::Error::InvalidArgs(error.to_string()))?;
+                let enqueue = mode
+                    .parse::<Enqueue>()
+                    .map_err(|error| fdo::Error::InvalidArgs(error.to_string()))?;
+                self.client
+                    .play_library_item(self.player_id.clone(), item, id, enqueue)
+                    .await
+                    .map_err(to_fdo_error)
+            },
+        )
+        .await
+    }
+
+    /// Browse the library like a file system: `/` lists the artists, `/<artist id>` the albums of
+    /// an artist and `/<artist id>/<album id>` the tracks of an album. The entries are returned
+    /// with their path and their name, the last element of a path is the id to pass to
+    /// `PlayLibraryItem`.
+    async fn browse(&self, path: String) -> fdo::Result<Vec<Entry>> {
+        bounded(
+            &self.client,
+            format!("Browse({})", path),
+            self.entries(&path),
+        )
+        .await
+    }
+
+    /// Rename the player on LMS, the MPRIS identity and the bus name follow
+    async fn set_player_name(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        name: String,
+    ) -> fdo::Result<()> {
+        bounded(&self.client, format!("SetPlayerName({:?})", name), async {
+            self.client
+                .set_name(self.player_id.clone(), name.clone())
+                .await
+                .map_err(to_fdo_error)?;
+            let old_name = self.player_name.borrow().clone();
+            rename_player(
+                connection,
+                self.instance.as_deref(),
+                &self.name_flags,
+                &old_name,
+                &name,
+            )
+            .await
+            .map_err(to_fdo_error)?;
+            self.player_name.send_replace(name);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Stop the player at the end of the current track, or cancel it when `armed` is false
+    async fn stop_after_current(&self, armed: bool) -> fdo::Result<()> {
+        trace(format!("StopAfterCurrent({})", armed), async {
+            self.requests
+                .stop_after_current
+                .send(armed)
+                .map_err(|error| fdo::Error::Failed(error.to_string()))
+        })
+        .await
+    }
+
+    /// The bass of the players supporting it, such as the Squeezebox Boom
+    #[zbus(property)]
+    async fn bass(&self) -> fdo::Result<i64> {
+        bounded(&self.client, "Bass", self.tone(Tone::Bass)).await
+    }
+    #[zbus(property)]
+    async fn set_bass(&self, value: i64) -> zbus::Result<()> {
+        bounded(
+            &self.client,
+            format!("Bass = {}", value),
+            self.set_tone(Tone::Bass, value),
+        )
+        .await
+    }
+
+    /// The treble of the players supporting it
+    #[zbus(property)]
+    async fn treble(&self) -> fdo::Result<i64> {
+        bounded(&self.client, "Treble", self.tone(Tone::Treble)).await
+    }
+    #[zbus(property)]
+    async fn set_treble(&self, value: i64) -> zbus::Result<()> {
+        bounded(
+            &self.client,
+            format!("Treble = {}", value),
+            self.set_tone(Tone::Treble, value),
+        )
+        .await
+    }
+
+    /// The shuffle mode: `off`, `songs` or `albums`. The `Shuffle` property of MPRIS can't tell
+    /// the songs from the albums.
+    #[zbus(property)]
+    async fn shuffle_mode(&self) -> fdo::Result<String> {
+        bounded(&self.client, "ShuffleMode", async {
+            let shuffle = match cached_state(&self.client, &self.state) {
+                Some(state) => state.shuffle,
+                None => self
+                    .client
+                    .get_shuffle(self.player_id.clone())
+                    .await
+                    .map_err(to_fdo_error)?,
+            };
+            Ok(shuffle.name().to_string())
+        })
+        .await
+    }
+    #[zbus(property)]
+    async fn set_shuffle_mode(&self, value: String) -> zbus::Result<()> {
+        bounded(&self.client, format!("ShuffleMode = {}", value), async {
+            let shuffle = value
+                .parse::<Shuffle>()
+                .map_err(|error| fdo::Error::InvalidArgs(error.to_string()))?;
+            self.client
+                .set_shuffle(self.player_id.clone(), shuffle)
+                .await
+                .map_err(to_fdo_error)?;
+            self.preferences
+                .update(|values| values.shuffle = Some(shuffle));
+            Ok(())
+        })
+        .await
+    }
+
+    /// The ceiling of the LMS volume set through MPRIS, between 0 and 100. It is saved for the
+    /// next runs, as the properties below.
+    #[zbus(property)]
+    async fn max_volume(&self) -> u32 {
+        trace("MaxVolume", async { self.preferences.max_volume() as u32 }).await
+    }
+    #[zbus(property)]
+    async fn set_max_volume(&self, value: u32) -> zbus::Result<()> {
+        trace(format!("MaxVolume = {}", value), async {
+            if value > 100 {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "Expected a volume between 0 and 100, got {}",
+                    value
+                ))
+                .into());
+            }
+            self.preferences
+                .update(|values| values.max_volume = Some(u64::from(value)));
+            Ok(())
+        })
+        .await
+    }
+
+    /// Whether the events of the server are shown as desktop notifications, with `--subscribe`
+    #[zbus(property)]
+    async fn notify_server_events(&self) -> bool {
+        trace("NotifyServerEvents", async {
+            self.preferences.notify_server_events()
+        })
+        .await
+    }
+    #[zbus(property)]
+    async fn set_notify_server_events(&self, value: bool) {
+        trace(format!("NotifyServerEvents = {}", value), async {
+            self.preferences
+                .update(|values| values.notify_server_events = Some(value));
+        })
+        .await
+    }
+
+    /// Whether the tracks the server can't play are shown as desktop notifications, with
+    /// `--subscribe`
+    #[zbus(property)]
+    async fn notify_playback_errors(&self) -> bool {
+        trace("NotifyPlaybackErrors", async {
+            self.preferences.notify_playback_errors()
+        })
+        .await
+    }
+    #[zbus(property)]
+    async fn set_notify_playback_errors(&self, value: bool) {
+        trace(format!("NotifyPlaybackErrors = {}", value), async {
+            self.preferences
+                .update(|values| values.notify_playback_errors = Some(value));
+        })
+        .await
+    }
+
+    /// The technical details of the current track, so that one can check that the playback is
+    /// bit-perfect. The codec is named as on LMS, such as `flc` or `mp3`, and empty when unknown.
+    #[zbus(property)]
+    async fn codec(&self) -> fdo::Result<String> {
+        bounded(&self.client, "Codec", async {
+            Ok(self.stream_info().await?.codec)
+        })
+        .await
+    }
+    /// In Hz, 0 when unknown
+    #[zbus(property)]
+    async fn sample_rate(&self) -> fdo::Result<u32> {
+        bounded(&self.client, "SampleRate", async {
+            Ok(self.stream_info().await?.sample_rate.unwrap_or_default())
+        })
+        .await
+    }
+    /// In bits, 0 when unknown
+    #[zbus(property)]
+    async fn bit_depth(&self) -> fdo::Result<u32> {
+        bounded(&self.client, "BitDepth", async {
+            Ok(self.stream_info().await?.bit_depth.unwrap_or_default())
+        })
+        .await
+    }
+    /// In kbps, 0 when unknown or variable
+    #[zbus(property)]
+    async fn bitrate(&self) -> fdo::Result<u32> {
+        bounded(&self.client, "Bitrate", async {
+            Ok(self.stream_info().await?.bitrate.unwrap_or_default())
+        })
+        .await
+    }
+
+    /// Set while the player fills its buffer before playing, for example when a stream starts
+    #[zbus(property)]
+    async fn buffering(&self) -> fdo::Result<bool> {
+        bounded(&self.client, "Buffering", async {
+            match cached_state(&self.client, &self.state) {
+                Some(state) => Ok(state.buffering),
+                None => self
+                    .client
+                    .get_buffering(self.player_id.clone())
+                    .await
+                    .map_err(to_fdo_error),
+            }
+        })
+        .await
+    }
+
+    /// The name of the saved playlist loaded on the player, empty when there is none
+    #[zbus(property)]
+    async fn playlist_name(&self) -> fdo::Result<String> {
+        bounded(&self.client, "PlaylistName", async {
+            let name = match cached_state(&self.client, &self.state) {
+                Some(state) => state.playlist_name,
+                None => self
+                    .client
+                    .get_playlist_name(self.player_id.clone())
+                    .await
+                    .map_err(to_fdo_error)?,
+            };
+            Ok(name.unwrap_or_default())
+        })
+        .await
+    }
+
+    /// The other players synced with the player, as their MAC address and their name. The name
+    /// is empty when LMS can't tell it, for example when it contains a comma.
+    #[zbus(property)]
+    async fn sync_group(&self) -> fdo::Result<Vec<(String, String)>> {
+        bounded(&self.client, "SyncGroup", async {
+            let players = match cached_state(&self.client, &self.state) {
+                Some(state) => state.sync_group,
+                None => self
+                    .client
+                    .get_sync_group(self.player_id.clone())
+                    .await
+                    .map_err(to_fdo_error)?,
+            };
+            Ok(sync_group_entries(players))
+        })
+        .await
+    }
+
+    /// The number of tracks in the playlist. The queue properties are computed when read and
+    /// their changes are not emitted.
+    #[zbus(property)]
+    async fn queue_length(&self) -> fdo::Result<u64> {
+        bounded(&self.client, "QueueLength", async {
+            self.queue().await.map(|(length, _)| length)
+        })
+        .await
+    }
+
+    /// The position of the current track in the playlist, starting at 1, 0 when it is empty
+    #[zbus(property)]
+    async fn queue_position(&self) -> fdo::Result<u64> {
+        bounded(&self.client, "QueuePosition", async {
+            self.queue().await.map(|(_, position)| position)
+        })
+        .await
+    }
+
+    /// The time left until the end of the playlist, in microseconds
+    #[zbus(property)]
+    async fn queue_remaining_time(&self) -> fdo::Result<i64> {
+        bounded(&self.client, "QueueRemainingTime", async {
+            Ok(to_mpris_time(self.queue_time().await?.remaining))
+        })
+        .await
+    }
+
+    /// The duration of the whole playlist, in microseconds
+    #[zbus(property)]
+    async fn queue_duration(&self) -> fdo::Result<i64> {
+        bounded(&self.client, "QueueDuration", async {
+            Ok(to_mpris_time(self.queue_time().await?.total))
+        })
+        .await
+    }
+
+    /// The time the playlist ends at when it plays without interruption, in seconds since the
+    /// Unix epoch, 0 when it is empty
+    #[zbus(property)]
+    async fn queue_end_time(&self) -> fdo::Result<u64> {
+        bounded(&self.client, "QueueEndTime", async {
+            let queue_time = self.queue_time().await?;
+            if queue_time.length == 0 {
+                return Ok(0);
+            }
+            Ok(queue_time
+                .end()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs())
+        })
+        .await
+    }
+
+    /// Set while the server doesn't answer, see `--circuit-breaker`. The properties of the MPRIS
+    /// player are the last ones known meanwhile.
+    #[zbus(property)]
+    async fn stale(&self) -> bool {
+        trace("Stale", async { self.client.is_circuit_open() }).await
+    }
+
+    /// Emitted when the server answers again after the circuit breaker opened, with the time it
+    /// didn't answer in microseconds
+    #[zbus(signal)]
+    async fn server_recovered(emitter: &SignalEmitter<'_>, outage: i64) -> zbus::Result<()>;
+
+    /// Emitted when the server can't play a track, for example a dead stream
+    #[zbus(signal)]
+    async fn playback_error(
+        emitter: &SignalEmitter<'_>,
+        url: &str,
+        reason: &str,
+    ) -> zbus::Result<()>;
+
+    /// Reload the configuration file and the credentials, as on SIGHUP
+    async fn reload(&self) -> fdo::Result<()> {
+        trace("Reload()", async {
+            self.requests
+                .reload
+                .send(())
+                .map_err(|error| fdo::Error::Failed(error.to_string()))
+        })
+        .await
+    }
+}
+
+/// Emit the `PlaybackError` signal
+pub async fn playback_error(connection: &Connection, url: &str, reason: &str) -> zbus::Result<()> {
+    let emitter = SignalEmitter::new(connection, MPRIS_PATH)?;
+    CustomPlayer::playback_error(&emitter, url, reason).await
+}
+
+/// Emit the change of the `Stale` property
+pub async fn stale_changed(connection: &Connection) -> zbus::Result<()> {
+    let custom = connection
+        .object_server()
+        .interface::<_, CustomPlayer>(MPRIS_PATH)
+        .await?;
+    let emitter = custom.signal_emitter();
+    let result = custom.get().await.stale_changed(emitter).await;
+    result
+}
+
+/// Emit the `ServerRecovered` signal, along with the change of the `Stale` property
+pub async fn server_recovered(connection: &Connection, outage: Duration) -> zbus::Result<()> {
+    stale_changed(connection).await?;
+    let emitter = SignalEmitter::new(connection, MPRIS_PATH)?;
+    CustomPlayer::server_recovered(&emitter, to_mpris_time(outage)).await
+}
+
+/// The client side of the interface, used by the subcommands to reach a running instance
+#[proxy(
+    interface = "org.mprisqueeze.Player1",
+    default_path = "/org/mpris/MediaPlayer2"
+)]
+pub trait Custom {
+    fn stop_after_current(&self, armed: bool) -> zbus::Result<()>;
+}
@@ -0,0 +1,115 @@
+//! The `org.mprisqueeze.Debug1` D-Bus interface. It reports the state of the bridge itself, to be
+//! attached to the bug reports.
+use crate::{
+    lms::LmsClient,
+    mpris::{bounded, to_fdo_error, to_mpris_time, trace},
+    state::{polls_actively, Activity, PlayerState},
+};
+use std::{
+    sync::{atomic::Ordering, Arc, OnceLock},
+    time::Instant,
+};
+use tokio::sync::watch;
+use zbus::{fdo, interface};
+
+/// When mprisqueeze started, set with [start_clock]
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Start counting the uptime
+pub fn start_clock() {
+    STARTED.get_or_init(Instant::now);
+}
+
+pub struct Diagnostics {
+    pub client: Arc<LmsClient>,
+    /// Read without being touched, the diagnostics don't make the poller active
+    pub activity: Arc<Activity>,
+    pub state: watch::Receiver<PlayerState>,
+}
+
+#[interface(name = "org.mprisqueeze.Debug1")]
+impl Diagnostics {
+    /// Send a request to LMS and return the time it took to reply, in microseconds. A failure is
+    /// returned to the caller only.
+    async fn ping(&self) -> fdo::Result<i64> {
+        bounded(&self.client, "Ping()", async {
+            let start = Instant::now();
+            self.client
+                .raw(String::new(), vec!["version".to_string(), "?".to_string()])
+                .await
+                .map_err(to_fdo_error)?;
+            Ok(to_mpris_time(start.elapsed()))
+        })
+        .await
+    }
+
+    /// In microseconds
+    #[zbus(property)]
+    async fn uptime(&self) -> i64 {
+        trace("Uptime", async {
+            STARTED
+                .get()
+                .map_or(0, |started| to_mpris_time(started.elapsed()))
+        })
+        .await
+    }
+
+    /// The last error received from LMS, empty if there was none
+    #[zbus(property)]
+    async fn last_error(&self) -> String {
+        trace("LastError", async {
+            self.client
+                .counters()
+                .last_error
+                .lock()
+                .unwrap()
+                .clone()
+                .unwrap_or_default()
+        })
+        .await
+    }
+
+    /// The URL of the LMS web server
+    #[zbus(property)]
+    async fn endpoint(&self) -> String {
+        trace("Endpoint", async { self.client.base_url() }).await
+    }
+
+    #[zbus(property)]
+    async fn requests(&self) -> u64 {
+        trace("Requests", async {
+            self.client.counters().requests.load(Ordering::Relaxed)
+        })
+        .await
+    }
+
+    #[zbus(property)]
+    async fn errors(&self) -> u64 {
+        trace("Errors", async {
+            self.client.counters().errors.load(Ordering::Relaxed)
+        })
+        .await
+    }
+
+    /// Set when a D-Bus call gave up waiting for LMS, until the server answers again
+    #[zbus(property)]
+    async fn degraded(&self) -> bool {
+        trace("Degraded", async {
+            self.client.counters().degraded.load(Ordering::Relaxed)
+        })
+        .await
+    }
+
+    /// `active` while playing or while a client is active, `idle` otherwise
+    #[zbus(property)]
+    async fn poll_mode(&self) -> String {
+        trace("PollMode", async {
+            if polls_actively(&self.state.borrow(), &self.activity) {
+                "active".to_string()
+            } else {
+                "idle".to_string()
+            }
+        })
+        .await
+    }
+}
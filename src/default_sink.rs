@@ -0,0 +1,54 @@
+//! Watch the default sink of PulseAudio, or of PipeWire through its PulseAudio server. The player
+//! keeps playing to the sink it has been started with, so it has to be restarted when the default
+//! sink changes, for example when headphones are plugged in.
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use std::process::Stdio;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::mpsc,
+};
+
+/// Send a request on the channel each time the default sink changes
+pub async fn watch(changes: mpsc::UnboundedSender<()>) -> Result<()> {
+    let mut subscriber = Command::new("pactl")
+        .arg("subscribe")
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Unable to run pactl subscribe")?;
+    let mut lines =
+        BufReader::new(subscriber.stdout.take().context("No output for pactl")?).lines();
+
+    let mut default_sink = get_default_sink().await?;
+    info!("Watching the default sink, currently {}", default_sink);
+    // the default sink is a property of the server
+    while let Some(line) = lines.next_line().await? {
+        if !line.contains("'change' on server") {
+            continue;
+        }
+        let sink = get_default_sink().await?;
+        if sink != default_sink {
+            info!("The default sink changed from {} to {}", default_sink, sink);
+            default_sink = sink;
+            changes.send(())?;
+        }
+    }
+    bail!("pactl subscribe exited")
+}
+
+async fn get_default_sink() -> Result<String> {
+    let output = Command::new("pactl")
+        .arg("info")
+        .output()
+        .await
+        .context("Unable to run pactl info")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    debug!("pactl info: {}", stdout);
+    stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Default Sink: "))
+        .map(|sink| sink.trim().to_string())
+        .context("No default sink in the output of pactl info")
+}
@@ -0,0 +1,423 @@
+//! The demo mode, enabled with `--demo`. A fake LMS runs in the process with a small library and a
+//! single player, so that the MPRIS interfaces can be tried without a server nor a network. There
+//! is no audio, the player only pretends to play.
+use crate::http::{self, Request, Response};
+use anyhow::Result;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::net::TcpListener;
+
+/// The MAC address of the fake player
+pub const PLAYER_ID: &str = "00:00:00:00:00:01";
+
+/// The title, the artist, the album and the duration in seconds of the tracks of the library
+const LIBRARY: [(&str, &str, &str, f64); 5] = [
+    ("Morning Coffee", "The Placeholders", "Sample Rates", 184.0),
+    ("Buffer Underrun", "The Placeholders", "Sample Rates", 221.0),
+    ("Slim Protocol", "Null Device", "Loopback", 163.0),
+    ("Port 9000", "Null Device", "Loopback", 245.0),
+    ("Squeeze Me", "Jitter", "Dithering", 197.0),
+];
+
+/// The names of the saved playlists, each of them loads the whole library
+const PLAYLISTS: [&str; 2] = ["Demo Favourites", "Demo Classics"];
+
+/// Start the fake LMS on a free port of the loopback interface, and return the port
+pub async fn start(player_name: String) -> Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let port = listener.local_addr()?.port();
+    info!("Demo server listening on port {}", port);
+    let player = Arc::new(Mutex::new(Player::new(player_name)));
+    tokio::spawn(http::serve_requests(listener, move |request| {
+        handle(&mut player.lock().unwrap(), request)
+    }));
+    Ok(port)
+}
+
+#[derive(Deserialize)]
+struct JsonRequest {
+    params: (String, Vec<String>),
+}
+
+fn handle(player: &mut Player, request: Request) -> Response {
+    if request.method != "POST" || request.path != "/jsonrpc.js" {
+        return Response::not_found();
+    }
+    let (player_id, params) = match serde_json::from_slice::<JsonRequest>(&request.body) {
+        Ok(JsonRequest { params }) => params,
+        Err(error) => return Response::internal_error(error.to_string()),
+    };
+    debug!("Demo request: {:?}", params);
+    let params: Vec<&str> = params.iter().map(String::as_str).collect();
+    let result = player.handle(&params);
+    let body = json!({
+        "method": "slim.request",
+        "params": [player_id, params],
+        "result": result,
+    });
+    Response::ok("application/json", body.to_string())
+}
+
+struct Player {
+    name: String,
+    mode: &'static str,
+    power: bool,
+    index: usize,
+    /// The elapsed time in the current track when it was last set
+    elapsed: f64,
+    since: Instant,
+    volume: i64,
+    bass: i64,
+    treble: i64,
+    shuffle: u8,
+    repeat: u8,
+    playlist_name: Option<&'static str>,
+}
+
+impl Player {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            mode: "stop",
+            power: true,
+            index: 0,
+            elapsed: 0.0,
+            since: Instant::now(),
+            volume: 50,
+            bass: 0,
+            treble: 0,
+            shuffle: 0,
+            repeat: 0,
+            playlist_name: None,
+        }
+    }
+
+    fn duration(&self) -> f64 {
+        LIBRARY[self.index].3
+    }
+
+    fn time(&self) -> f64 {
+        match self.mode {
+            "play" => self.elapsed + self.since.elapsed().as_secs_f64(),
+            _ => self.elapsed,
+        }
+    }
+
+    fn set_time(&mut self, time: f64) {
+        self.elapsed = time.clamp(0.0, self.duration());
+        self.since = Instant::now();
+    }
+
+    fn set_mode(&mut self, mode: &'static str) {
+        let time = self.time();
+        self.mode = mode;
+        self.set_time(time);
+    }
+
+    fn set_index(&mut self, index: usize) {
+        self.index = index % LIBRARY.len();
+        self.set_time(0.0);
+    }
+
+    /// Move to the next tracks once the current one is over, stopping at the end of the playlist
+    /// unless it repeats
+    fn advance(&mut self) {
+        while self.mode == "play" && self.time() >= self.duration() {
+            let over = self.time() - self.duration();
+            if self.index + 1 == LIBRARY.len() && self.repeat == 0 {
+                self.set_index(0);
+                self.mode = "stop";
+                return;
+            }
+            self.set_index(self.index + 1);
+            self.set_time(over);
+        }
+    }
+
+    fn track(&self, index: usize) -> Value {
+        let (title, artist, album, duration) = LIBRARY[index];
+        let number = LIBRARY[..index]
+            .iter()
+            .filter(|track| track.2 == album)
+            .count();
+        json!({
+            "playlist index": index,
+            "id": index + 1,
+            "title": title,
+            "artist": artist,
+            "album": album,
+            "genre": "Electronic",
+            "tracknum": number + 1,
+            "duration": duration,
+            "type": "flc",
+            "bitrate": "1411kbps",
+            "samplerate": "44100",
+            "samplesize": "16",
+        })
+    }
+
+    fn handle(&mut self, params: &[&str]) -> Value {
+        self.advance();
+        let (title, artist, album, duration) = LIBRARY[self.index];
+        match params {
+            ["version", "?"] => json!({ "_version": "9.0.0" }),
+            ["serverstatus", ..] => json!({
+                "version": "9.0.0",
+                "uuid": "00000000-0000-0000-0000-000000000000",
+            }),
+            ["pref", "libraryname", "?"] => json!({ "_p2": "Demo" }),
+            // there is no CLI port to send the notifications on
+            ["can", command, "?"] => json!({ "_can": u8::from(*command != "listen") }),
+            ["player", "count", "?"] => json!({ "_count": 1 }),
+            ["players", ..] => json!({
+                "count": 1,
+                "players_loop": [{ "name": self.name, "playerid": PLAYER_ID, "connected": 1 }],
+            }),
+            // the demo player is alone, it is never synced
+            ["syncgroups", "?"] => json!({}),
+            ["connected", "?"] => json!({ "_connected": 1 }),
+            ["power", "?"] => json!({ "_power": u8::from(self.power) }),
+            ["mode", "?"] => json!({ "_mode": self.mode }),
+            ["time", "?"] => json!({ "_time": self.time() }),
+            ["duration", "?"] => json!({ "_duration": duration }),
+            ["title", "?"] => json!({ "_title": title }),
+            ["artist", "?"] => json!({ "_artist": artist }),
+            ["album", "?"] => json!({ "_album": album }),
+            ["mixer", "volume", "?"] => json!({ "_volume": self.volume }),
+            ["mixer", "bass", "?"] => json!({ "_bass": self.bass }),
+            ["mixer", "treble", "?"] => json!({ "_treble": self.treble }),
+            ["playlist", "tracks", "?"] => json!({ "_tracks": LIBRARY.len() }),
+            ["playlist", "index", "?"] => json!({ "_index": self.index }),
+            ["playlist", "shuffle", "?"] => json!({ "_shuffle": self.shuffle }),
+            ["playlist", "repeat", "?"] => json!({ "_repeat": self.repeat }),
+            ["playlist", "name", "?"] => match self.playlist_name {
+                Some(name) => json!({ "_name": name }),
+                None => json!({}),
+            },
+            ["status", "-", ..] => json!({
+                "mode": self.mode,
+                "playlist_loop": [self.track(self.index)],
+            }),
+            ["status", start, count, ..] => {
+                let start = start.parse().unwrap_or(0).min(LIBRARY.len());
+                let end = start
+                    .saturating_add(count.parse().unwrap_or(0))
+                    .min(LIBRARY.len());
+                let tracks: Vec<Value> = (start..end).map(|index| self.track(index)).collect();
+                if tracks.is_empty() {
+                    json!({ "playlist_tracks": LIBRARY.len() })
+                } else {
+                    json!({ "playlist_tracks": LIBRARY.len(), "playlist_loop": tracks })
+                }
+            }
+            ["playlists", start, count, ..] => {
+                let playlists = PLAYLISTS
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| json!({ "id": index + 1, "playlist": name }))
+                    .collect();
+                page(playlists, start, count, "playlists_loop")
+            }
+            ["artists", start, count, ..] => {
+                let artists = distinct(|(_, artist, _, _)| artist)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, artist)| json!({ "id": index + 1, "artist": artist }))
+                    .collect();
+                page(artists, start, count, "artists_loop")
+            }
+            ["albums", start, count, filters @ ..] => {
+                let artist = filter(filters, "artist_id")
+                    .and_then(|id| distinct(|(_, artist, _, _)| artist).get(id - 1).copied());
+                let albums = distinct(|(_, _, album, _)| album)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, album)| {
+                        artist.is_none_or(|artist| {
+                            LIBRARY
+                                .iter()
+                                .any(|track| track.1 == artist && track.2 == *album)
+                        })
+                    })
+                    .map(|(index, album)| json!({ "id": index + 1, "album": album }))
+                    .collect();
+                page(albums, start, count, "albums_loop")
+            }
+            ["titles", start, count, filters @ ..] => {
+                let album = filter(filters, "album_id")
+                    .and_then(|id| distinct(|(_, _, album, _)| album).get(id - 1).copied());
+                let tracks = LIBRARY
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, track)| album.is_none_or(|album| track.2 == album))
+                    .enumerate()
+                    .map(|(number, (index, (title, ..)))| {
+                        json!({ "id": index + 1, "title": title, "tracknum": number + 1 })
+                    })
+                    .collect();
+                page(tracks, start, count, "titles_loop")
+            }
+            // the playlist is the whole library, loading a track moves to it
+            ["playlistcontrol", "cmd:load", item] if item.starts_with("track_id:") => {
+                let index = item
+                    .trim_start_matches("track_id:")
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|id| (1..=LIBRARY.len()).contains(id));
+                if let Some(index) = index {
+                    self.playlist_name = None;
+                    self.set_index(index - 1);
+                    self.set_mode("play");
+                }
+                json!({})
+            }
+            ["playlistcontrol", "cmd:load", id] => {
+                let index = id
+                    .trim_start_matches("playlist_id:")
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|id| (1..=PLAYLISTS.len()).contains(id));
+                if let Some(index) = index {
+                    self.playlist_name = Some(PLAYLISTS[index - 1]);
+                    self.set_index(0);
+                    self.set_mode("play");
+                }
+                json!({})
+            }
+            ["play"] => {
+                self.set_mode("play");
+                json!({})
+            }
+            ["stop"] => {
+                self.set_mode("stop");
+                self.set_time(0.0);
+                json!({})
+            }
+            ["pause", "1"] => {
+                self.set_mode("pause");
+                json!({})
+            }
+            ["pause", "0"] => {
+                self.set_mode("play");
+                json!({})
+            }
+            ["pause"] => {
+                self.set_mode(if self.mode == "play" { "pause" } else { "play" });
+                json!({})
+            }
+            ["playlist", "index", "+1"] => {
+                self.set_index(self.index + 1);
+                json!({})
+            }
+            ["playlist", "index", "-1"] => {
+                self.set_index(self.index + LIBRARY.len() - 1);
+                json!({})
+            }
+            ["playlist", "shuffle", value] => {
+                self.shuffle = value.parse().unwrap_or(0);
+                json!({})
+            }
+            ["playlist", "repeat", value] => {
+                self.repeat = value.parse().unwrap_or(0);
+                json!({})
+            }
+            ["mixer", "volume", value] => {
+                // relative when signed, which unmutes the player
+                self.volume = match value.parse::<f64>() {
+                    Ok(change) if value.starts_with(['+', '-']) => {
+                        (self.volume.abs() + change as i64).clamp(0, 100)
+                    }
+                    Ok(volume) => volume as i64,
+                    Err(_) => self.volume,
+                };
+                json!({})
+            }
+            ["mixer", "muting", value] => {
+                // the volume is negative while muted
+                self.volume = if *value == "1" {
+                    -self.volume.abs()
+                } else {
+                    self.volume.abs()
+                };
+                json!({})
+            }
+            ["mixer", "bass", value] => {
+                self.bass = value.parse().unwrap_or(self.bass);
+                json!({})
+            }
+            ["mixer", "treble", value] => {
+                self.treble = value.parse().unwrap_or(self.treble);
+                json!({})
+            }
+            ["time", value] => {
+                // relative when signed
+                let time = match value.parse::<f64>() {
+                    Ok(seconds) if value.starts_with(['+', '-']) => self.time() + seconds,
+                    Ok(seconds) => seconds,
+                    Err(_) => self.time(),
+                };
+                self.set_time(time);
+                json!({})
+            }
+            ["power", value] => {
+                self.power = *value == "1";
+                if !self.power {
+                    self.set_mode("stop");
+                }
+                json!({})
+            }
+            ["name", name] => {
+                self.name = name.to_string();
+                json!({})
+            }
+            _ => {
+                debug!("Demo request not supported: {:?}", params);
+                json!({})
+            }
+        }
+    }
+}
+
+/// The distinct values of a column of the library in order, their id is their position starting
+/// at 1
+fn distinct(
+    column: fn(&(&'static str, &'static str, &'static str, f64)) -> &'static str,
+) -> Vec<&'static str> {
+    let mut values: Vec<&'static str> = Vec::new();
+    for track in LIBRARY.iter() {
+        let value = column(track);
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    values
+}
+
+/// The value of a filter such as `artist_id:1`
+fn filter(params: &[&str], name: &str) -> Option<usize> {
+    params.iter().find_map(|param| {
+        param
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .and_then(|value| value.parse().ok())
+            .filter(|value| *value > 0)
+    })
+}
+
+/// A page of a list, the loop is left out when it is empty as LMS does
+fn page(items: Vec<Value>, start: &str, count: &str, field: &str) -> Value {
+    let total = items.len();
+    let start = start.parse().unwrap_or(0).min(total);
+    let end = start.saturating_add(count.parse().unwrap_or(0)).min(total);
+    let mut result = json!({ "count": total });
+    if start < end {
+        result[field] = Value::from(items[start..end].to_vec());
+    }
+    result
+}
@@ -0,0 +1,86 @@
+//! The desktop entry of mprisqueeze, written by the `install-desktop-entry` subcommand. The desktop
+//! shells find the name and the icon of the player through the `DesktopEntry` property of MPRIS.
+use anyhow::{anyhow, Context, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// The base name of the file, as given in the `DesktopEntry` property
+pub const NAME: &str = "mprisqueeze";
+
+/// Write the desktop entry to the applications directory of the user, running the current
+/// executable. Return the path of the file.
+pub fn install(icon: &str) -> Result<PathBuf> {
+    let executable = env::current_exe().context("Unable to find the path of mprisqueeze")?;
+    let directory = data_home()
+        .ok_or_else(|| anyhow!("Unable to find the data directory"))?
+        .join("applications");
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("Unable to create {}", directory.display()))?;
+    let path = directory.join(format!("{}.desktop", NAME));
+    fs::write(&path, content(&executable, icon))
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Whether the desktop entry is found in the data directories, the one of the user or the ones of
+/// the system
+pub fn is_installed() -> bool {
+    let system = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    data_home()
+        .into_iter()
+        .chain(env::split_paths(&system))
+        .any(|directory| {
+            directory
+                .join("applications")
+                .join(format!("{}.desktop", NAME))
+                .is_file()
+        })
+}
+
+/// `$XDG_DATA_HOME`, falling back to `~/.local/share`
+fn data_home() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+}
+
+fn content(executable: &Path, icon: &str) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=mprisqueeze\n\
+         GenericName=Squeezebox Player\n\
+         Comment=Play music from Lyrion Music Server, controlled with MPRIS\n\
+         Exec={}\n\
+         Icon={}\n\
+         Terminal=false\n\
+         Categories=AudioVideo;Audio;Player;\n\
+         StartupWMClass={}\n",
+        quote(&executable.to_string_lossy()),
+        icon,
+        NAME
+    )
+}
+
+/// The executable is quoted when it holds reserved characters, as the specification requires
+fn quote(argument: &str) -> String {
+    const RESERVED: &str = " \t\n\"'\\><~|&;$*?#()`";
+    if !argument.contains(|c| RESERVED.contains(c)) {
+        return argument.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in argument.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    // the backslashes are escaped once more as the value is a string
+    quoted.replace('\\', "\\\\")
+}
@@ -1,101 +1,185 @@
+use crate::ip_family::IpFamily;
 use anyhow::Result;
-use log::{info, warn};
+use log::{debug, info, warn};
 use nom::{
     bytes::{self, complete::tag},
-    combinator::{flat_map, map, map_res},
+    combinator::{flat_map, map},
+    multi::many0,
     number,
-    sequence::{preceded, tuple},
+    sequence::{pair, preceded},
     IResult,
 };
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
 use tokio::{net::UdpSocket, time::timeout};
 
 #[derive(Debug)]
 pub struct Reply {
     pub hostname: String,
     pub port: u16,
-    #[allow(dead_code)]
-    pub uuid: String,
-    pub version: String,
+    pub uuid: Option<String>,
+    /// Left out by some older servers
+    pub version: Option<String>,
 }
 
+/// The port of the web server when the reply doesn't tell
+const DEFAULT_PORT: u16 = 9000;
+
+const DISCOVERY_PORT: u16 = 3483;
+
 // The LMS server can be discovered by sending a broadcast UDP packet to port 3483.
 // Example of answer from LMS
 // "ENAME\u{10}myhostnameJSON\u{4}9000UUID$e9b557b8-92e2-45cd-8a95-8730ffd604a5VERS\u{5}8.3.1"
 // '$' = 36 in the ASCII table
 // Each value starts with a tag, followed by the length of the value in one byte, then the value
-// itself in the next length bytes.
+// itself in the next length bytes. Older servers, such as LMS 7, may send the tags in another
+// order or leave some of them out.
 
-/// Discover the LMS server on the local network
-pub async fn discover(reply_timeout: Duration) -> Result<Reply> {
-    info!("Discovering LMS server on the local network");
+/// Discover the LMS servers on the local network. Once a server replied, the others are given the
+/// reply timeout to reply as well. Over IPv6, the message is sent to all the nodes of the link as
+/// there is no broadcast.
+pub async fn discover(reply_timeout: Duration, family: IpFamily) -> Result<Vec<Reply>> {
+    info!(
+        "Discovering LMS server on the local network over {}",
+        family
+    );
 
-    let sock = UdpSocket::bind("0.0.0.0:0").await?;
-    sock.set_broadcast(true)?;
+    let (sock, destination) = match family {
+        IpFamily::V4 => {
+            let sock = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+            sock.set_broadcast(true)?;
+            (
+                sock,
+                SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT)),
+            )
+        }
+        IpFamily::V6 => {
+            let sock = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?;
+            let all_nodes = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+            (sock, SocketAddr::from((all_nodes, DISCOVERY_PORT)))
+        }
+    };
 
     let mut buf = [0; 1024];
 
-    loop {
-        let response = timeout(reply_timeout, broasdcast_and_recv(&mut buf, &sock)).await;
+    let (len, sender) = loop {
+        let response = timeout(
+            reply_timeout,
+            broasdcast_and_recv(&mut buf, &sock, destination),
+        )
+        .await;
         match response {
-            Ok(Ok(())) => break,
-            Ok(Err(e)) => return Err(e.into()),
+            Ok(Ok(received)) => break received,
+            Ok(Err(e)) => return Err(e),
             Err(_) => warn!("Timeout waiting for LMS reply, retrying..."),
         }
+    };
+    let mut replies = vec![read_reply(&buf[..len], sender)?];
+
+    while let Ok(received) = timeout(reply_timeout, sock.recv_from(&mut buf)).await {
+        let (len, sender) = received?;
+        match read_reply(&buf[..len], sender) {
+            Ok(reply) if !replies.iter().any(|known| known.is_same_server(&reply)) => {
+                replies.push(reply)
+            }
+            Ok(_) => (),
+            Err(error) => warn!("Ignoring an invalid reply from {}: {}", sender, error),
+        }
     }
+    Ok(replies)
+}
 
-    parse_reply(&buf)
-        .map(|(_, reply)| {
-            info!(
-                "Found LMS server: {}:{} ({})",
-                reply.hostname, reply.port, reply.version
-            );
-            reply
-        })
-        .map_err(|error| error.to_owned().into())
+fn read_reply(buf: &[u8], sender: SocketAddr) -> Result<Reply> {
+    let (rest, tags) = parse_reply(buf).map_err(|error| error.to_owned())?;
+    if !rest.is_empty() {
+        debug!("Ignoring the end of the reply: {:?}", rest);
+    }
+    let reply = to_reply(tags, sender);
+    info!(
+        "Found LMS server: {}:{} ({})",
+        reply.hostname,
+        reply.port,
+        reply.version.as_deref().unwrap_or("unknown version")
+    );
+    Ok(reply)
+}
+
+impl Reply {
+    /// A server reachable over several addresses may reply more than once
+    fn is_same_server(&self, other: &Reply) -> bool {
+        match (&self.uuid, &other.uuid) {
+            (Some(uuid), Some(other_uuid)) => uuid == other_uuid,
+            _ => self.hostname == other.hostname && self.port == other.port,
+        }
+    }
 }
 
-async fn broasdcast_and_recv(buf: &mut [u8], sock: &UdpSocket) -> Result<()> {
+async fn broasdcast_and_recv(
+    buf: &mut [u8],
+    sock: &UdpSocket,
+    destination: SocketAddr,
+) -> Result<(usize, SocketAddr)> {
     let message = "eNAME\0JSON\0UUID\0VERS\0".as_bytes();
-    let _ = sock.send_to(&message, "255.255.255.255:3483").await?;
-    let _ = sock.recv(buf).await?;
-    Ok(())
+    let _ = sock.send_to(message, destination).await?;
+    Ok(sock.recv_from(buf).await?)
 }
 
-fn parse_tag<'a>(input: &'a [u8], start_tag: &str) -> IResult<&'a [u8], String> {
-    map_res(
-        preceded(
-            tag(start_tag),
+/// A tag of four letters followed by its value
+fn parse_tag(input: &[u8]) -> IResult<&[u8], (String, String)> {
+    pair(
+        map(bytes::complete::take(4usize), |tag: &[u8]| {
+            String::from_utf8_lossy(tag).into_owned()
+        }),
+        map(
             flat_map(number::complete::be_u8, bytes::complete::take),
+            |value: &[u8]| String::from_utf8_lossy(value).into_owned(),
         ),
-        |bytes: &[u8]| String::from_utf8(bytes.to_vec()),
     )(input)
 }
 
-fn parse_hostname(input: &[u8]) -> IResult<&[u8], String> {
-    parse_tag(input, "ENAME")
-}
-
-fn parse_port(input: &[u8]) -> IResult<&[u8], u16> {
-    map_res(|input| parse_tag(input, "JSON"), |s| s.parse::<u16>())(input)
-}
-
-fn parse_uuid(input: &[u8]) -> IResult<&[u8], String> {
-    parse_tag(input, "UUID")
-}
-
-fn parse_version(input: &[u8]) -> IResult<&[u8], String> {
-    parse_tag(input, "VERS")
+fn parse_reply(input: &[u8]) -> IResult<&[u8], Vec<(String, String)>> {
+    preceded(tag("E"), many0(parse_tag))(input)
 }
 
-fn parse_reply(input: &[u8]) -> IResult<&[u8], Reply> {
-    map(
-        tuple((parse_hostname, parse_port, parse_uuid, parse_version)),
-        |(hostname, port, uuid, version)| Reply {
-            hostname,
-            port,
-            uuid,
-            version,
-        },
-    )(input)
+/// Build the reply from the tags in any order, falling back on the address of the sender for the
+/// hostname and on the default port
+fn to_reply(tags: Vec<(String, String)>, sender: SocketAddr) -> Reply {
+    let mut tags: HashMap<String, String> = tags.into_iter().collect();
+    let hostname = tags.remove("NAME").unwrap_or_else(|| {
+        warn!(
+            "No NAME tag in the reply, using the address {}",
+            sender.ip()
+        );
+        sender.ip().to_string()
+    });
+    let port = match tags.remove("JSON") {
+        Some(port) => port.parse().unwrap_or_else(|_| {
+            warn!(
+                "Invalid JSON tag {} in the reply, assuming port {}",
+                port, DEFAULT_PORT
+            );
+            DEFAULT_PORT
+        }),
+        None => {
+            warn!("No JSON tag in the reply, assuming port {}", DEFAULT_PORT);
+            DEFAULT_PORT
+        }
+    };
+    let uuid = tags.remove("UUID");
+    let version = tags.remove("VERS");
+    if version.is_none() {
+        warn!("No VERS tag in the reply, the version is asked to the server");
+    }
+    if !tags.is_empty() {
+        debug!("Ignoring the tags {:?}", tags);
+    }
+    Reply {
+        hostname,
+        port,
+        uuid,
+        version,
+    }
 }
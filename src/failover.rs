@@ -0,0 +1,181 @@
+//! Several servers can be given, in order of preference. When the active one can't be reached for
+//! longer than a threshold, mprisqueeze moves on to the next one.
+use crate::{
+    ip_family::IpFamily,
+    lms::{flavor::ServerFlavor, Credentials, LmsClient, Timeouts},
+};
+use anyhow::{anyhow, Error, Result};
+use serde::Deserialize;
+use std::{convert::TryFrom, fmt, str::FromStr, time::Duration};
+use tokio::time::{sleep, timeout};
+
+/// How often an unreachable server is tried again
+const RETRY: Duration = Duration::from_secs(1);
+
+/// A server given with `--server` or in a profile
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ServerEntry {
+    /// The port defaults to the one given with `--port`
+    Address {
+        hostname: String,
+        port: Option<u16>,
+    },
+    Discover,
+}
+
+impl FromStr for ServerEntry {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "discover" {
+            return Ok(ServerEntry::Discover);
+        }
+        // an IPv6 address is enclosed in brackets when followed by a port
+        let (hostname, port) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (hostname, rest) = rest
+                    .split_once(']')
+                    .ok_or_else(|| anyhow!("Missing closing bracket in {}", s))?;
+                (hostname, rest.strip_prefix(':'))
+            }
+            None => match s.split_once(':') {
+                Some((hostname, port)) if !port.contains(':') => (hostname, Some(port)),
+                _ => (s, None),
+            },
+        };
+        if hostname.is_empty() {
+            return Err(anyhow!("Expected HOST[:PORT] or discover, got {}", s));
+        }
+        let port = port
+            .map(|port| port.parse().map_err(|_| anyhow!("Invalid port in {}", s)))
+            .transpose()?;
+        Ok(ServerEntry::Address {
+            hostname: hostname.to_string(),
+            port,
+        })
+    }
+}
+
+impl TryFrom<String> for ServerEntry {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ServerEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerEntry::Address {
+                hostname,
+                port: Some(port),
+            } => write!(f, "{}:{}", hostname, port),
+            ServerEntry::Address {
+                hostname,
+                port: None,
+            } => write!(f, "{}", hostname),
+            ServerEntry::Discover => write!(f, "discover"),
+        }
+    }
+}
+
+/// How the server given with `--hostname` and the ones found on the network are tried, see
+/// `--server-strategy`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ServerStrategy {
+    /// The given server, then discovery
+    PreferConfig,
+    /// Discovery, then the given server
+    PreferDiscovery,
+    /// Both at once, the first one answering is used
+    Race,
+}
+
+impl ServerStrategy {
+    /// The servers in order of preference, before the race if any
+    pub fn order(self, configured: ServerEntry) -> Vec<ServerEntry> {
+        match self {
+            ServerStrategy::PreferConfig | ServerStrategy::Race => {
+                vec![configured, ServerEntry::Discover]
+            }
+            ServerStrategy::PreferDiscovery => vec![ServerEntry::Discover, configured],
+        }
+    }
+}
+
+impl FromStr for ServerStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefer-config" => Ok(ServerStrategy::PreferConfig),
+            "prefer-discovery" => Ok(ServerStrategy::PreferDiscovery),
+            "race" => Ok(ServerStrategy::Race),
+            _ => Err(anyhow!(
+                "Expected prefer-config, prefer-discovery or race, got {}",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for ServerStrategy {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Whether the server answers, within the default timeouts
+pub async fn answers(
+    hostname: String,
+    port: u16,
+    credentials: Option<Credentials>,
+    flavor: ServerFlavor,
+    family: Option<IpFamily>,
+) -> bool {
+    let (client, _recv) = LmsClient::new(
+        hostname,
+        port,
+        credentials,
+        flavor,
+        Timeouts::default(),
+        family,
+        None,
+    );
+    client.get_version().await.is_ok()
+}
+
+/// Wait for the server to answer again, `false` if it doesn't within `delay`
+pub async fn wait_until_back(
+    hostname: String,
+    port: u16,
+    credentials: Option<Credentials>,
+    flavor: ServerFlavor,
+    family: Option<IpFamily>,
+    delay: Duration,
+) -> bool {
+    // the probe as a whole is bounded by the delay
+    let (client, mut recv) = LmsClient::new(
+        hostname,
+        port,
+        credentials,
+        flavor,
+        Timeouts::default(),
+        family,
+        None,
+    );
+    timeout(delay, async {
+        while client.get_version().await.is_err() {
+            // only the last error matters, make room for the next one
+            let _ = recv.try_recv();
+            sleep(RETRY).await;
+        }
+    })
+    .await
+    .is_ok()
+}
@@ -0,0 +1,107 @@
+//! Run the user defined hooks on playback events. The state of the player is given in environment
+//! variables, and as a whole in JSON on the standard input.
+use crate::{
+    config::Hooks,
+    lms::Mode,
+    mpris::playback_status,
+    state::{PlayerState, Track},
+};
+use anyhow::Result;
+use log::{debug, info, warn};
+use std::process::Stdio;
+use tokio::{io::AsyncWriteExt, process::Command, sync::watch};
+
+pub async fn run(
+    hooks: watch::Receiver<Hooks>,
+    player_name: watch::Receiver<String>,
+    mut state: watch::Receiver<PlayerState>,
+) -> Result<()> {
+    // the first state is the one found at startup, it doesn't trigger any hook
+    state.changed().await?;
+    let mut last = state.borrow_and_update().clone();
+    loop {
+        state.changed().await?;
+        let new = state.borrow_and_update().clone();
+        // the hooks may have been reloaded in the meantime
+        let hooks = hooks.borrow().clone();
+        // the player may have been renamed as well
+        let player_name = player_name.borrow().clone();
+
+        if new.track != last.track {
+            run_hook("track_change", &hooks.track_change, &player_name, &new);
+        }
+        if new.mode != last.mode {
+            let (event, hook) = match new.mode {
+                Mode::Play => ("play", &hooks.play),
+                Mode::Pause => ("pause", &hooks.pause),
+                Mode::Stop => ("stop", &hooks.stop),
+            };
+            run_hook(event, hook, &player_name, &new);
+        }
+        if new.volume != last.volume {
+            run_hook("volume_change", &hooks.volume_change, &player_name, &new);
+        }
+
+        last = new;
+    }
+}
+
+fn run_hook(event: &str, hook: &Option<String>, player_name: &str, state: &PlayerState) {
+    let Some(hook) = hook else {
+        return;
+    };
+    info!("Running {} hook: {}", event, hook);
+
+    let track = state.track.as_ref();
+    let field = |get: fn(&Track) -> &Option<String>| {
+        track
+            .and_then(|track| get(track).clone())
+            .unwrap_or_default()
+    };
+    // the same JSON as the one served with `--http-status`, on a line to be read from the shell
+    let payload = match serde_json::to_vec(state) {
+        Ok(mut payload) => {
+            payload.push(b'\n');
+            payload
+        }
+        Err(error) => {
+            warn!(
+                "Unable to serialize the state for the {} hook: {}",
+                event, error
+            );
+            return;
+        }
+    };
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("MPRISQUEEZE_EVENT", event)
+        .env("MPRISQUEEZE_PLAYER", player_name)
+        .env("MPRISQUEEZE_STATUS", playback_status(state.mode))
+        .env("MPRISQUEEZE_VOLUME", state.volume.to_string())
+        .env("MPRISQUEEZE_ARTIST", field(|track| &track.artist))
+        .env("MPRISQUEEZE_ALBUM", field(|track| &track.album))
+        .env("MPRISQUEEZE_TITLE", field(|track| &track.title))
+        .stdin(Stdio::piped())
+        .spawn();
+
+    match child {
+        Ok(mut child) => {
+            let event = event.to_string();
+            tokio::spawn(async move {
+                if let Some(mut stdin) = child.stdin.take() {
+                    // a hook may exit without reading it
+                    if let Err(error) = stdin.write_all(&payload).await {
+                        debug!("The {} hook didn't read the state: {}", event, error);
+                    }
+                }
+                match child.wait().await {
+                    Ok(status) if status.success() => debug!("The {} hook succeeded", event),
+                    Ok(status) => warn!("The {} hook failed: {}", event, status),
+                    Err(error) => warn!("Unable to wait for the {} hook: {}", event, error),
+                }
+            });
+        }
+        Err(error) => warn!("Unable to run the {} hook: {}", event, error),
+    }
+}
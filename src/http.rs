@@ -0,0 +1,182 @@
+//! A minimal HTTP server, good enough to answer `GET` requests from local tools such as
+//! Prometheus or `curl`, and the JSON requests of the client in the demo mode. Each connection
+//! serves a single request.
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+/// The largest body accepted in a request
+const MAX_BODY_SIZE: usize = 64 * 1024;
+/// The largest request line and headers accepted in a request
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+/// How long a client may take to send its request before the connection is closed
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    pub fn internal_error(message: String) -> Self {
+        Self {
+            status: "500 Internal Server Error",
+            content_type: "text/plain",
+            body: (message + "\n").into_bytes(),
+        }
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Self {
+            status: "405 Method Not Allowed",
+            content_type: "text/plain",
+            body: b"Method not allowed\n".to_vec(),
+        }
+    }
+
+    pub fn bad_request() -> Self {
+        Self {
+            status: "400 Bad Request",
+            content_type: "text/plain",
+            body: b"Bad request\n".to_vec(),
+        }
+    }
+
+    pub fn payload_too_large() -> Self {
+        Self {
+            status: "413 Payload Too Large",
+            content_type: "text/plain",
+            body: b"Payload too large\n".to_vec(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: "404 Not Found",
+            content_type: "text/plain",
+            body: b"Not found\n".to_vec(),
+        }
+    }
+}
+
+/// Serve the `GET` requests on `address`, the handler gets the path of the requested resource
+pub async fn serve<H>(address: SocketAddr, handler: H) -> Result<()>
+where
+    H: Fn(&str) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(address).await?;
+    info!("HTTP server listening on {}", address);
+    serve_requests(listener, move |request| match request.method.as_str() {
+        "GET" => handler(&request.path),
+        _ => Response::method_not_allowed(),
+    })
+    .await
+}
+
+/// Serve the requests of any method accepted on `listener`
+pub async fn serve_requests<H>(listener: TcpListener, handler: H) -> Result<()>
+where
+    H: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(error) = handle_connection(stream, handler.as_ref()).await {
+                warn!("HTTP error with {}: {}", peer, error);
+            }
+        });
+    }
+}
+
+async fn handle_connection<H>(mut stream: TcpStream, handler: &H) -> Result<()>
+where
+    H: Fn(Request) -> Response,
+{
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader.take((MAX_HEAD_SIZE + MAX_BODY_SIZE) as u64));
+
+    let response = match timeout(READ_TIMEOUT, read_request(&mut reader))
+        .await
+        .map_err(|_| anyhow!("timed out reading the request"))??
+    {
+        Ok(request) => handler(request),
+        Err(response) => response,
+    };
+
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(&response.body).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Read a request, or the response to send back when it can't be served. The query string is
+/// dropped from the path, and a body is only read for the methods that take one.
+async fn read_request(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<std::result::Result<Request, Response>> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    debug!("HTTP request: {}", request_line.trim_end());
+    // only the length of the body matters in the headers
+    let mut content_length = Some(0);
+    let mut header = String::new();
+    while reader.read_line(&mut header).await? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok();
+            }
+        }
+        header.clear();
+    }
+
+    let (method, path) = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        [method, path, _] => (method, path),
+        _ => return Ok(Err(Response::bad_request())),
+    };
+    let body = match (method, content_length) {
+        ("POST" | "PUT" | "PATCH", None) => return Ok(Err(Response::bad_request())),
+        ("POST" | "PUT" | "PATCH", Some(length)) if length > MAX_BODY_SIZE => {
+            return Ok(Err(Response::payload_too_large()))
+        }
+        ("POST" | "PUT" | "PATCH", Some(length)) => {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).await?;
+            body
+        }
+        _ => Vec::new(),
+    };
+
+    Ok(Ok(Request {
+        method: method.to_string(),
+        path: path.split('?').next().unwrap_or_default().to_string(),
+        body,
+    }))
+}
@@ -0,0 +1,37 @@
+//! Prevent the system from suspending while the player is playing. This is done by taking a
+//! systemd-logind inhibitor lock, which is held as long as its file descriptor is open.
+use crate::{lms::Mode, state::PlayerState};
+use anyhow::Result;
+use log::info;
+use tokio::sync::watch;
+use zbus::{proxy, zvariant::OwnedFd, Connection};
+
+#[proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+}
+
+pub async fn run(mut state: watch::Receiver<PlayerState>) -> Result<()> {
+    let connection = Connection::system().await?;
+    let manager = ManagerProxy::new(&connection).await?;
+    let mut lock: Option<OwnedFd> = None;
+    loop {
+        let playing = state.borrow_and_update().mode == Mode::Play;
+        if playing && lock.is_none() {
+            info!("Inhibiting sleep while playing");
+            lock = Some(
+                manager
+                    .inhibit("sleep", "mprisqueeze", "Playing music", "block")
+                    .await?,
+            );
+        } else if !playing && lock.is_some() {
+            info!("Releasing the sleep inhibitor");
+            lock = None;
+        }
+        state.changed().await?;
+    }
+}
@@ -0,0 +1,49 @@
+//! The IP family used to reach the server, selected with `--ipv4` or `--ipv6`. It helps when the
+//! hostname of the server resolves to an address of a family the network can't reach, such as an
+//! AAAA record without IPv6 connectivity.
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::{fmt, net::SocketAddr};
+use tokio::net::lookup_host;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn matches(self, address: &SocketAddr) -> bool {
+        match self {
+            IpFamily::V4 => address.is_ipv4(),
+            IpFamily::V6 => address.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "IPv4"),
+            IpFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// Resolve the hostnames of the HTTP requests to the addresses of a single family
+pub struct Resolver(pub IpFamily);
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.0;
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| family.matches(address))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("No {} address for {}", family, name.as_str()).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
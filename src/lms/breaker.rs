@@ -0,0 +1,46 @@
+//! The circuit breaker, enabled with `--circuit-breaker`. After a number of requests failed in a
+//! row because the server doesn't answer, the circuit opens: the requests fail right away instead
+//! of being sent, until a probe finds the server back.
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+#[derive(Debug)]
+pub struct Breaker {
+    /// The failures in a row which open the circuit
+    threshold: u32,
+    failures: AtomicU32,
+    open: AtomicBool,
+}
+
+impl Breaker {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            failures: AtomicU32::new(0),
+            open: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Count a request the server didn't answer, the circuit opens past the threshold
+    pub fn failure(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.threshold && !self.open.swap(true, Ordering::Relaxed) {
+            warn!(
+                "{} requests failed in a row, not sending any until the server answers again",
+                failures
+            );
+        }
+    }
+
+    /// The server answered, the circuit closes if it was open
+    pub fn success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        if self.open.swap(false, Ordering::Relaxed) {
+            info!("The server answers again, closing the circuit");
+        }
+    }
+}
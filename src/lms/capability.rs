@@ -0,0 +1,29 @@
+//! The optional features of the server. They rely on commands coming from plugins which may be
+//! disabled, and are looked up once at startup with the `can` command.
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// The notifications sent on the CLI port, used by `--subscribe`. They come from the CLI
+    /// plugin.
+    Notifications,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 1] = [Capability::Notifications];
+
+    /// The command looked up with `can`
+    pub fn command(self) -> &'static str {
+        match self {
+            Capability::Notifications => "listen",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Capability::Notifications => write!(f, "notifications"),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! The push transport: the notifications sent by the server on its CLI port, 9090 by default.
+//! Each notification is a line of space separated and percent-encoded parameters, starting with
+//! the id of the player it is about when there is one. The transport reconnects when the
+//! connection is lost, for example when the server restarts.
+use crate::lms::Credentials;
+use anyhow::{bail, Result};
+use log::{debug, info, warn};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::{broadcast, watch},
+    time::sleep,
+};
+
+/// The delay before reconnecting to the server
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Connected,
+    /// The connection has been lost, the server is likely restarting
+    Disconnected,
+    Notification {
+        /// The MAC address of the player, `None` for the notifications about the server
+        player_id: Option<String>,
+        params: Vec<String>,
+    },
+}
+
+/// Subscribe to the notifications of the server and forward them to the sender. The hostname is
+/// read again on each connection, as the server may move to another address.
+pub async fn subscribe(
+    hostname: watch::Receiver<String>,
+    port: u16,
+    credentials: Option<Credentials>,
+    sender: broadcast::Sender<Event>,
+) -> Result<()> {
+    loop {
+        let current = hostname.borrow().clone();
+        match listen(&current, port, credentials.as_ref(), &sender).await {
+            Ok(()) => warn!("The server closed the CLI connection"),
+            Err(error) => warn!("CLI connection error: {}", error),
+        }
+        let _ = sender.send(Event::Disconnected);
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen(
+    hostname: &str,
+    port: u16,
+    credentials: Option<&Credentials>,
+    sender: &broadcast::Sender<Event>,
+) -> Result<()> {
+    let mut stream = TcpStream::connect((hostname, port)).await?;
+    let (reader, mut writer) = stream.split();
+    let mut lines = BufReader::new(reader).lines();
+
+    if let Some(credentials) = credentials {
+        let login = format!(
+            "login {} {}\n",
+            encode(&credentials.username),
+            encode(&credentials.password.0)
+        );
+        writer.write_all(login.as_bytes()).await?;
+        // the server closes the connection when the credentials are wrong
+        if lines.next_line().await?.is_none() {
+            bail!("The server rejected the credentials");
+        }
+    }
+    writer.write_all(b"listen 1\n").await?;
+
+    info!("Listening to the notifications of {}:{}", hostname, port);
+    let _ = sender.send(Event::Connected);
+    while let Some(line) = lines.next_line().await? {
+        debug!("CLI notification: {}", line);
+        if let Some(event) = parse(&line) {
+            let _ = sender.send(event);
+        }
+    }
+    Ok(())
+}
+
+fn encode(param: &str) -> String {
+    utf8_percent_encode(param, NON_ALPHANUMERIC).to_string()
+}
+
+fn parse(line: &str) -> Option<Event> {
+    let mut params: Vec<String> = line
+        .split_whitespace()
+        .map(|param| percent_decode_str(param).decode_utf8_lossy().into_owned())
+        .collect();
+    // the echo of our own `listen` command
+    if params.first().is_some_and(|param| param == "listen") {
+        return None;
+    }
+    let player_id = match params.first() {
+        Some(param) if is_mac_address(param) => Some(params.remove(0)),
+        _ => None,
+    };
+    Some(Event::Notification { player_id, params })
+}
+
+fn is_mac_address(param: &str) -> bool {
+    param.len() == 17
+        && param
+            .split(':')
+            .all(|byte| byte.len() == 2 && byte.chars().all(|c| c.is_ascii_hexdigit()))
+}
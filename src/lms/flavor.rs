@@ -0,0 +1,29 @@
+//! The servers speaking the LMS protocol besides LMS itself, such as Music Assistant, send
+//! slightly different responses: missing fields, numbers as floats or booleans, and so on. The
+//! flavor of the server selects how strictly the responses are parsed.
+use anyhow::{anyhow, Error};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ServerFlavor {
+    /// The responses are parsed strictly
+    Lms,
+    /// The missing fields take a default value and the types are coerced
+    MusicAssistant,
+    /// Start strictly, and switch to the lenient parsing on the first response that needs it
+    #[default]
+    Auto,
+}
+
+impl FromStr for ServerFlavor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lms" => Ok(ServerFlavor::Lms),
+            "music-assistant" => Ok(ServerFlavor::MusicAssistant),
+            "auto" => Ok(ServerFlavor::Auto),
+            _ => Err(anyhow!("Expected lms, music-assistant or auto, got {}", s)),
+        }
+    }
+}
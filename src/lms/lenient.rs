@@ -0,0 +1,102 @@
+//! Serde adapters for the values the servers don't always send with the expected type: numbers as
+//! strings, missing fields, nulls and so on. The values are coerced when it is safe, the others
+//! are replaced by a default with a warning showing the raw value, rather than failing the whole
+//! response.
+use anyhow::{anyhow, bail, Result};
+use log::{debug, warn};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::Value;
+use std::result;
+
+/// Other servers send the numbers as strings, integers, floats or booleans, and leave out the
+/// fields they don't know about
+pub fn number(value: &Value) -> Result<f64> {
+    match value {
+        Value::Null => Ok(0.0),
+        Value::Bool(b) => Ok(f64::from(u8::from(*b))),
+        Value::Number(n) => n.as_f64().ok_or_else(|| anyhow!("{} is not a number", n)),
+        Value::String(s) if s.is_empty() => Ok(0.0),
+        Value::String(s) => s.trim().parse::<f64>().map_err(|e| e.into()),
+        _ => bail!("Wrong top level type for number: {:?}", value),
+    }
+}
+
+/// The ids are numbers, possibly sent as strings. An item without a valid id can't be used, so
+/// this one fails.
+pub fn id<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<u64, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    match number(&value) {
+        Ok(id) if id >= 0.0 && id.fract() == 0.0 => Ok(id as u64),
+        _ => Err(serde::de::Error::custom(format!("Invalid id {}", value))),
+    }
+}
+
+/// LMS sends the flags as 0 or 1, other servers may send booleans or strings
+pub fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<bool, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(flag) => flag,
+        value => number(&value).map(|n| n != 0.0).unwrap_or_else(|error| {
+            warn!("Unexpected flag {}, assuming false: {:#}", value, error);
+            false
+        }),
+    })
+}
+
+/// The names made of digits only may be sent as numbers
+pub fn string<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<String, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => s,
+        Value::Number(n) => n.to_string(),
+        value => {
+            warn!("Unexpected string {}, assuming an empty one", value);
+            String::new()
+        }
+    })
+}
+
+/// The number a string starts with, such as the bitrate in `320kbps CBR`. The values without one,
+/// such as `VBR`, are unknown.
+pub fn leading_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> result::Result<Option<u32>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(n) => n.as_f64().map(|n| n as u32),
+        Value::String(s) => s
+            .trim()
+            .split(|c: char| !c.is_ascii_digit())
+            .next()
+            .and_then(|digits| digits.parse().ok()),
+        _ => None,
+    })
+}
+
+/// For the fields that are only informative, such as the request echoed in the responses. As they
+/// would be repeated in every response, the unexpected values are only logged in debug.
+pub fn or_default<'de, D, T>(deserializer: D) -> result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = Value::deserialize(deserializer)?;
+    serde_json::from_value(value.clone()).or_else(|error| {
+        debug!("Unexpected value {}, using the default: {}", value, error);
+        Ok(T::default())
+    })
+}
+
+/// The items of a loop such as `players_loop`, skipping the ones that can't be parsed. A missing
+/// loop is empty.
+pub fn items<T: DeserializeOwned>(value: Value) -> Result<Vec<T>> {
+    match value {
+        Value::Null => Ok(Vec::new()),
+        Value::Array(items) => Ok(items
+            .into_iter()
+            .filter_map(|item| {
+                serde_json::from_value(item.clone())
+                    .map_err(|error| warn!("Skipping the unexpected item {}: {}", item, error))
+                    .ok()
+            })
+            .collect()),
+        _ => bail!("Wrong top level type for loop: {:?}", value),
+    }
+}
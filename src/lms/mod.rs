@@ -1,70 +1,642 @@
 //! The functions to talk to the LMS server. LMS accepts and returns JSON data. The requests are
 //! created using the functions in the [request] module.
-use crate::lms::request::LmsRequest;
+use crate::{
+    ip_family::{IpFamily, Resolver},
+    lms::{
+        breaker::Breaker,
+        capability::Capability,
+        flavor::ServerFlavor,
+        queue::{Command, CommandQueue},
+        request::LmsRequest,
+    },
+};
 use anyhow::bail;
 use anyhow::{anyhow, Ok, Result};
-use log::debug;
-use reqwest::Client;
-use serde::Deserialize;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use log::{debug, info, warn};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    result,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
 use thiserror::Error;
 use tokio::sync::mpsc;
 
+mod breaker;
+pub mod capability;
+pub mod events;
+pub mod flavor;
+mod lenient;
+mod queue;
 mod request;
+pub mod version;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Mode {
+    #[default]
     Stop,
     Play,
     Pause,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Shuffle {
+    #[default]
     Off,
     Songs,
     Albums,
 }
 
-#[derive(Debug)]
-pub struct LmsClient {
+impl Shuffle {
+    /// The value of the mode in the requests
+    fn param(self) -> u8 {
+        match self {
+            Shuffle::Off => 0,
+            Shuffle::Songs => 1,
+            Shuffle::Albums => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Shuffle::Off => "off",
+            Shuffle::Songs => "songs",
+            Shuffle::Albums => "albums",
+        }
+    }
+}
+
+impl FromStr for Shuffle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "off" => result::Result::Ok(Shuffle::Off),
+            "songs" => result::Result::Ok(Shuffle::Songs),
+            "albums" => result::Result::Ok(Shuffle::Albums),
+            _ => Err(anyhow!("Expected off, songs or albums, got {}", s)),
+        }
+    }
+}
+
+/// What the player plays again at the end of the track or of the playlist
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeat {
+    #[default]
+    Off,
+    Track,
+    Playlist,
+}
+
+impl Repeat {
+    /// The value of the mode in the requests
+    fn param(self) -> u8 {
+        match self {
+            Repeat::Off => 0,
+            Repeat::Track => 1,
+            Repeat::Playlist => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Repeat::Off => "off",
+            Repeat::Track => "track",
+            Repeat::Playlist => "playlist",
+        }
+    }
+}
+
+/// The items of the library that can be queued by id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LibraryItem {
+    Album,
+    Artist,
+    Track,
+}
+
+impl LibraryItem {
+    fn param(self) -> &'static str {
+        match self {
+            LibraryItem::Album => "album_id",
+            LibraryItem::Artist => "artist_id",
+            LibraryItem::Track => "track_id",
+        }
+    }
+}
+
+impl FromStr for LibraryItem {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "album" => result::Result::Ok(LibraryItem::Album),
+            "artist" => result::Result::Ok(LibraryItem::Artist),
+            "track" => result::Result::Ok(LibraryItem::Track),
+            _ => Err(anyhow!("Expected album, artist or track, got {}", s)),
+        }
+    }
+}
+
+/// Where the items are put in the playlist
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Enqueue {
+    /// Replace the playlist and play
+    Load,
+    /// Append to the playlist
+    Add,
+    /// Insert after the current track
+    Insert,
+}
+
+impl Enqueue {
+    fn param(self) -> &'static str {
+        match self {
+            Enqueue::Load => "cmd:load",
+            Enqueue::Add => "cmd:add",
+            Enqueue::Insert => "cmd:insert",
+        }
+    }
+
+    /// The verb of the `playlist` command for a URL
+    fn verb(self) -> &'static str {
+        match self {
+            Enqueue::Load => "play",
+            Enqueue::Add => "add",
+            Enqueue::Insert => "insert",
+        }
+    }
+}
+
+impl FromStr for Enqueue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "load" => result::Result::Ok(Enqueue::Load),
+            "add" => result::Result::Ok(Enqueue::Add),
+            "insert" => result::Result::Ok(Enqueue::Insert),
+            _ => Err(anyhow!("Expected load, add or insert, got {}", s)),
+        }
+    }
+}
+
+/// The tone controls of the hardware players, such as the Squeezebox Boom
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tone {
+    Bass,
+    Treble,
+}
+
+impl Tone {
+    fn param(self) -> &'static str {
+        match self {
+            Tone::Bass => "bass",
+            Tone::Treble => "treble",
+        }
+    }
+}
+
+/// A query being sent to LMS. Its result is shared between all the callers waiting for it.
+type InFlight = Shared<BoxFuture<'static, result::Result<LmsResponse, Arc<anyhow::Error>>>>;
+
+/// Where the requests are sent. It is cheap to clone.
+#[derive(Clone)]
+struct Endpoint {
     /// The HTTP client
     client: Client,
-    /// The URL to reach the LMS server
-    url: String,
+    /// The URL of the LMS web server, changed when the server moves to another address
+    base_url: Arc<RwLock<String>>,
+    /// Can be changed at runtime, for example once the user has supplied them
+    credentials: Arc<RwLock<Option<Credentials>>>,
+    counters: Arc<Counters>,
+    /// Can be changed at runtime when the configuration is reloaded
+    timeouts: Arc<RwLock<Timeouts>>,
+    /// Set with `--circuit-breaker`
+    breaker: Option<Arc<Breaker>>,
+}
+
+/// The timeouts of the requests in milliseconds, none by default. The control commands are
+/// expected to be quick, while the queries may take a while on large libraries.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    pub command: Option<u64>,
+    pub query: Option<u64>,
+}
+
+/// The credentials for the servers protected by a password
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: Password,
+}
+
+/// A password which is not shown in the logs
+#[derive(Clone)]
+pub struct Password(pub String);
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "********")
+    }
+}
+
+impl FromStr for Password {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        result::Result::Ok(Password(s.to_string()))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LmsError {
+    #[error("The server requires authentication, pass --username and --password")]
+    AuthenticationRequired,
+    #[error("The server rejected the credentials, check --username and --password")]
+    InvalidCredentials,
+    /// Unlike the failures of the queries, it doesn't stop the player
+    #[error("The server didn't answer the command within {0} ms")]
+    CommandTimeout(u64),
+    /// Returned without sending the request while the circuit breaker is open
+    #[error("The server doesn't answer, the requests are suspended until it is back")]
+    CircuitOpen,
+    /// Sent instead of the result for the malformed or unauthorized requests
+    #[error("The server returned an error: {0}")]
+    Server(String),
+}
+
+/// The error of a request, shared between all the callers waiting for it
+#[derive(Debug, Error)]
+#[error("{0:#}")]
+struct QueryError(Arc<anyhow::Error>);
+
+/// Whether the error comes from the server not answering, rather than from an invalid response
+pub fn is_unreachable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|error| error.is_connect() || error.is_timeout())
+            || cause
+                .downcast_ref::<QueryError>()
+                .is_some_and(|error| is_unreachable(&error.0))
+    })
+}
+
+fn is_circuit_open(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<LmsError>(),
+            Some(LmsError::CircuitOpen)
+        ) || cause
+            .downcast_ref::<QueryError>()
+            .is_some_and(|error| is_circuit_open(&error.0))
+    })
+}
+
+fn is_command_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<LmsError>(),
+            Some(LmsError::CommandTimeout(_))
+        ) || cause
+            .downcast_ref::<QueryError>()
+            .is_some_and(|error| is_command_timeout(&error.0))
+    })
+}
+
+/// Statistics about the requests sent to LMS
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub requests: AtomicU64,
+    pub errors: AtomicU64,
+    /// The message of the last error, with its causes
+    pub last_error: Mutex<Option<String>>,
+    /// Set when a D-Bus call gave up waiting for LMS, cleared by the next response
+    pub degraded: AtomicBool,
+}
+
+pub struct LmsClient {
+    endpoint: Endpoint,
     /// The channel to report errors
     sender: mpsc::Sender<anyhow::Error>,
+    /// The queries currently sent to LMS, indexed by their JSON serialization
+    in_flight: Arc<Mutex<HashMap<String, InFlight>>>,
+    /// The queues of control commands, one per player
+    queues: Mutex<HashMap<String, CommandQueue>>,
+    flavor: ServerFlavor,
+    /// Whether the responses are parsed leniently, see [flavor]
+    lenient: AtomicBool,
+    /// The optional features of the server, `None` until they are detected
+    capabilities: RwLock<Option<HashSet<Capability>>>,
 }
 
+/// Convert the field of a response, leniently or not
+type Converter<T> = fn(LmsResponse, &String, bool) -> Result<T>;
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Player {
+    #[serde(default, deserialize_with = "lenient::string")]
     pub name: String,
+    /// The MAC address of the player, which identifies it unlike its name
+    #[serde(rename = "playerid")]
+    pub id: String,
+    #[serde(default, deserialize_with = "lenient::flag")]
+    pub connected: bool,
+}
+
+/// Another player of the sync group of the player
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SyncedPlayer {
+    /// The MAC address of the player
+    pub id: String,
+    pub name: String,
+}
+
+/// The members of a sync group, as comma separated lists
+#[derive(Clone, Debug, Deserialize)]
+struct SyncGroup {
+    #[serde(rename = "sync_members", default, deserialize_with = "lenient::string")]
+    ids: String,
+    #[serde(
+        rename = "sync_member_names",
+        default,
+        deserialize_with = "lenient::string"
+    )]
+    names: String,
+}
+
+/// A playlist saved on the server
+#[derive(Clone, Debug, Deserialize)]
+pub struct SavedPlaylist {
+    #[serde(deserialize_with = "lenient::id")]
+    pub id: u64,
+    #[serde(rename = "playlist", default, deserialize_with = "lenient::string")]
+    pub name: String,
+}
+
+/// An artist of the library
+#[derive(Clone, Debug, Deserialize)]
+pub struct Artist {
+    #[serde(deserialize_with = "lenient::id")]
+    pub id: u64,
+    #[serde(rename = "artist", default, deserialize_with = "lenient::string")]
+    pub name: String,
+}
+
+/// An album of the library
+#[derive(Clone, Debug, Deserialize)]
+pub struct Album {
+    #[serde(deserialize_with = "lenient::id")]
+    pub id: u64,
+    #[serde(rename = "album", default, deserialize_with = "lenient::string")]
+    pub name: String,
+}
+
+/// A track of the library, unlike the ones of the playlist of a player
+#[derive(Clone, Debug, Deserialize)]
+pub struct LibraryTrack {
+    #[serde(deserialize_with = "lenient::id")]
+    pub id: u64,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub title: String,
+    /// The position on the album, unknown for some files
+    #[serde(
+        rename = "tracknum",
+        default,
+        deserialize_with = "lenient::leading_number"
+    )]
+    pub track_number: Option<u32>,
+}
+
+/// A track of the playlist of a player
+#[derive(Clone, Debug, Deserialize)]
+pub struct QueuedTrack {
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub title: String,
+    /// Empty when unknown, such as for most streams
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub artist: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub url: String,
+    /// In seconds, missing for the streams
+    #[serde(default, deserialize_with = "lenient::or_default")]
+    pub duration: Option<f64>,
+}
+
+/// The metadata of the current track fetched with the full profile only
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TrackDetails {
+    #[serde(default, deserialize_with = "lenient::or_default")]
+    pub genre: Option<String>,
+    #[serde(
+        rename = "tracknum",
+        default,
+        deserialize_with = "lenient::leading_number"
+    )]
+    pub track_number: Option<u32>,
+    /// In seconds, missing for the streams
+    #[serde(default, deserialize_with = "lenient::or_default")]
+    pub duration: Option<f64>,
+}
+
+/// The technical details of the current track, as far as LMS knows them
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StreamInfo {
+    /// The format as named by LMS, such as `flc`, `mp3` or `aac`
+    #[serde(rename = "type", default, deserialize_with = "lenient::string")]
+    pub codec: String,
+    /// In Hz
+    #[serde(
+        rename = "samplerate",
+        default,
+        deserialize_with = "lenient::leading_number"
+    )]
+    pub sample_rate: Option<u32>,
+    #[serde(
+        rename = "samplesize",
+        default,
+        deserialize_with = "lenient::leading_number"
+    )]
+    pub bit_depth: Option<u32>,
+    /// In kbps
+    #[serde(default, deserialize_with = "lenient::leading_number")]
+    pub bitrate: Option<u32>,
 }
 
 impl LmsClient {
-    pub fn new(hostname: String, port: u16) -> (Self, mpsc::Receiver<anyhow::Error>) {
-        let client = Client::new();
-        let url = format!("http://{}:{}/jsonrpc.js", hostname, port);
+    pub fn new(
+        hostname: String,
+        port: u16,
+        credentials: Option<Credentials>,
+        flavor: ServerFlavor,
+        timeouts: Timeouts,
+        family: Option<IpFamily>,
+        circuit_breaker: Option<u32>,
+    ) -> (Self, mpsc::Receiver<anyhow::Error>) {
+        let client = match family {
+            Some(family) => Client::builder()
+                .dns_resolver(Arc::new(Resolver(family)))
+                .build()
+                .expect("Unable to create the HTTP client"),
+            None => Client::new(),
+        };
         let (sender, receiver) = mpsc::channel::<anyhow::Error>(1);
 
         (
             Self {
-                client,
-                url,
+                endpoint: Endpoint {
+                    client,
+                    base_url: Arc::new(RwLock::new(to_base_url(&hostname, port))),
+                    credentials: Arc::new(RwLock::new(credentials)),
+                    counters: Arc::new(Counters::default()),
+                    timeouts: Arc::new(RwLock::new(timeouts)),
+                    breaker: circuit_breaker.map(|threshold| Arc::new(Breaker::new(threshold))),
+                },
                 sender,
+                in_flight: Arc::new(Mutex::new(HashMap::new())),
+                queues: Mutex::new(HashMap::new()),
+                flavor,
+                lenient: AtomicBool::new(flavor == ServerFlavor::MusicAssistant),
+                capabilities: RwLock::new(None),
             },
             receiver,
         )
     }
 
-    #[allow(dead_code)]
+    /// Change the address of the server for the next requests
+    pub fn set_server(&self, hostname: &str, port: u16) {
+        *self.endpoint.base_url.write().unwrap() = to_base_url(hostname, port);
+    }
+
+    /// Change the credentials used for the next requests
+    pub fn set_credentials(&self, credentials: Option<Credentials>) {
+        *self.endpoint.credentials.write().unwrap() = credentials;
+    }
+
+    /// Change the timeouts of the next requests
+    pub fn set_timeouts(&self, timeouts: Timeouts) {
+        *self.endpoint.timeouts.write().unwrap() = timeouts;
+    }
+
+    /// The unique id of the server, `None` when it doesn't tell
+    pub async fn get_uuid(&self) -> Result<Option<String>> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::server_status();
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_string_or_not_there)
+            }
+            .await,
+            anyhow!("Error get_uuid"),
+        )
+        .await
+    }
+
+    /// The name given to the server in its settings, `None` when it has not been set
+    pub async fn get_library_name(&self) -> Result<Option<String>> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::library_name();
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_string_or_not_there)
+                    .map(|name| name.filter(|name| !name.is_empty()))
+            }
+            .await,
+            anyhow!("Error get_library_name"),
+        )
+        .await
+    }
+
+    /// Look up the optional features of the server. When they can't be, all of them are assumed
+    /// to be available, as before their detection.
+    pub async fn detect_capabilities(&self) {
+        let mut capabilities = HashSet::new();
+        for capability in Capability::ALL {
+            let (request, field) = LmsRequest::can(capability.command());
+            // a server without the command doesn't switch the parsing to lenient
+            let available = match self.query(request).await {
+                result::Result::Ok(response) => as_bool(response, &field, self.is_lenient()),
+                Err(error) => Err(error),
+            };
+            match available {
+                result::Result::Ok(true) => {
+                    capabilities.insert(capability);
+                }
+                result::Result::Ok(false) => info!(
+                    "The server doesn't support the {}, the {} command is missing",
+                    capability,
+                    capability.command()
+                ),
+                Err(error) => {
+                    warn!("Unable to detect the features of the server: {:#}", error);
+                    return;
+                }
+            }
+        }
+        debug!("Capabilities of the server: {:?}", capabilities);
+        *self.capabilities.write().unwrap() = Some(capabilities);
+    }
+
+    /// Whether the server supports the feature, true until the features are detected
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|capabilities| capabilities.contains(&capability))
+    }
+
+    /// Whether the requests are suspended by the circuit breaker
+    pub fn is_circuit_open(&self) -> bool {
+        self.endpoint
+            .breaker
+            .as_ref()
+            .is_some_and(|breaker| breaker.is_open())
+    }
+
+    /// Whether the error is reported to the caller only rather than ending the session: the
+    /// command timeouts, and the failures to reach the server when the circuit breaker is enabled
+    pub fn tolerates(&self, error: &anyhow::Error) -> bool {
+        is_command_timeout(error)
+            || (self.endpoint.breaker.is_some()
+                && (is_unreachable(error) || is_circuit_open(error)))
+    }
+
+    /// Check whether the server answers, even with the circuit open. The circuit closes if it
+    /// does.
+    pub async fn probe(&self) -> Result<()> {
+        let (request, _) = LmsRequest::version();
+        self.endpoint
+            .send(&request, self.endpoint.timeouts().query)
+            .await?;
+        if let Some(ref breaker) = self.endpoint.breaker {
+            breaker.success();
+        }
+        Ok(())
+    }
+
     pub async fn get_version(&self) -> Result<String> {
         self.handle_error(
-            (|| async {
+            async {
                 let (request, field) = LmsRequest::version();
-                let lms_response = self.post(&request).await?;
-                as_string(lms_response, &field)
-            })()
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_string)
+            }
             .await,
             anyhow!("Error get_version"),
         )
@@ -72,13 +644,13 @@ impl LmsClient {
     }
 
     #[allow(dead_code)]
-    pub async fn get_connected(&self, name: String) -> Result<bool> {
+    pub async fn get_connected(&self, player_id: String) -> Result<bool> {
         self.handle_error(
-            (|| async {
-                let (request, field) = LmsRequest::connected(name);
-                let lms_response = self.post(&request).await?;
-                as_bool(lms_response, &field)
-            })()
+            async {
+                let (request, field) = LmsRequest::connected(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_bool)
+            }
             .await,
             anyhow!("Error get_connected"),
         )
@@ -87,11 +659,11 @@ impl LmsClient {
 
     pub async fn get_player_count(&self) -> Result<u64> {
         self.handle_error(
-            (|| async {
+            async {
                 let (request, field) = LmsRequest::player_count();
-                let lms_response = self.post(&request).await?;
-                as_u64(lms_response, &field)
-            })()
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_u64)
+            }
             .await,
             anyhow!("Error player_count"),
         )
@@ -100,79 +672,335 @@ impl LmsClient {
 
     pub async fn get_players(&self) -> Result<Vec<Player>> {
         self.handle_error(
-            (|| async {
+            async {
                 let (request, field) = LmsRequest::players();
-                let lms_response = self.post(&request).await?;
-                let value = result_field(lms_response, &field)?.clone();
-                serde_json::from_value(value.to_owned()).map_err(|e| e.into())
-            })()
+                let lms_response = self.query(request).await?;
+                lenient::items(result_field(lms_response, &field, self.is_lenient())?)
+            }
             .await,
             anyhow!("Error get_players"),
         )
         .await
     }
 
-    pub async fn get_index(&self, name: String) -> Result<u64> {
+    /// The other players of the sync group of the player, empty when it is not synced
+    pub async fn get_sync_group(&self, player_id: String) -> Result<Vec<SyncedPlayer>> {
         self.handle_error(
-            (|| async {
-                let (request, field) = LmsRequest::index(name);
-                let lms_response = self.post(&request).await?;
-                as_u64(lms_response, &field)
-            })()
+            async {
+                let (request, field) = LmsRequest::sync_groups();
+                let lms_response = self.query(request).await?;
+                let groups: Vec<SyncGroup> =
+                    match result_field(lms_response, &field, self.is_lenient()) {
+                        Result::Ok(value) => lenient::items(value)?,
+                        Err(error) => match error.downcast_ref::<ResultError>() {
+                            Some(ResultError::NoField { .. }) => Vec::new(),
+                            _ => return Err(error),
+                        },
+                    };
+                let Some(group) = groups.into_iter().find(|group| {
+                    group
+                        .ids
+                        .split(',')
+                        .any(|id| id.eq_ignore_ascii_case(&player_id))
+                }) else {
+                    return Ok(Vec::new());
+                };
+                let ids: Vec<&str> = group.ids.split(',').collect();
+                let names: Vec<&str> = group.names.split(',').collect();
+                // a name with a comma can't be told apart from the others
+                let names_match = ids.len() == names.len();
+                Ok(ids
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, id)| !id.eq_ignore_ascii_case(&player_id))
+                    .map(|(index, id)| SyncedPlayer {
+                        id: id.to_string(),
+                        name: if names_match {
+                            names[index].to_string()
+                        } else {
+                            String::new()
+                        },
+                    })
+                    .collect())
+            }
+            .await,
+            anyhow!("Error get_sync_group"),
+        )
+        .await
+    }
+
+    /// A page of the saved playlists sorted by name, along with the total number of playlists
+    pub async fn get_playlists(&self, start: u64, count: u64) -> Result<(u64, Vec<SavedPlaylist>)> {
+        self.handle_error(
+            self.page(LmsRequest::playlists(start, count)).await,
+            anyhow!("Error get_playlists"),
+        )
+        .await
+    }
+
+    /// A page of the artists of the library sorted by name, along with the total number of artists
+    pub async fn get_artists(&self, start: u64, count: u64) -> Result<(u64, Vec<Artist>)> {
+        self.handle_error(
+            self.page(LmsRequest::artists(start, count)).await,
+            anyhow!("Error get_artists"),
+        )
+        .await
+    }
+
+    /// A page of the albums, of a single artist if given, along with the total number of albums
+    pub async fn get_albums(
+        &self,
+        artist_id: Option<u64>,
+        start: u64,
+        count: u64,
+    ) -> Result<(u64, Vec<Album>)> {
+        self.handle_error(
+            self.page(LmsRequest::albums(artist_id, start, count)).await,
+            anyhow!("Error get_albums"),
+        )
+        .await
+    }
+
+    /// A page of the tracks of an album, along with the total number of tracks
+    pub async fn get_tracks(
+        &self,
+        album_id: u64,
+        start: u64,
+        count: u64,
+    ) -> Result<(u64, Vec<LibraryTrack>)> {
+        self.handle_error(
+            self.page(LmsRequest::tracks(album_id, start, count)).await,
+            anyhow!("Error get_tracks"),
+        )
+        .await
+    }
+
+    /// A page of the playlist of the player, along with the total number of tracks
+    pub async fn get_queue(
+        &self,
+        player_id: String,
+        start: u64,
+        count: u64,
+    ) -> Result<(u64, Vec<QueuedTrack>)> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::queue(player_id, start, count);
+                let lms_response = self.query(request).await?;
+                let total = as_u64(
+                    lms_response.clone(),
+                    &"playlist_tracks".to_string(),
+                    self.is_lenient(),
+                )?;
+                // the loop is missing past the end of the playlist
+                let tracks = lenient::items(result_field(lms_response, &field, true)?)?;
+                Ok((total, tracks))
+            }
+            .await,
+            anyhow!("Error get_queue"),
+        )
+        .await
+    }
+
+    /// Send the query of a page, the total number of items comes in the `count` field
+    async fn page<T: DeserializeOwned>(
+        &self,
+        (request, field): (LmsRequest, String),
+    ) -> Result<(u64, Vec<T>)> {
+        let lms_response = self.query(request).await?;
+        let total = as_u64(
+            lms_response.clone(),
+            &"count".to_string(),
+            self.is_lenient(),
+        )?;
+        // the loop is missing when the page is empty
+        let items = lenient::items(result_field(lms_response, &field, true)?)?;
+        Ok((total, items))
+    }
+
+    /// Put a URL, such as the one of a file or of a stream, in the playlist of the player
+    pub async fn enqueue_url(
+        &self,
+        player_id: String,
+        url: String,
+        enqueue: Enqueue,
+    ) -> Result<()> {
+        self.handle_error(
+            self.endpoint
+                .command(&LmsRequest::enqueue_url(player_id, url, enqueue))
+                .await
+                .map(|_| ()),
+            anyhow!("Error enqueue_url"),
+        )
+        .await
+    }
+
+    pub async fn load_playlist(&self, player_id: String, playlist_id: u64) -> Result<()> {
+        self.handle_error(
+            self.endpoint
+                .command(&LmsRequest::load_playlist(player_id, playlist_id))
+                .await
+                .map(|_| ()),
+            anyhow!("Error load_playlist"),
+        )
+        .await
+    }
+
+    /// Queue the tracks of an album, of an artist, or a single track
+    pub async fn play_library_item(
+        &self,
+        player_id: String,
+        item: LibraryItem,
+        id: u64,
+        enqueue: Enqueue,
+    ) -> Result<()> {
+        self.handle_error(
+            self.endpoint
+                .command(&LmsRequest::play_library_item(player_id, item, id, enqueue))
+                .await
+                .map(|_| ()),
+            anyhow!("Error play_library_item"),
+        )
+        .await
+    }
+
+    pub async fn save_playlist(&self, player_id: String, name: String) -> Result<()> {
+        self.handle_error(
+            self.endpoint
+                .command(&LmsRequest::save_playlist(player_id, name))
+                .await
+                .map(|_| ()),
+            anyhow!("Error save_playlist"),
+        )
+        .await
+    }
+
+    pub async fn resume_playlist(&self, player_id: String, name: String, play: bool) -> Result<()> {
+        self.handle_error(
+            self.endpoint
+                .command(&LmsRequest::resume_playlist(player_id, name, play))
+                .await
+                .map(|_| ()),
+            anyhow!("Error resume_playlist"),
+        )
+        .await
+    }
+
+    pub async fn get_index(&self, player_id: String) -> Result<u64> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::index(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_u64)
+            }
             .await,
             anyhow!("Error get_index"),
         )
         .await
     }
 
-    pub async fn get_track_count(&self, name: String) -> Result<u64> {
+    pub async fn get_track_count(&self, player_id: String) -> Result<u64> {
         self.handle_error(
-            (|| async {
-                let (request, field) = LmsRequest::track_count(name);
-                let lms_response = self.post(&request).await?;
-                as_u64(lms_response, &field)
-            })()
+            async {
+                let (request, field) = LmsRequest::track_count(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_u64)
+            }
             .await,
             anyhow!("Error get_track_count"),
         )
         .await
     }
 
-    pub async fn get_shuffle(&self, name: String) -> Result<Shuffle> {
+    pub async fn get_shuffle(&self, player_id: String) -> Result<Shuffle> {
         self.handle_error(
-            (|| async {
-                let (request, field) = LmsRequest::shuffle(name);
-                let lms_response = self.post(&request).await?;
-                as_shuffle(lms_response, &field)
-            })()
+            async {
+                let (request, field) = LmsRequest::shuffle(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_shuffle)
+            }
             .await,
             anyhow!("Error get_shuffle"),
         )
         .await
     }
 
-    pub async fn get_mode(&self, name: String) -> Result<Mode> {
+    pub async fn get_repeat(&self, player_id: String) -> Result<Repeat> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::repeat(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_repeat)
+            }
+            .await,
+            anyhow!("Error get_repeat"),
+        )
+        .await
+    }
+
+    pub async fn get_mode(&self, player_id: String) -> Result<Mode> {
         self.handle_error(
-            (|| async {
-                let (request, field) = LmsRequest::mode(name);
-                let lms_response = self.post(&request).await?;
-                as_mode(lms_response, &field)
-            })()
+            async {
+                let (request, field) = LmsRequest::mode(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_mode)
+            }
             .await,
             anyhow!("Error get_mode"),
         )
         .await
     }
 
+    /// Whether the player is buffering before it starts playing
+    pub async fn get_buffering(&self, player_id: String) -> Result<bool> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::waiting_to_play(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_bool_or_not_there)
+            }
+            .await,
+            anyhow!("Error get_buffering"),
+        )
+        .await
+    }
+
+    /// The elapsed time of the current track
+    pub async fn get_time(&self, player_id: String) -> Result<Duration> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::time(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_duration)
+            }
+            .await,
+            anyhow!("Error get_time"),
+        )
+        .await
+    }
+
+    /// The duration of the current track, remote streams usually don't have one
+    pub async fn get_duration(&self, player_id: String) -> Result<Option<Duration>> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::duration(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_duration_or_not_there)
+            }
+            .await,
+            anyhow!("Error get_duration"),
+        )
+        .await
+    }
+
     // When the playlist is empty, the `field` is not here. The `result` field contains an empty
     // object.
-    pub async fn get_artist(&self, name: String) -> Result<Option<String>> {
+    pub async fn get_artist(&self, player_id: String) -> Result<Option<String>> {
         self.handle_error(
-            (|| async {
-                let (request, field) = LmsRequest::artist(name);
-                let lms_response = self.post(&request).await?;
-                as_string_or_not_there(lms_response, &field)
-            })()
+            async {
+                let (request, field) = LmsRequest::artist(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_string_or_not_there)
+            }
             .await,
             anyhow!("Error get_artist"),
         )
@@ -180,13 +1008,13 @@ impl LmsClient {
     }
 
     // Same remark as [`get_artist`]
-    pub async fn get_title(&self, name: String) -> Result<Option<String>> {
+    pub async fn get_title(&self, player_id: String) -> Result<Option<String>> {
         self.handle_error(
-            (|| async {
-                let (request, field) = LmsRequest::title(name);
-                let lms_response = self.post(&request).await?;
-                as_string_or_not_there(lms_response, &field)
-            })()
+            async {
+                let (request, field) = LmsRequest::title(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_string_or_not_there)
+            }
             .await,
             anyhow!("Error get_title"),
         )
@@ -194,67 +1022,371 @@ impl LmsClient {
     }
 
     // ditto
-    pub async fn get_album(&self, name: String) -> Result<Option<String>> {
+    pub async fn get_album(&self, player_id: String) -> Result<Option<String>> {
         self.handle_error(
-            (|| async {
-                let (request, field) = LmsRequest::album(name);
-                let lms_response = self.post(&request).await?;
-                as_string_or_not_there(lms_response, &field)
-            })()
+            async {
+                let (request, field) = LmsRequest::album(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_string_or_not_there)
+            }
             .await,
             anyhow!("Error get_album"),
         )
         .await
     }
 
-    pub async fn play(&self, name: String) -> Result<()> {
+    /// The name of the saved playlist loaded on the player, `None` when the playlist has been
+    /// built otherwise
+    pub async fn get_playlist_name(&self, player_id: String) -> Result<Option<String>> {
         self.handle_error(
-            self.post_no_result(&LmsRequest::play(name)).await,
+            async {
+                let (request, field) = LmsRequest::playlist_name(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_string_or_not_there)
+                    .map(|name| name.filter(|name| !name.is_empty()))
+            }
+            .await,
+            anyhow!("Error get_playlist_name"),
+        )
+        .await
+    }
+
+    // Remote streams usually don't have a cover id
+    pub async fn get_cover_id(&self, player_id: String) -> Result<Option<String>> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::cover_id(player_id);
+                let lms_response = self.query(request).await?;
+                let tracks = result_field(lms_response, &field, self.is_lenient())?;
+                Ok(tracks
+                    .get(0)
+                    .and_then(|track| track.get("coverid"))
+                    .and_then(|cover_id| cover_id.as_str())
+                    .map(|cover_id| cover_id.to_string()))
+            }
+            .await,
+            anyhow!("Error get_cover_id"),
+        )
+        .await
+    }
+
+    /// The genre, the track number and the duration of the current track
+    pub async fn get_track_details(&self, player_id: String) -> Result<TrackDetails> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::track_details(player_id);
+                let lms_response = self.query(request).await?;
+                // the loop is missing when the playlist is empty
+                let tracks = result_field(lms_response, &field, true)?;
+                Ok(tracks
+                    .get(0)
+                    .cloned()
+                    .map(serde_json::from_value)
+                    .transpose()?
+                    .unwrap_or_default())
+            }
+            .await,
+            anyhow!("Error get_track_details"),
+        )
+        .await
+    }
+
+    /// The codec, the sample rate, the bit depth and the bitrate of the current track
+    pub async fn get_stream_info(&self, player_id: String) -> Result<StreamInfo> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::stream_info(player_id);
+                let lms_response = self.query(request).await?;
+                // the loop is missing when the playlist is empty
+                let tracks = result_field(lms_response, &field, true)?;
+                Ok(tracks
+                    .get(0)
+                    .cloned()
+                    .map(serde_json::from_value)
+                    .transpose()?
+                    .unwrap_or_default())
+            }
+            .await,
+            anyhow!("Error get_stream_info"),
+        )
+        .await
+    }
+
+    /// The durations of a page of the playlist, `None` for the streams
+    pub async fn get_durations(
+        &self,
+        player_id: String,
+        start: u64,
+        count: u64,
+    ) -> Result<Vec<Option<Duration>>> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::durations(player_id, start, count);
+                let lms_response = self.query(request).await?;
+                // the loop is missing past the end of the playlist
+                let tracks = result_field(lms_response, &field, true)?;
+                Ok(tracks
+                    .as_array()
+                    .map(|tracks| {
+                        tracks
+                            .iter()
+                            .map(|track| {
+                                track
+                                    .get("duration")
+                                    .and_then(|duration| lenient::number(duration).ok())
+                                    .map(to_duration)
+                                    .filter(|duration| !duration.is_zero())
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default())
+            }
+            .await,
+            anyhow!("Error get_durations"),
+        )
+        .await
+    }
+
+    /// The URL of the cover with the given id
+    pub fn cover_url(&self, cover_id: &str) -> String {
+        format!("{}/music/{}/cover.jpg", self.base_url(), cover_id)
+    }
+
+    // A missing cover is not fatal, so the error is returned to the caller rather than sent to the
+    // error channel
+    pub async fn get_cover(&self, cover_id: &str) -> Result<Vec<u8>> {
+        let url = self.cover_url(cover_id);
+        debug!("Downloading cover: {}", url);
+        self.endpoint
+            .counters
+            .requests
+            .fetch_add(1, Ordering::Relaxed);
+        let response = self
+            .endpoint
+            .authenticate(self.endpoint.client.get(url))
+            .send()
+            .await?;
+        let bytes = self
+            .endpoint
+            .check_status(response)?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(bytes.to_vec())
+    }
+
+    // The volume is between 0 and 100, it is negative when the player is muted
+    pub async fn get_volume(&self, player_id: String) -> Result<i64> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::mixer_volume(player_id);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_i64)
+            }
+            .await,
+            anyhow!("Error get_volume"),
+        )
+        .await
+    }
+
+    pub async fn get_tone(&self, player_id: String, tone: Tone) -> Result<i64> {
+        self.handle_error(
+            async {
+                let (request, field) = LmsRequest::mixer_tone(player_id, tone);
+                let lms_response = self.query(request).await?;
+                self.convert(lms_response, &field, as_i64)
+            }
+            .await,
+            anyhow!("Error get_tone"),
+        )
+        .await
+    }
+
+    pub async fn play(&self, player_id: String) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::Play).await,
             anyhow!("Error play"),
         )
         .await
     }
 
-    pub async fn stop(&self, name: String) -> Result<()> {
+    pub async fn stop(&self, player_id: String) -> Result<()> {
         self.handle_error(
-            self.post_no_result(&LmsRequest::stop(name)).await,
+            self.send_command(player_id, Command::Stop).await,
             anyhow!("Error stop"),
         )
         .await
     }
 
-    pub async fn pause(&self, name: String) -> Result<()> {
+    pub async fn pause(&self, player_id: String) -> Result<()> {
         self.handle_error(
-            self.post_no_result(&LmsRequest::pause(name)).await,
+            self.send_command(player_id, Command::Pause).await,
             anyhow!("Error pause"),
         )
         .await
     }
 
-    pub async fn play_pause(&self, name: String) -> Result<()> {
+    pub async fn play_pause(&self, player_id: String) -> Result<()> {
         self.handle_error(
-            self.post_no_result(&LmsRequest::play_pause(name)).await,
+            self.send_command(player_id, Command::PlayPause).await,
             anyhow!("Error play_pause"),
         )
         .await
     }
 
-    pub async fn previous(&self, name: String) -> Result<()> {
+    pub async fn previous(&self, player_id: String) -> Result<()> {
         self.handle_error(
-            self.post_no_result(&LmsRequest::previous(name)).await,
+            self.send_command(player_id, Command::Previous).await,
             anyhow!("Error previous"),
         )
         .await
     }
 
-    pub async fn next(&self, name: String) -> Result<()> {
+    pub async fn power(&self, player_id: String, on: bool) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::Power(on)).await,
+            anyhow!("Error power"),
+        )
+        .await
+    }
+
+    pub async fn set_volume(&self, player_id: String, volume: u64) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::Volume(volume)).await,
+            anyhow!("Error set_volume"),
+        )
+        .await
+    }
+
+    /// Change the volume by the given amount, down when negative
+    pub async fn change_volume(&self, player_id: String, change: i64) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::VolumeChange(change))
+                .await,
+            anyhow!("Error change_volume"),
+        )
+        .await
+    }
+
+    pub async fn set_muting(&self, player_id: String, muted: bool) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::Muting(muted)).await,
+            anyhow!("Error set_muting"),
+        )
+        .await
+    }
+
+    pub async fn set_shuffle(&self, player_id: String, shuffle: Shuffle) -> Result<()> {
         self.handle_error(
-            self.post_no_result(&LmsRequest::next(name)).await,
+            self.send_command(player_id, Command::Shuffle(shuffle))
+                .await,
+            anyhow!("Error set_shuffle"),
+        )
+        .await
+    }
+
+    pub async fn set_repeat(&self, player_id: String, repeat: Repeat) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::Repeat(repeat)).await,
+            anyhow!("Error set_repeat"),
+        )
+        .await
+    }
+
+    pub async fn set_tone(&self, player_id: String, tone: Tone, value: i64) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::Tone(tone, value))
+                .await,
+            anyhow!("Error set_tone"),
+        )
+        .await
+    }
+
+    pub async fn next(&self, player_id: String) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::Next).await,
             anyhow!("Error next"),
         )
         .await
     }
 
+    /// Move to the given position in the current track. The seeks made while scrubbing are merged.
+    pub async fn seek(&self, player_id: String, position: Duration) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::Seek(position)).await,
+            anyhow!("Error seek"),
+        )
+        .await
+    }
+
+    /// Move in the current track by the given number of seconds, backward when negative
+    pub async fn seek_relative(&self, player_id: String, seconds: i64) -> Result<()> {
+        self.handle_error(
+            self.send_command(player_id, Command::SeekRelative(seconds))
+                .await,
+            anyhow!("Error seek_relative"),
+        )
+        .await
+    }
+
+    /// Rename the player on LMS
+    pub async fn set_name(&self, player_id: String, new_name: String) -> Result<()> {
+        self.handle_error(
+            self.endpoint
+                .command(&LmsRequest::set_name(player_id, new_name))
+                .await
+                .map(|_| ()),
+            anyhow!("Error set_name"),
+        )
+        .await
+    }
+
+    // The escape hatch for the commands without a dedicated function. As the parameters come from
+    // the user, the error is returned to the caller rather than sent to the error channel.
+    pub async fn raw(&self, player_id: String, params: Vec<String>) -> Result<Value> {
+        let lms_response = self
+            .endpoint
+            .query(&LmsRequest::raw(player_id, params))
+            .await?;
+        Ok(lms_response.result)
+    }
+
+    pub fn counters(&self) -> &Counters {
+        &self.endpoint.counters
+    }
+
+    /// The URL of the LMS web server
+    pub fn base_url(&self) -> String {
+        self.endpoint.base_url.read().unwrap().clone()
+    }
+
+    fn is_lenient(&self) -> bool {
+        self.lenient.load(Ordering::Relaxed)
+    }
+
+    // With the auto flavor, a response that can't be parsed strictly switches to the lenient
+    // parsing for good
+    fn convert<T>(
+        &self,
+        response: LmsResponse,
+        field: &String,
+        converter: Converter<T>,
+    ) -> Result<T> {
+        if self.is_lenient() || self.flavor != ServerFlavor::Auto {
+            return converter(response, field, self.is_lenient());
+        }
+        converter(response.clone(), field, false).or_else(|error| {
+            let result = response.result.clone();
+            let value = converter(response, field, true).map_err(|_| error)?;
+            warn!(
+                "Unexpected response from the server, parsing the next ones leniently: {}",
+                result
+            );
+            self.lenient.store(true, Ordering::Relaxed);
+            Ok(value)
+        })
+    }
+
     // The error is not passed to the client but sent to the error channel
     async fn handle_error<T: std::fmt::Debug>(
         &self,
@@ -267,48 +1399,208 @@ impl LmsClient {
                 Ok(s)
             }
             Err(error_from_result) => {
+                let counters = &self.endpoint.counters;
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+                *counters.last_error.lock().unwrap() = Some(format!("{:#}", error_from_result));
+                if self.tolerates(&error_from_result) {
+                    if is_circuit_open(&error_from_result) {
+                        debug!("{:#}", error_from_result);
+                    } else {
+                        warn!("{:#}", error_from_result);
+                    }
+                    // the cause tells the callers that the error is tolerated
+                    return Err(error_from_result.context(error));
+                }
                 self.sender.send(error_from_result).await?;
                 Err(error)
             }
         }
     }
 
-    async fn post(&self, request: &LmsRequest) -> Result<LmsResponse> {
+    async fn send_command(&self, player_id: String, command: Command) -> Result<()> {
+        let queue = self
+            .queues
+            .lock()
+            .unwrap()
+            .entry(player_id.clone())
+            .or_insert_with(|| CommandQueue::new(self.endpoint.clone(), player_id))
+            .clone();
+        queue.send(command).await
+    }
+
+    // Identical queries sent concurrently are coalesced: the first caller sends the request to
+    // LMS, the others wait for its response.
+    async fn query(&self, request: LmsRequest) -> Result<LmsResponse> {
+        let key = serde_json::to_string(&request)?;
+        let in_flight = {
+            let mut queries = self.in_flight.lock().unwrap();
+            match queries.get(&key) {
+                Some(in_flight) => {
+                    debug!("Waiting for in-flight request: {}", key);
+                    in_flight.clone()
+                }
+                None => {
+                    let endpoint = self.endpoint.clone();
+                    let queries_ref = self.in_flight.clone();
+                    let key_ref = key.clone();
+                    let in_flight = async move {
+                        let result = endpoint.query(&request).await;
+                        queries_ref.lock().unwrap().remove(&key_ref);
+                        result.map_err(Arc::new)
+                    }
+                    .boxed()
+                    .shared();
+                    queries.insert(key, in_flight.clone());
+                    in_flight
+                }
+            }
+        };
+        in_flight.await.map_err(|error| QueryError(error).into())
+    }
+}
+
+impl Endpoint {
+    fn timeouts(&self) -> Timeouts {
+        *self.timeouts.read().unwrap()
+    }
+
+    async fn query(&self, request: &LmsRequest) -> Result<LmsResponse> {
+        self.post(request, self.timeouts().query).await
+    }
+
+    async fn command(&self, request: &LmsRequest) -> Result<LmsResponse> {
+        let timeout = self.timeouts().command;
+        self.post(request, timeout).await.map_err(|error| {
+            match (timeout, error.downcast_ref::<reqwest::Error>()) {
+                (Some(timeout), Some(cause)) if cause.is_timeout() => {
+                    anyhow!(LmsError::CommandTimeout(timeout))
+                }
+                _ => error,
+            }
+        })
+    }
+
+    // With the circuit breaker, the request is not sent while the circuit is open
+    async fn post(&self, request: &LmsRequest, timeout: Option<u64>) -> Result<LmsResponse> {
+        let Some(ref breaker) = self.breaker else {
+            return self.send(request, timeout).await;
+        };
+        if breaker.is_open() {
+            bail!(LmsError::CircuitOpen);
+        }
+        let result = self.send(request, timeout).await;
+        match result {
+            Result::Ok(_) => breaker.success(),
+            Err(ref error) if is_unreachable(error) => breaker.failure(),
+            Err(_) => (),
+        }
+        result
+    }
+
+    async fn send(&self, request: &LmsRequest, timeout: Option<u64>) -> Result<LmsResponse> {
         debug!("Sending: {:?}", request);
-        let response = self.client.post(&self.url).json(&request).send().await?;
-        response
-            .json()
-            .await
-            .map(|response| {
-                debug!("Received: {:?}", response);
-                response
-            })
-            .map_err(|error| error.into())
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        let url = format!("{}/jsonrpc.js", self.base_url.read().unwrap());
+        let mut builder = self.authenticate(self.client.post(url)).json(request);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(Duration::from_millis(timeout));
+        }
+        let response = builder.send().await?;
+        let response: LmsResponse = self.check_status(response)?.json().await?;
+        debug!("Received: {:?}", response);
+        if self.counters.degraded.swap(false, Ordering::Relaxed) {
+            info!("The server answers again");
+        }
+        match response.error {
+            Some(error) => bail!(LmsError::Server(error_message(error))),
+            None => Ok(response),
+        }
+    }
+
+    fn authenticate(&self, builder: RequestBuilder) -> RequestBuilder {
+        match *self.credentials.read().unwrap() {
+            Some(ref credentials) => {
+                builder.basic_auth(&credentials.username, Some(&credentials.password.0))
+            }
+            None => builder,
+        }
+    }
+
+    // LMS replies with an HTML page when the authentication fails, which would otherwise end up in
+    // a confusing decoding error
+    fn check_status(&self, response: reqwest::Response) -> Result<reqwest::Response> {
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                if self.credentials.read().unwrap().is_some() {
+                    bail!(LmsError::InvalidCredentials)
+                } else {
+                    bail!(LmsError::AuthenticationRequired)
+                }
+            }
+            _ => Ok(response),
+        }
     }
+}
 
-    async fn post_no_result(&self, request: &LmsRequest) -> Result<()> {
-        self.post(&request)
-            .await
-            .map(|_| ())
-            .map_err(|error| error.into())
+/// Fetch all the pages of a list, such as the artists of the library or the playlist of a player
+pub async fn all_pages<T, F, R>(fetch: F) -> Result<Vec<T>>
+where
+    F: Fn(u64) -> R,
+    R: Future<Output = Result<(u64, Vec<T>)>>,
+{
+    let mut items = Vec::new();
+    loop {
+        let (total, page) = fetch(items.len() as u64).await?;
+        let empty = page.is_empty();
+        items.extend(page);
+        if empty || items.len() as u64 >= total {
+            return Ok(items);
+        }
     }
 }
 
+fn to_base_url(hostname: &str, port: u16) -> String {
+    format!("http://{}:{}", hostname, port)
+}
+
 /// The response sent by LMS is a JSON object with this structure. The actual payload is in the
-/// result field.
+/// result field. Other servers may leave out some of the fields.
 #[derive(Clone, Debug, Deserialize)]
 struct LmsResponse {
     #[allow(dead_code)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     method: String,
     #[allow(dead_code)]
+    #[serde(default, deserialize_with = "lenient::or_default")]
     params: (String, Vec<String>),
 
+    #[serde(default)]
     result: serde_json::Value,
+
+    /// The JSON-RPC error object, sent instead of the result
+    #[serde(default)]
+    error: Option<Value>,
 }
 
-fn as_bool(response: LmsResponse, field: &String) -> Result<bool> {
-    let value = result_field(response, field)?;
+// The error is usually an object with a message, some servers send the message alone
+fn error_message(error: Value) -> String {
+    match error {
+        Value::String(message) => message,
+        Value::Object(ref object) => match object.get("message") {
+            Some(Value::String(message)) => message.clone(),
+            _ => error.to_string(),
+        },
+        other => other.to_string(),
+    }
+}
+
+fn as_bool(response: LmsResponse, field: &String, lenient: bool) -> Result<bool> {
+    let value = result_field(response, field, lenient)?;
+    if lenient {
+        return lenient::number(&value).map(|n| n != 0.0);
+    }
     match value {
+        Value::String(n) => n.parse::<i64>().map(|i| i != 0).map_err(|e| e.into()),
         Value::Number(n) => n
             .as_i64()
             .map(|i| i != 0)
@@ -317,8 +1609,19 @@ fn as_bool(response: LmsResponse, field: &String) -> Result<bool> {
     }
 }
 
-fn as_u64(response: LmsResponse, field: &String) -> Result<u64> {
-    let value = result_field(response, field)?;
+// The flags only sent when relevant are false when missing
+fn as_bool_or_not_there(response: LmsResponse, field: &String, lenient: bool) -> Result<bool> {
+    as_bool(response, field, lenient).or_else(|e| match e.downcast_ref::<ResultError>() {
+        Some(ResultError::NoField { .. }) => Ok(false),
+        _ => Err(e),
+    })
+}
+
+fn as_u64(response: LmsResponse, field: &String, lenient: bool) -> Result<u64> {
+    let value = result_field(response, field, lenient)?;
+    if lenient {
+        return lenient::number(&value).map(|n| n as u64);
+    }
     match value {
         Value::String(n) => n.parse::<u64>().map_err(|e| e.into()),
         Value::Number(n) => n.as_u64().ok_or_else(|| anyhow!("{} is not an u64", n)),
@@ -326,42 +1629,105 @@ fn as_u64(response: LmsResponse, field: &String) -> Result<u64> {
     }
 }
 
-fn as_string(response: LmsResponse, field: &String) -> Result<String> {
-    let value = result_field(response, field)?;
+fn as_i64(response: LmsResponse, field: &String, lenient: bool) -> Result<i64> {
+    let value = result_field(response, field, lenient)?;
+    if lenient {
+        return lenient::number(&value).map(|n| n as i64);
+    }
+    match value {
+        Value::String(n) => n.parse::<i64>().map_err(|e| e.into()),
+        Value::Number(n) => n.as_i64().ok_or_else(|| anyhow!("{} is not an i64", n)),
+        _ => bail!("Wrong top level type for i64: {:?}", value),
+    }
+}
+
+fn as_f64(response: LmsResponse, field: &String, lenient: bool) -> Result<f64> {
+    let value = result_field(response, field, lenient)?;
+    if lenient {
+        return lenient::number(&value);
+    }
+    match value {
+        Value::String(n) => n.parse::<f64>().map_err(|e| e.into()),
+        Value::Number(n) => n.as_f64().ok_or_else(|| anyhow!("{} is not an f64", n)),
+        _ => bail!("Wrong top level type for f64: {:?}", value),
+    }
+}
+
+// LMS sends the times in seconds, the invalid ones such as the negative ones are clamped to zero
+fn to_duration(seconds: f64) -> Duration {
+    Duration::try_from_secs_f64(seconds).unwrap_or_default()
+}
+
+fn as_duration(response: LmsResponse, field: &String, lenient: bool) -> Result<Duration> {
+    as_f64(response, field, lenient).map(to_duration)
+}
+
+// A duration of zero is considered as missing, LMS reports it for the remote streams
+fn as_duration_or_not_there(
+    response: LmsResponse,
+    field: &String,
+    lenient: bool,
+) -> Result<Option<Duration>> {
+    as_duration(response, field, lenient)
+        .map(|duration| Some(duration).filter(|duration| !duration.is_zero()))
+        .or_else(|e| match e.downcast_ref::<ResultError>() {
+            Some(ResultError::NoField { .. }) => Ok(None),
+            _ => Err(e),
+        })
+}
+
+fn as_string(response: LmsResponse, field: &String, lenient: bool) -> Result<String> {
+    let value = result_field(response, field, lenient)?;
     match value {
         Value::String(s) => Ok(s.clone()),
+        Value::Null if lenient => Ok(String::new()),
+        Value::Number(n) if lenient => Ok(n.to_string()),
         _ => bail!("Wrong top level type for string: {:?}", value),
     }
 }
 
-fn as_string_or_not_there(response: LmsResponse, field: &String) -> Result<Option<String>> {
-    as_string(response, &field)
-        .map(Some)
+// With the lenient profile, an empty string is considered as missing
+fn as_string_or_not_there(
+    response: LmsResponse,
+    field: &String,
+    lenient: bool,
+) -> Result<Option<String>> {
+    as_string(response, field, lenient)
+        .map(|s| Some(s).filter(|s| !lenient || !s.is_empty()))
         .or_else(|e| match e.downcast_ref::<ResultError>() {
             Some(ResultError::NoField { .. }) => Ok(None),
             _ => Err(e),
         })
 }
 
-fn as_mode(response: LmsResponse, field: &String) -> Result<Mode> {
-    let value = result_field(response, &field)?;
+fn as_mode(response: LmsResponse, field: &String, lenient: bool) -> Result<Mode> {
+    let value = result_field(response, field, lenient)?;
     match value {
         Value::String(s) => match s.as_str() {
             "stop" => Ok(Mode::Stop),
             "play" => Ok(Mode::Play),
             "pause" => Ok(Mode::Pause),
+            _ if lenient => Ok(Mode::Stop),
             other => bail!("Expected stop, play or pause, got {}", other),
         },
+        Value::Null if lenient => Ok(Mode::Stop),
         _ => bail!("Wrong top level type for mode: {:?}", value),
     }
 }
 
-fn as_shuffle(response: LmsResponse, field: &String) -> Result<Shuffle> {
+fn as_shuffle(response: LmsResponse, field: &String, lenient: bool) -> Result<Shuffle> {
     fn wrong_value<T: std::fmt::Display>(value: T) -> anyhow::Error {
         anyhow!("Expected 0, 1 or 2, got {}", value)
     }
 
-    let value = result_field(response, &field)?;
+    let value = result_field(response, field, lenient)?;
+    if lenient {
+        return lenient::number(&value).map(|n| match n as u64 {
+            1 => Shuffle::Songs,
+            2 => Shuffle::Albums,
+            _ => Shuffle::Off,
+        });
+    }
     match value {
         Value::String(s) => match s.as_str() {
             "0" => Ok(Shuffle::Off),
@@ -379,6 +1745,36 @@ fn as_shuffle(response: LmsResponse, field: &String) -> Result<Shuffle> {
     }
 }
 
+fn as_repeat(response: LmsResponse, field: &String, lenient: bool) -> Result<Repeat> {
+    fn wrong_value<T: std::fmt::Display>(value: T) -> anyhow::Error {
+        anyhow!("Expected 0, 1 or 2, got {}", value)
+    }
+
+    let value = result_field(response, field, lenient)?;
+    if lenient {
+        return lenient::number(&value).map(|n| match n as u64 {
+            1 => Repeat::Track,
+            2 => Repeat::Playlist,
+            _ => Repeat::Off,
+        });
+    }
+    match value {
+        Value::String(s) => match s.as_str() {
+            "0" => Ok(Repeat::Off),
+            "1" => Ok(Repeat::Track),
+            "2" => Ok(Repeat::Playlist),
+            _ => Err(wrong_value(s)),
+        },
+        Value::Number(n) => match n.as_u64() {
+            Some(0) => Ok(Repeat::Off),
+            Some(1) => Ok(Repeat::Track),
+            Some(2) => Ok(Repeat::Playlist),
+            _ => Err(wrong_value(n)),
+        },
+        _ => bail!("Wrong top level type for repeat: {:?}", value),
+    }
+}
+
 #[derive(Debug, Error)]
 enum ResultError {
     #[error("The result field has the wrong type: {response:?}")]
@@ -390,7 +1786,11 @@ enum ResultError {
     },
 }
 
-fn result_field(response: LmsResponse, field: &String) -> Result<Value> {
+// With the lenient profile, a missing field is null
+fn result_field(response: LmsResponse, field: &String, lenient: bool) -> Result<Value> {
+    if lenient {
+        return Ok(response.result.get(field).cloned().unwrap_or_default());
+    }
     let mut result = match response.result {
         Value::Object(ref map) => Ok(map.clone()),
         _ => Err(anyhow!(ResultError::ResultHasWrongType {
@@ -399,8 +1799,44 @@ fn result_field(response: LmsResponse, field: &String) -> Result<Value> {
     }?;
     result.remove(field).ok_or_else(|| {
         anyhow!(ResultError::NoField {
-            response: response,
+            response,
             field: field.clone()
         })
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_duration_of_zero() {
+        assert_eq!(to_duration(0.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn to_duration_keeps_the_fractions_of_microsecond() {
+        assert_eq!(to_duration(0.000_000_5), Duration::from_nanos(500));
+        assert_eq!(to_duration(12.5), Duration::from_millis(12_500));
+    }
+
+    #[test]
+    fn to_duration_at_the_end_of_the_track() {
+        assert_eq!(to_duration(200.0), Duration::from_secs(200));
+        assert!(to_duration(199.999_999_9) < Duration::from_secs(200));
+    }
+
+    #[test]
+    fn to_duration_past_the_end_of_the_track() {
+        // LMS may report an elapsed time slightly past the duration, it is kept as is
+        assert_eq!(to_duration(200.25), Duration::from_millis(200_250));
+    }
+
+    #[test]
+    fn to_duration_clamps_the_invalid_times() {
+        assert_eq!(to_duration(-1.0), Duration::ZERO);
+        assert_eq!(to_duration(-0.000_001), Duration::ZERO);
+        assert_eq!(to_duration(f64::NAN), Duration::ZERO);
+        assert_eq!(to_duration(f64::INFINITY), Duration::ZERO);
+    }
+}
@@ -0,0 +1,185 @@
+//! The control commands sent to a player. They go through a queue processed by a task dedicated to
+//! the player, so that they reach LMS in the order they have been issued. The commands queued
+//! while a previous one is being sent are coalesced when they are redundant.
+use crate::lms::{request::LmsRequest, Endpoint, QueryError, Repeat, Shuffle, Tone};
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::{result, sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+
+/// A seek is held this long for the next one, so that the scrubbing of a slider ends up in a single
+/// request
+const SEEK_WINDOW: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Play,
+    Stop,
+    Pause,
+    PlayPause,
+    Previous,
+    Next,
+    Volume(u64),
+    /// Change the volume by the given amount
+    VolumeChange(i64),
+    Muting(bool),
+    Tone(Tone, i64),
+    Shuffle(Shuffle),
+    Repeat(Repeat),
+    Power(bool),
+    /// Move in the current track by the given number of seconds
+    SeekRelative(i64),
+    /// Move to the given position in the current track
+    Seek(Duration),
+}
+
+impl Command {
+    fn request(self, player_id: String) -> LmsRequest {
+        match self {
+            Command::Play => LmsRequest::play(player_id),
+            Command::Stop => LmsRequest::stop(player_id),
+            Command::Pause => LmsRequest::pause(player_id),
+            Command::PlayPause => LmsRequest::play_pause(player_id),
+            Command::Previous => LmsRequest::previous(player_id),
+            Command::Next => LmsRequest::next(player_id),
+            Command::Volume(volume) => LmsRequest::set_mixer_volume(player_id, volume),
+            Command::VolumeChange(change) => LmsRequest::change_mixer_volume(player_id, change),
+            Command::Muting(muted) => LmsRequest::mixer_muting(player_id, muted),
+            Command::Tone(tone, value) => LmsRequest::set_mixer_tone(player_id, tone, value),
+            Command::Shuffle(shuffle) => LmsRequest::set_shuffle(player_id, shuffle),
+            Command::Repeat(repeat) => LmsRequest::set_repeat(player_id, repeat),
+            Command::Power(on) => LmsRequest::power(player_id, on),
+            Command::SeekRelative(seconds) => LmsRequest::seek_relative(player_id, seconds),
+            Command::Seek(position) => LmsRequest::seek(player_id, position),
+        }
+    }
+
+    /// Setting the volume, a tone, the shuffle or repeat mode, the mode or the position overrides
+    /// the previous value
+    fn overrides(self, previous: Command) -> bool {
+        (self.sets_mode() && previous.sets_mode())
+            || matches!((self, previous), (Command::Volume(_), Command::Volume(_)))
+            || matches!((self, previous), (Command::Shuffle(_), Command::Shuffle(_)))
+            || matches!((self, previous), (Command::Repeat(_), Command::Repeat(_)))
+            || matches!((self, previous), (Command::Seek(_), Command::Seek(_)))
+            || matches!((self, previous), (Command::Tone(tone, _), Command::Tone(previous, _)) if tone == previous)
+    }
+
+    /// Play, stop and pause set the mode of the player whatever its current mode is
+    fn sets_mode(self) -> bool {
+        matches!(self, Command::Play | Command::Stop | Command::Pause)
+    }
+}
+
+type CommandResult = result::Result<(), Arc<anyhow::Error>>;
+
+type Reply = oneshot::Sender<CommandResult>;
+
+#[derive(Clone)]
+pub struct CommandQueue {
+    sender: mpsc::UnboundedSender<(Command, Reply)>,
+}
+
+impl CommandQueue {
+    pub fn new(endpoint: Endpoint, player_id: String) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run(endpoint, player_id, receiver));
+        Self { sender }
+    }
+
+    /// Queue the command and wait for it to be sent
+    pub async fn send(&self, command: Command) -> Result<()> {
+        let (reply, receiver) = oneshot::channel();
+        self.sender
+            .send((command, reply))
+            .map_err(|_| anyhow!("The command queue is closed"))?;
+        receiver.await?.map_err(|error| QueryError(error).into())
+    }
+}
+
+/// A command to send to LMS along with the callers waiting for it
+struct Step {
+    command: Command,
+    /// Toggling the mode twice in a row is a no-op
+    cancelled: bool,
+    replies: Vec<Reply>,
+}
+
+impl Step {
+    /// The command to actually send, if any
+    fn to_send(&self) -> Option<Command> {
+        Some(self.command).filter(|_| !self.cancelled)
+    }
+}
+
+// Consecutive commands setting the mode, the volume, a tone or the position are replaced by the last
+// one, consecutive toggles are merged together. Next, previous and the relative seeks are never
+// coalesced as each of them moves in the playlist or in the track.
+fn coalesce(batch: Vec<(Command, Reply)>) -> Vec<Step> {
+    let mut steps: Vec<Step> = Vec::new();
+    for (command, reply) in batch {
+        match steps.last_mut() {
+            Some(last) if command.overrides(last.command) => {
+                last.command = command;
+                last.replies.push(reply);
+            }
+            Some(last) if last.command == Command::PlayPause && command == Command::PlayPause => {
+                last.cancelled = !last.cancelled;
+                last.replies.push(reply);
+            }
+            _ => steps.push(Step {
+                command,
+                cancelled: false,
+                replies: vec![reply],
+            }),
+        }
+    }
+    steps
+}
+
+async fn run(
+    endpoint: Endpoint,
+    player_id: String,
+    mut receiver: mpsc::UnboundedReceiver<(Command, Reply)>,
+) {
+    while let Some(first) = receiver.recv().await {
+        let mut batch = vec![first];
+        while let Ok(next) = receiver.try_recv() {
+            batch.push(next);
+        }
+        // wait for the end of the scrubbing
+        while matches!(batch.last(), Some((Command::Seek(_), _))) {
+            match timeout(SEEK_WINDOW, receiver.recv()).await {
+                Ok(Some(next)) => batch.push(next),
+                _ => break,
+            }
+        }
+        let batch_len = batch.len();
+        let steps = coalesce(batch);
+        if steps.len() != batch_len {
+            debug!(
+                "Coalesced {} commands into {} for player {}",
+                batch_len,
+                steps.len(),
+                player_id
+            );
+        }
+
+        for step in steps {
+            let result = match step.to_send() {
+                Some(command) => endpoint
+                    .command(&command.request(player_id.clone()))
+                    .await
+                    .map(|_| ())
+                    .map_err(Arc::new),
+                None => Ok(()),
+            };
+            for reply in step.replies {
+                let _ = reply.send(result.clone());
+            }
+        }
+    }
+}
@@ -1,7 +1,9 @@
 //! The functions to create the requests sent to the LMS server. The requests available are
 //! described in [the LMS
 //! documentation](https://raw.githack.com/Logitech/slimserver/public/8.4/HTML/EN/html/docs/cli-api.html)
+use crate::lms::{Enqueue, LibraryItem, Repeat, Shuffle, Tone};
 use serde::Serialize;
+use std::time::Duration;
 
 /// This structure is serialized to JSON and sent to the LMS server.
 #[derive(Debug, Serialize)]
@@ -11,10 +13,10 @@ pub struct LmsRequest {
 }
 
 impl LmsRequest {
-    fn new(name: String) -> Self {
+    fn new(player_id: String) -> Self {
         Self {
             method: "slim.request".to_string(),
-            params: (name, vec![]),
+            params: (player_id, vec![]),
         }
     }
 
@@ -27,6 +29,33 @@ impl LmsRequest {
         Self::new("".to_string()).question("version".to_string())
     }
 
+    /// The status of the server, without any player
+    pub fn server_status() -> (Self, String) {
+        let request = Self::new("".to_string())
+            .add_param("serverstatus".to_string())
+            .add_param("0".to_string())
+            .add_param("0".to_string());
+        (request, "uuid".to_string())
+    }
+
+    /// The name of the library, empty when it has not been set
+    pub fn library_name() -> (Self, String) {
+        let request = Self::new("".to_string())
+            .add_param("pref".to_string())
+            .add_param("libraryname".to_string())
+            .add_param("?".to_string());
+        (request, "_p2".to_string())
+    }
+
+    /// Whether the server knows the command, the ones of the disabled plugins are missing
+    pub fn can(command: &str) -> (Self, String) {
+        let request = Self::new("".to_string())
+            .add_param("can".to_string())
+            .add_param(command.to_string())
+            .add_param("?".to_string());
+        (request, "_can".to_string())
+    }
+
     fn question(self, key: String) -> (Self, String) {
         (
             self.add_param(key.clone()).add_param("?".to_string()),
@@ -34,8 +63,8 @@ impl LmsRequest {
         )
     }
 
-    pub fn connected(name: String) -> (Self, String) {
-        Self::new(name).question("connected".to_string())
+    pub fn connected(player_id: String) -> (Self, String) {
+        Self::new(player_id).question("connected".to_string())
     }
 
     pub fn players() -> (Self, String) {
@@ -47,71 +76,342 @@ impl LmsRequest {
         )
     }
 
+    /// The groups of synced players, without a loop when there is none
+    pub fn sync_groups() -> (Self, String) {
+        let request = Self::new("".to_string())
+            .add_param("syncgroups".to_string())
+            .add_param("?".to_string());
+        (request, "syncgroups_loop".to_string())
+    }
+
+    /// A page of the saved playlists, which LMS sorts by name. The total number of playlists comes
+    /// in the `count` field.
+    pub fn playlists(start: u64, count: u64) -> (Self, String) {
+        (
+            Self::page("playlists", start, count),
+            "playlists_loop".to_string(),
+        )
+    }
+
+    /// A page of the artists of the library, sorted by name
+    pub fn artists(start: u64, count: u64) -> (Self, String) {
+        (
+            Self::page("artists", start, count),
+            "artists_loop".to_string(),
+        )
+    }
+
+    /// A page of the albums, of a single artist if given
+    pub fn albums(artist_id: Option<u64>, start: u64, count: u64) -> (Self, String) {
+        let mut request = Self::page("albums", start, count).add_param("tags:l".to_string());
+        if let Some(artist_id) = artist_id {
+            request = request.add_param(format!("artist_id:{}", artist_id));
+        }
+        (request, "albums_loop".to_string())
+    }
+
+    /// A page of the tracks of an album, in their order on the album
+    pub fn tracks(album_id: u64, start: u64, count: u64) -> (Self, String) {
+        (
+            Self::page("titles", start, count)
+                .add_param(format!("album_id:{}", album_id))
+                .add_param("sort:tracknum".to_string())
+                .add_param("tags:t".to_string()),
+            "titles_loop".to_string(),
+        )
+    }
+
+    fn page(command: &str, start: u64, count: u64) -> Self {
+        Self::new("".to_string())
+            .add_param(command.to_string())
+            .add_param(start.to_string())
+            .add_param(count.to_string())
+    }
+
+    /// Replace the playlist of the player by a saved playlist
+    pub fn load_playlist(player_id: String, playlist_id: u64) -> Self {
+        Self::new(player_id)
+            .add_param("playlistcontrol".to_string())
+            .add_param("cmd:load".to_string())
+            .add_param(format!("playlist_id:{}", playlist_id))
+    }
+
+    pub fn play_library_item(
+        player_id: String,
+        item: LibraryItem,
+        id: u64,
+        enqueue: Enqueue,
+    ) -> Self {
+        Self::new(player_id)
+            .add_param("playlistcontrol".to_string())
+            .add_param(enqueue.param().to_string())
+            .add_param(format!("{}:{}", item.param(), id))
+    }
+
+    pub fn enqueue_url(player_id: String, url: String, enqueue: Enqueue) -> Self {
+        Self::playlist(player_id)
+            .add_param(enqueue.verb().to_string())
+            .add_param(url)
+    }
+
+    /// Save the playlist of the player on the server, with the current track and position
+    pub fn save_playlist(player_id: String, name: String) -> Self {
+        Self::playlist(player_id)
+            .add_param("save".to_string())
+            .add_param(name)
+            .add_param("silent:1".to_string())
+    }
+
+    /// Restore a playlist saved with [LmsRequest::save_playlist], which is deleted afterwards
+    pub fn resume_playlist(player_id: String, name: String, play: bool) -> Self {
+        Self::playlist(player_id)
+            .add_param("resume".to_string())
+            .add_param(name)
+            .add_param(format!("noplay:{}", u8::from(!play)))
+            .add_param("wipePlaylist:1".to_string())
+    }
+
     pub fn player_count() -> (Self, String) {
         Self::new("".to_string())
             .add_param("player".to_string())
             .question("count".to_string())
     }
 
-    pub fn artist(name: String) -> (Self, String) {
-        Self::new(name).question("artist".to_string())
+    pub fn artist(player_id: String) -> (Self, String) {
+        Self::new(player_id).question("artist".to_string())
+    }
+
+    pub fn title(player_id: String) -> (Self, String) {
+        Self::new(player_id).question("title".to_string())
+    }
+
+    pub fn album(player_id: String) -> (Self, String) {
+        Self::new(player_id).question("album".to_string())
+    }
+
+    pub fn time(player_id: String) -> (Self, String) {
+        Self::new(player_id).question("time".to_string())
+    }
+
+    pub fn duration(player_id: String) -> (Self, String) {
+        Self::new(player_id).question("duration".to_string())
+    }
+
+    /// Move to the given position in the current track
+    pub fn seek(player_id: String, position: Duration) -> Self {
+        Self::new(player_id)
+            .add_param("time".to_string())
+            .add_param(format!("{:.3}", position.as_secs_f64()))
+    }
+
+    /// Move in the current track by the given number of seconds, backward when negative
+    pub fn seek_relative(player_id: String, seconds: i64) -> Self {
+        Self::new(player_id)
+            .add_param("time".to_string())
+            .add_param(format!("{:+}", seconds))
+    }
+
+    pub fn mode(player_id: String) -> (Self, String) {
+        Self::new(player_id).question("mode".to_string())
+    }
+
+    /// The cover of the current track is identified by the `coverid` field of the first item of
+    /// the playlist loop
+    pub fn cover_id(player_id: String) -> (Self, String) {
+        (
+            Self::new(player_id)
+                .add_param("status".to_string())
+                .add_param("-".to_string())
+                .add_param("1".to_string())
+                .add_param("tags:c".to_string()),
+            "playlist_loop".to_string(),
+        )
+    }
+
+    /// The genre, the track number and the duration of the current track
+    pub fn track_details(player_id: String) -> (Self, String) {
+        (
+            Self::new(player_id)
+                .add_param("status".to_string())
+                .add_param("-".to_string())
+                .add_param("1".to_string())
+                .add_param("tags:gtd".to_string()),
+            "playlist_loop".to_string(),
+        )
+    }
+
+    /// The technical details of the current track: its type, bitrate, sample rate and sample size
+    pub fn stream_info(player_id: String) -> (Self, String) {
+        (
+            Self::new(player_id)
+                .add_param("status".to_string())
+                .add_param("-".to_string())
+                .add_param("1".to_string())
+                .add_param("tags:orTI".to_string()),
+            "playlist_loop".to_string(),
+        )
+    }
+
+    /// A page of the playlist of the player with the artist, the URL and the duration of the
+    /// tracks. The total number of tracks comes in the `playlist_tracks` field.
+    pub fn queue(player_id: String, start: u64, count: u64) -> (Self, String) {
+        (
+            Self::new(player_id)
+                .add_param("status".to_string())
+                .add_param(start.to_string())
+                .add_param(count.to_string())
+                .add_param("tags:adu".to_string()),
+            "playlist_loop".to_string(),
+        )
     }
 
-    pub fn title(name: String) -> (Self, String) {
-        Self::new(name).question("title".to_string())
+    /// A page of the playlist of the player along with the durations of the tracks
+    pub fn durations(player_id: String, start: u64, count: u64) -> (Self, String) {
+        (
+            Self::new(player_id)
+                .add_param("status".to_string())
+                .add_param(start.to_string())
+                .add_param(count.to_string())
+                .add_param("tags:d".to_string()),
+            "playlist_loop".to_string(),
+        )
     }
 
-    pub fn album(name: String) -> (Self, String) {
-        Self::new(name).question("album".to_string())
+    /// Set while the player fills its buffer before playing, for example when a stream starts
+    pub fn waiting_to_play(player_id: String) -> (Self, String) {
+        (
+            Self::new(player_id)
+                .add_param("status".to_string())
+                .add_param("-".to_string())
+                .add_param("1".to_string()),
+            "waitingToPlay".to_string(),
+        )
     }
 
-    pub fn mode(name: String) -> (Self, String) {
-        Self::new(name).question("mode".to_string())
+    pub fn mixer_volume(player_id: String) -> (Self, String) {
+        Self::new(player_id)
+            .add_param("mixer".to_string())
+            .question("volume".to_string())
     }
 
-    fn playlist(name: String) -> Self {
-        Self::new(name).add_param("playlist".to_string())
+    pub fn set_mixer_volume(player_id: String, volume: u64) -> Self {
+        Self::new(player_id)
+            .add_param("mixer".to_string())
+            .add_param("volume".to_string())
+            .add_param(volume.to_string())
     }
 
-    pub fn shuffle(name: String) -> (Self, String) {
-        Self::playlist(name).question("shuffle".to_string())
+    /// Change the volume by the given amount, down when negative
+    pub fn change_mixer_volume(player_id: String, change: i64) -> Self {
+        Self::new(player_id)
+            .add_param("mixer".to_string())
+            .add_param("volume".to_string())
+            .add_param(format!("{:+}", change))
     }
 
-    pub fn index(name: String) -> (Self, String) {
-        Self::playlist(name).question("index".to_string())
+    pub fn mixer_muting(player_id: String, muted: bool) -> Self {
+        Self::new(player_id)
+            .add_param("mixer".to_string())
+            .add_param("muting".to_string())
+            .add_param(if muted { "1" } else { "0" }.to_string())
     }
 
-    pub fn track_count(name: String) -> (Self, String) {
-        Self::playlist(name).question("tracks".to_string())
+    pub fn mixer_tone(player_id: String, tone: Tone) -> (Self, String) {
+        Self::new(player_id)
+            .add_param("mixer".to_string())
+            .question(tone.param().to_string())
     }
 
-    pub fn play(name: String) -> Self {
-        Self::new(name).add_param("play".to_string())
+    pub fn set_mixer_tone(player_id: String, tone: Tone, value: i64) -> Self {
+        Self::new(player_id)
+            .add_param("mixer".to_string())
+            .add_param(tone.param().to_string())
+            .add_param(value.to_string())
     }
 
-    pub fn stop(name: String) -> Self {
-        Self::new(name).add_param("stop".to_string())
+    fn playlist(player_id: String) -> Self {
+        Self::new(player_id).add_param("playlist".to_string())
     }
 
-    pub fn pause(name: String) -> Self {
-        Self::new(name)
+    /// The name of the saved playlist loaded on the player, if any
+    pub fn playlist_name(player_id: String) -> (Self, String) {
+        Self::playlist(player_id).question("name".to_string())
+    }
+
+    pub fn shuffle(player_id: String) -> (Self, String) {
+        Self::playlist(player_id).question("shuffle".to_string())
+    }
+
+    pub fn repeat(player_id: String) -> (Self, String) {
+        Self::playlist(player_id).question("repeat".to_string())
+    }
+
+    pub fn set_shuffle(player_id: String, shuffle: Shuffle) -> Self {
+        Self::playlist(player_id)
+            .add_param("shuffle".to_string())
+            .add_param(shuffle.param().to_string())
+    }
+
+    pub fn set_repeat(player_id: String, repeat: Repeat) -> Self {
+        Self::playlist(player_id)
+            .add_param("repeat".to_string())
+            .add_param(repeat.param().to_string())
+    }
+
+    pub fn index(player_id: String) -> (Self, String) {
+        Self::playlist(player_id).question("index".to_string())
+    }
+
+    pub fn track_count(player_id: String) -> (Self, String) {
+        Self::playlist(player_id).question("tracks".to_string())
+    }
+
+    pub fn play(player_id: String) -> Self {
+        Self::new(player_id).add_param("play".to_string())
+    }
+
+    pub fn power(player_id: String, on: bool) -> Self {
+        Self::new(player_id)
+            .add_param("power".to_string())
+            .add_param(if on { "1" } else { "0" }.to_string())
+    }
+
+    pub fn set_name(player_id: String, new_name: String) -> Self {
+        Self::new(player_id)
+            .add_param("name".to_string())
+            .add_param(new_name)
+    }
+
+    pub fn stop(player_id: String) -> Self {
+        Self::new(player_id).add_param("stop".to_string())
+    }
+
+    pub fn pause(player_id: String) -> Self {
+        Self::new(player_id)
             .add_param("pause".to_string())
             .add_param("1".to_string())
     }
 
-    pub fn play_pause(name: String) -> Self {
-        Self::new(name).add_param("pause".to_string())
+    pub fn play_pause(player_id: String) -> Self {
+        Self::new(player_id).add_param("pause".to_string())
     }
 
-    pub fn previous(name: String) -> Self {
-        Self::playlist(name)
+    pub fn previous(player_id: String) -> Self {
+        Self::playlist(player_id)
             .add_param("index".to_string())
             .add_param("-1".to_string())
     }
 
-    pub fn next(name: String) -> Self {
-        Self::playlist(name)
+    pub fn next(player_id: String) -> Self {
+        Self::playlist(player_id)
             .add_param("index".to_string())
             .add_param("+1".to_string())
     }
+
+    /// An arbitrary command, made of the given parameters
+    pub fn raw(player_id: String, params: Vec<String>) -> Self {
+        params
+            .into_iter()
+            .fold(Self::new(player_id), Self::add_param)
+    }
 }
@@ -0,0 +1,61 @@
+//! The version of the server. LMS has been renamed to Lyrion Music Server from version 9.
+use anyhow::{anyhow, Error};
+use std::{fmt, str::FromStr};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ServerVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl ServerVersion {
+    /// The oldest version the requests have been checked against
+    pub const OLDEST_SUPPORTED: ServerVersion = ServerVersion {
+        major: 7,
+        minor: 9,
+        patch: 0,
+    };
+
+    pub fn product(self) -> &'static str {
+        if self.major >= 9 {
+            "Lyrion Music Server"
+        } else {
+            "Logitech Media Server"
+        }
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for ServerVersion {
+    type Err = Error;
+
+    /// For example `8.3.1` or `9.0.0`, the missing components are 0
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut components = s.trim().splitn(3, '.').map(|component| {
+            // ignore the suffixes such as `9.0.0-dev`
+            let digits = component
+                .split(|c: char| !c.is_ascii_digit())
+                .next()
+                .unwrap_or_default();
+            digits
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Invalid server version: {}", s))
+        });
+        let major = components
+            .next()
+            .ok_or_else(|| anyhow!("Invalid server version: {}", s))??;
+        let minor = components.next().transpose()?.unwrap_or(0);
+        let patch = components.next().transpose()?.unwrap_or(0);
+        Ok(Self {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
@@ -1,29 +1,221 @@
-use anyhow::{anyhow, bail, Ok, Result};
-use clap::{command, Parser};
+use anyhow::{anyhow, bail, Context, Ok, Result};
+use art::ArtCache;
+use backend::Backend;
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use config::{Config, Hooks, Profile};
+use custom::{CustomProxy, Requests};
 use discover::discover;
-use lms::LmsClient;
-use log::{debug, info};
-use mpris::start_dbus_server;
-use std::time::Duration;
+use failover::{ServerEntry, ServerStrategy};
+use futures_util::{future::select_ok, FutureExt};
+use ip_family::IpFamily;
+use lms::{
+    capability::Capability,
+    events::{self, Event},
+    flavor::ServerFlavor,
+    version::ServerVersion,
+    Credentials, LmsClient, Mode, Password, Player, Timeouts,
+};
+use log::{debug, info, warn, LevelFilter};
+use mpris::{bus_name, Settings, DBUS_LOG_TARGET};
+use on_exit::OnExit;
+use player_match::{NameMatch, Wanted};
+use player_process::PlayerProcess;
+use preferences::Preferences;
+use queue_time::QueueTime;
+use resources::{IoClass, Resources};
+use session_bus::Server;
+use state::{poll, Activity, MetadataProfile, PlayerState, PollSettings};
+use std::{
+    future::{pending, Future},
+    io::{self, Write},
+    process::ExitStatus,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
     pin,
-    process::{Child, Command},
+    process::Command,
     select,
+    signal::unix::{signal, SignalKind},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, watch,
+    },
+    task::JoinSet,
     time::{sleep, timeout},
 };
+use volume::{VolumeChange, VolumeCurve};
+use zbus::fdo::RequestNameFlags;
+mod art;
+mod backend;
+mod changes;
+mod check;
+mod collate;
+mod config;
+mod custom;
+mod debug;
+mod default_sink;
+mod demo;
+mod desktop_entry;
 mod discover;
+mod failover;
+mod hooks;
+mod http;
+mod inhibit;
+mod ip_family;
 mod lms;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod mpris;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod notify;
+mod now_playing;
+mod on_exit;
+mod outputs;
+mod playback_errors;
+mod player_match;
+mod player_process;
+mod playlist_file;
+mod playlists;
+mod power_save;
+mod preferences;
+mod queue_time;
+mod resources;
+#[cfg(feature = "secret-service")]
+mod secret;
+mod select;
+mod server_choice;
+mod server_events;
+mod server_info;
+mod session_bus;
+mod state;
+mod status;
+mod stop_after;
+mod volume;
+
+/// Polling more often would only load the server, in milliseconds
+const MIN_POLL_INTERVAL: u64 = 100;
+
+/// The poll intervals of `--lite`, in milliseconds
+const LITE_POLL_INTERVAL: u64 = 5000;
+const LITE_IDLE_POLL_INTERVAL: u64 = 60000;
 
-#[derive(Debug, Parser)]
+/// While waiting for the player with the notifications of the server, the players are looked up
+/// again after this delay without any registration
+const REGISTRATION_FALLBACK: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Options {
+    #[command(subcommand)]
+    action: Option<Action>,
+    #[arg(
+        short,
+        long,
+        help = "Configuration file, $XDG_CONFIG_HOME/mprisqueeze/config.toml by default"
+    )]
+    config: Option<std::path::PathBuf>,
+    #[arg(long, help = "Profile of the configuration file to use")]
+    profile: Option<String>,
     #[arg(short = 'H', long, help = "LMS hostname")]
     hostname: Option<String>,
     #[arg(short = 'P', long, help = "LMS port", default_value_t = 9000)]
     port: u16,
+    #[arg(
+        long = "server",
+        value_name = "HOST[:PORT]|discover",
+        conflicts_with = "hostname",
+        help = "LMS server, can be repeated to give several servers in order of preference"
+    )]
+    servers: Vec<ServerEntry>,
+    #[arg(
+        long,
+        value_name = "STRATEGY",
+        conflicts_with = "servers",
+        help = "How the server given with --hostname and discovery are tried: prefer-config, \
+                prefer-discovery or race. Only the given server is used by default"
+    )]
+    server_strategy: Option<ServerStrategy>,
+    #[arg(
+        long,
+        default_value_t = 30,
+        help = "Time in seconds the server can be unreachable before failing over to the next one \
+            given with --server"
+    )]
+    failover_after: u64,
+    #[arg(
+        long,
+        value_name = "FAILURES",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Keep running when the server stops answering: after the given number of failed \
+                requests in a row, the requests are suspended and the last known state is served \
+                until the server is back"
+    )]
+    circuit_breaker: Option<u32>,
+    #[arg(
+        long,
+        conflicts_with_all = ["hostname", "servers"],
+        help = "Run against an embedded fake server with a small library, without starting the player"
+    )]
+    demo: bool,
+    #[arg(
+        long,
+        conflicts_with = "ipv6",
+        help = "Reach the server over IPv4 only, for the discovery and the requests"
+    )]
+    ipv4: bool,
+    #[arg(
+        long,
+        help = "Reach the server over IPv6 only, for the discovery and the requests"
+    )]
+    ipv6: bool,
+    #[arg(short, long, help = "Username for LMS", requires = "password_source")]
+    username: Option<String>,
+    #[arg(
+        long,
+        help = "Password for LMS",
+        group = "password_source",
+        requires = "username"
+    )]
+    password: Option<Password>,
+    #[arg(
+        long,
+        help = "File containing the password for LMS",
+        group = "password_source",
+        requires = "username"
+    )]
+    password_file: Option<std::path::PathBuf>,
+    #[cfg(feature = "secret-service")]
+    #[arg(
+        long,
+        help = "Look up the password for LMS in the Secret Service",
+        group = "password_source",
+        requires = "username"
+    )]
+    password_from_secret_service: bool,
     #[arg(short, long, default_value = "SqueezeLite", help = "Player name")]
     player_name: String,
+    #[arg(
+        long,
+        help = "MAC address of the player, to find it by its id rather than by its name"
+    )]
+    player_mac: Option<String>,
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "exact",
+        help = "How the player name is matched against the ones known to the server: exact, \
+                ignore-case or prefix, which ignores the case as well"
+    )]
+    player_match: NameMatch,
+    #[arg(
+        long,
+        conflicts_with_all = ["player_mac", "demo"],
+        help = "Choose the player among the ones known to the server on startup. It is offered \
+                as well when no player name is given and the server knows several players."
+    )]
+    select_player: bool,
     #[arg(
         short = 't',
         long,
@@ -31,6 +223,16 @@ struct Options {
         help = "Timeout in seconds for squeezelite to be recognized by LMS"
     )]
     player_timeout: u64,
+    #[arg(
+        long,
+        help = "Append a suffix to the player name when another connected player has the same name"
+    )]
+    rename_on_conflict: bool,
+    #[arg(
+        long,
+        help = "Rename the player on the server once it is available, the bus name follows"
+    )]
+    rename_player: Option<String>,
     #[arg(
         short = 'd',
         long,
@@ -45,124 +247,1689 @@ struct Options {
         help = "Timeout in milliseconds for LMS to reply to the discovery message"
     )]
     discover_reply_timeout: u64,
+    #[arg(
+        long,
+        default_value = "auto",
+        help = "Kind of server: lms, music-assistant or auto. The responses of the servers other \
+                than LMS are parsed leniently."
+    )]
+    server_flavor: ServerFlavor,
+    #[arg(
+        long,
+        help = "Suffix of the bus name, the log lines and the cache directory, to run several \
+                instances side by side"
+    )]
+    instance: Option<String>,
+    #[arg(long, help = "Let another instance take the bus name over")]
+    allow_replacement: bool,
+    #[arg(
+        long,
+        help = "Don't take the bus name over from an instance started with --allow-replacement"
+    )]
+    no_replace: bool,
+    #[arg(
+        long,
+        help = "Wait for the bus name to be released instead of failing when it is taken"
+    )]
+    queue: bool,
+    #[arg(
+        long,
+        help = "Subscribe to the notifications of the server on its CLI port"
+    )]
+    subscribe: bool,
+    #[arg(long, default_value_t = 9090, help = "CLI port of the server")]
+    cli_port: u16,
+    #[arg(
+        long,
+        requires = "subscribe",
+        help = "Show the events of the server as desktop notifications"
+    )]
+    notify_server_events: bool,
+    #[arg(
+        long,
+        requires = "subscribe",
+        help = "Show the tracks the server can't play as desktop notifications"
+    )]
+    notify_playback_errors: bool,
+    #[arg(
+        long,
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(MIN_POLL_INTERVAL..),
+        help = "Interval in milliseconds between two polls of LMS while playing or while a client \
+                is active, at least 100"
+    )]
+    poll_interval: u64,
+    #[arg(
+        long,
+        default_value_t = 10000,
+        value_parser = clap::value_parser!(u64).range(MIN_POLL_INTERVAL..),
+        help = "Interval in milliseconds between two polls of LMS otherwise, at least 100"
+    )]
+    idle_poll_interval: u64,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of queries of a poll sent at once, one after the other by default"
+    )]
+    poll_concurrency: u64,
+    #[arg(
+        long,
+        help = "Save bandwidth for a remote server: poll every 5 seconds while playing and every \
+                minute otherwise, unless the intervals are given, and leave out the covers and \
+                the albums unless --metadata is given"
+    )]
+    lite: bool,
+    #[arg(
+        long,
+        value_name = "PROFILE",
+        help = "Metadata fetched about the current track: minimal for the artist and the title, \
+                standard for the album and the cover too, full for the genre, the track number \
+                and the duration too. minimal with --lite, standard otherwise."
+    )]
+    metadata: Option<MetadataProfile>,
+    #[arg(
+        long,
+        help = "Keep the names of the library and the playlists as the server sorts and spells \
+                them, instead of sorting them by the rules of the locale"
+    )]
+    server_order: bool,
+    #[cfg(feature = "mqtt")]
+    #[arg(
+        long,
+        help = "MQTT broker hostname, enables publishing the player state"
+    )]
+    mqtt_host: Option<String>,
+    #[cfg(feature = "mqtt")]
+    #[arg(long, default_value_t = 1883, help = "MQTT broker port")]
+    mqtt_port: u16,
+    #[cfg(feature = "mqtt")]
+    #[arg(long, help = "MQTT topic prefix, mprisqueeze/{player name} by default")]
+    mqtt_topic: Option<String>,
+    #[cfg(feature = "mqtt")]
+    #[arg(
+        long,
+        help = "Control the player with the messages published on the MQTT command topic"
+    )]
+    mqtt_commands: bool,
+    #[arg(
+        long,
+        help = "Power on the player before playing, and power it off when quitting via MPRIS"
+    )]
+    power_on: bool,
+    #[arg(
+        long,
+        default_value = "linear",
+        help = "Mapping between the MPRIS and the LMS volumes: linear, logarithmic or an exponent"
+    )]
+    volume_curve: VolumeCurve,
+    #[arg(
+        long,
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(0..=100),
+        help = "Ceiling of the LMS volume, between 0 and 100, set through MPRIS"
+    )]
+    max_volume: u64,
+    #[arg(
+        long,
+        default_value = "nothing",
+        help = "What to do with the player when exiting: nothing, stop or power-off"
+    )]
+    on_exit: OnExit,
+    #[arg(
+        long,
+        default_value_t = 30,
+        help = "Default skip forward in seconds of the SkipForward method"
+    )]
+    skip_forward: u32,
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "Default skip backward in seconds of the SkipBackward method"
+    )]
+    skip_backward: u32,
+    #[arg(
+        long,
+        help = "Download the covers to the cache directory and expose them as local files, \
+            always the case with credentials"
+    )]
+    art_cache: bool,
+    #[arg(
+        long,
+        default_value_t = 50,
+        help = "Maximum size in megabytes of the cover cache"
+    )]
+    art_cache_size: u64,
+    #[arg(
+        long,
+        help = "Log every MPRIS call with its arguments, result and duration"
+    )]
+    debug_dbus: bool,
+    #[arg(
+        long,
+        help = "Restart the player when the default sink of PulseAudio or PipeWire changes"
+    )]
+    restart_on_sink_change: bool,
+    #[arg(long, help = "Prevent the system from sleeping while playing")]
+    inhibit_sleep: bool,
+    #[arg(
+        long,
+        value_name = "MINUTES",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Suspend the player process once the player has been stopped for the given number \
+                of minutes, it is continued when the playback is requested"
+    )]
+    suspend_when_stopped: Option<u64>,
+    #[arg(long, help = "File to write the current track to each time it changes")]
+    now_playing_file: Option<std::path::PathBuf>,
+    #[arg(
+        long,
+        default_value = "{artist} - {title}",
+        help = "Format of the now playing file. The strings '{artist}', '{album}' and '{title}' \
+                are replaced with the values of the current track."
+    )]
+    now_playing_format: String,
+    #[arg(
+        long,
+        help = "Address to serve the state of the player as JSON on, for example 127.0.0.1:9151"
+    )]
+    http_status: Option<std::net::SocketAddr>,
+    #[cfg(feature = "metrics")]
+    #[arg(
+        long,
+        help = "Address to expose the Prometheus metrics on, for example 127.0.0.1:9150"
+    )]
+    metrics_address: Option<std::net::SocketAddr>,
+    #[arg(
+        long,
+        help = "Run the player command with 'sh -c', for pipelines, redirections or variables"
+    )]
+    shell: bool,
+    #[arg(
+        long,
+        help = "Only warn when the player command lacks the '{name}' or '{server}' placeholder"
+    )]
+    no_placeholder_check: bool,
+    #[arg(
+        long,
+        help = "Preset of the player command: pulse, alsa, pipewire or portaudio. A player command \
+                given after '--' takes precedence."
+    )]
+    backend: Option<Backend>,
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i8).range(-20..=19),
+        help = "Niceness of the player, from -20 to 19"
+    )]
+    nice: Option<i8>,
+    #[arg(
+        long,
+        value_name = "CLASS",
+        help = "I/O scheduling class of the player: realtime, best-effort or idle"
+    )]
+    ionice: Option<IoClass>,
+    #[arg(
+        long,
+        value_name = "CPUS",
+        help = "CPUs the player runs on, as a list such as 0,2-3"
+    )]
+    cpu_affinity: Option<String>,
+    #[arg(
+        long,
+        help = "Systemd slice of the user manager to run the player in, such as background.slice"
+    )]
+    slice: Option<String>,
     #[arg(
         last = true,
         default_values_t = vec!["squeezelite-pulse".to_string(), "-n".to_string(),
             "{name}".to_string(), "-s".to_string(), "{server}".to_string()],
-        help = "Player command and arguments. The string '{name}' will be replaced with the player\
-                name, '{server}' with the LMS server name."
+        help = "Player command and arguments. The string '{name}' will be replaced with the player \
+                name, '{server}' with the LMS server name, '{server_port}' with its port, \
+                '{server_host_port}' with both as in 192.168.1.10:9000, and '{server_url}' with \
+                its URL as in http://192.168.1.10:9000."
     )]
     player_command: Vec<String>,
 }
 
-/// Wait for maximum `timeout` seconds for the player to be available
-async fn wait_for_player(client: &LmsClient, player_name: &str, timeout: u64) -> Result<()> {
-    info!("Waiting for player {} to be available", player_name);
+#[derive(Clone, Debug, Subcommand)]
+enum Action {
+    /// Check the environment: the player command, the server, the credentials and the session bus
+    Check {
+        #[arg(
+            last = true,
+            help = "Player command and arguments to check instead of the ones of the main command"
+        )]
+        player_command: Vec<String>,
+    },
+    /// List the audio outputs of the player, to be given to its `-o` argument
+    Outputs {
+        #[arg(
+            long,
+            default_value = "-l",
+            help = "Flag of the player listing its outputs"
+        )]
+        list_flag: String,
+        #[arg(
+            last = true,
+            help = "Player command to use instead of the one of the main command"
+        )]
+        player_command: Vec<String>,
+    },
+    /// Write the desktop entry of mprisqueeze to the applications directory of the user, for the
+    /// desktop shells to show its name and icon
+    InstallDesktopEntry {
+        #[arg(
+            long,
+            default_value = "multimedia-audio-player",
+            help = "Name of the icon in the theme, or path of an image"
+        )]
+        icon: String,
+    },
+    /// Ask the running instance to stop the player at the end of the current track
+    StopAfterCurrent {
+        #[arg(long, help = "Cancel a previous request instead")]
+        cancel: bool,
+    },
+    /// Control the player on the server directly, without a running instance
+    Control {
+        #[command(subcommand)]
+        action: ControlAction,
+    },
+    /// Work with the playlist of the player on the server
+    Queue {
+        #[command(subcommand)]
+        action: QueueAction,
+    },
+    /// Print a line on stdout on each change of the track, the playback status or the volume
+    Watch {
+        #[arg(long, help = "Print the whole state of the player as JSON instead")]
+        json: bool,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum ControlAction {
+    /// Set the volume, or change it by a signed amount
+    Volume {
+        #[arg(
+            allow_hyphen_values = true,
+            help = "The volume between 0 and 100, or +N or -N to change it"
+        )]
+        volume: VolumeChange,
+    },
+    /// Mute the player
+    Mute,
+    /// Unmute the player
+    Unmute,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum QueueAction {
+    /// Export the playlist to an M3U file, with the title and the URL of each track
+    Export {
+        #[arg(help = "The playlist file to write, such as queue.m3u or queue.m3u8")]
+        file: std::path::PathBuf,
+    },
+    /// Add the entries of an M3U or a PLS file to the playlist
+    Import {
+        #[arg(help = "The playlist file to read, its paths have to exist on the server")]
+        file: std::path::PathBuf,
+        #[arg(
+            long,
+            help = "Replace the playlist and play instead of appending to it"
+        )]
+        replace: bool,
+    },
+    /// Print the duration of the playlist, the time left and the time it ends at
+    Status,
+}
+
+impl Options {
+    /// Take the settings saved in a previous run, unless they are given on the command line. The
+    /// preferences then hold the settings in effect.
+    fn apply_preferences(&mut self, preferences: &Preferences, matches: &ArgMatches) {
+        let from_command_line =
+            |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        preferences.resolve(|values| {
+            match values.max_volume {
+                Some(max_volume) if !from_command_line("max_volume") => {
+                    self.max_volume = max_volume
+                }
+                _ => values.max_volume = Some(self.max_volume),
+            }
+            match values.notify_server_events {
+                Some(notify) if !from_command_line("notify_server_events") => {
+                    self.notify_server_events = notify
+                }
+                _ => values.notify_server_events = Some(self.notify_server_events),
+            }
+            match values.notify_playback_errors {
+                Some(notify) if !from_command_line("notify_playback_errors") => {
+                    self.notify_playback_errors = notify
+                }
+                _ => values.notify_playback_errors = Some(self.notify_playback_errors),
+            }
+        });
+    }
+
+    /// Take the options of the profile, unless they are given on the command line
+    fn apply_profile(&mut self, profile: Profile, matches: &ArgMatches) {
+        let from_command_line =
+            |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if let Some(hostname) = profile.hostname.filter(|_| !from_command_line("hostname")) {
+            self.hostname = Some(hostname);
+        }
+        if let Some(port) = profile.port.filter(|_| !from_command_line("port")) {
+            self.port = port;
+        }
+        if let Some(servers) = profile
+            .servers
+            .filter(|_| !from_command_line("servers") && !from_command_line("hostname"))
+        {
+            self.servers = servers;
+        }
+        if let Some(strategy) = profile
+            .server_strategy
+            .filter(|_| !from_command_line("server_strategy"))
+        {
+            self.server_strategy = Some(strategy);
+        }
+        if let Some(player_name) = profile
+            .player_name
+            .filter(|_| !from_command_line("player_name"))
+        {
+            self.player_name = player_name;
+        }
+        if let Some(player_mac) = profile
+            .player_mac
+            .filter(|_| !from_command_line("player_mac"))
+        {
+            self.player_mac = Some(player_mac);
+        }
+        if let Some(player_command) = profile
+            .player_command
+            .filter(|_| !from_command_line("player_command"))
+        {
+            self.player_command = player_command;
+        }
+    }
+
+    /// The player command and its arguments, which must contain the placeholders unless the check is
+    /// disabled. With `--shell`, the command line is run by `sh -c`.
+    fn split_player_command(&self) -> Result<(String, Vec<String>)> {
+        let (player_command, player_args) = match self.player_command[..] {
+            [] => bail!("No player command given"),
+            _ if self.shell => (
+                "sh".to_string(),
+                vec!["-c".to_string(), self.player_command.join(" ")],
+            ),
+            [ref player_command, ref player_args @ ..] => {
+                (player_command.clone(), player_args.to_vec())
+            }
+        };
+
+        let placeholders: [(&[&str], &str); 2] = [
+            (&["{name}"], "player name"),
+            (
+                &["{server}", "{server_host_port}", "{server_url}"],
+                "server name",
+            ),
+        ];
+        for (alternatives, value) in placeholders {
+            if player_args.iter().any(|arg| {
+                alternatives
+                    .iter()
+                    .any(|placeholder| arg.contains(placeholder))
+            }) {
+                continue;
+            }
+            let message = format!(
+                "Player args must contain the string {} to be replaced with the {}",
+                alternatives[0], value
+            );
+            if !self.no_placeholder_check {
+                bail!("{}, or use --no-placeholder-check", message);
+            }
+            warn!("{}", message);
+        }
+        Ok((player_command, player_args))
+    }
+
+    /// The servers to use in order of preference. Without `--server`, it is the one given with
+    /// `--hostname` or the one discovered on the network.
+    async fn servers(&self) -> Result<Vec<ServerEntry>> {
+        if !self.servers.is_empty() {
+            return Ok(self.servers.clone());
+        }
+        let Some(ref hostname) = self.hostname else {
+            return Ok(vec![ServerEntry::Discover]);
+        };
+        let configured = ServerEntry::Address {
+            hostname: hostname.clone(),
+            port: Some(self.port),
+        };
+        let Some(strategy) = self.server_strategy else {
+            return Ok(vec![configured]);
+        };
+        let servers = strategy.order(configured);
+        if strategy == ServerStrategy::Race {
+            return self.race(servers).await;
+        }
+        info!("Trying {} first, then {}", servers[0], servers[1]);
+        Ok(servers)
+    }
+
+    /// Resolve and probe the servers at once, the first one answering is moved to the front. The
+    /// order is kept when none answers.
+    async fn race(&self, mut servers: Vec<ServerEntry>) -> Result<Vec<ServerEntry>> {
+        let credentials = self.credentials().await?;
+        let probes = servers.iter().enumerate().map(|(index, entry)| {
+            let credentials = credentials.clone();
+            async move {
+                let (hostname, port, _) = self.resolve(entry).await?;
+                let answers = failover::answers(
+                    hostname.clone(),
+                    port,
+                    credentials,
+                    self.server_flavor,
+                    self.ip_family(),
+                )
+                .await;
+                if !answers {
+                    bail!("The server {}:{} doesn't answer", hostname, port);
+                }
+                Ok(index)
+            }
+            .boxed()
+        });
+        // the probes still running are dropped
+        let first = select_ok(probes).await.map(|(index, _)| index);
+        match first {
+            Result::Ok(index) => {
+                match servers[index] {
+                    ServerEntry::Discover => {
+                        info!("The server found on the network answered first")
+                    }
+                    ref entry => info!("The server {} answered first", entry),
+                }
+                let winner = servers.remove(index);
+                servers.insert(0, winner);
+            }
+            Err(error) => warn!("None of the servers answered the race: {:#}", error),
+        }
+        Ok(servers)
+    }
+
+    /// The player to look for on the server
+    fn wanted(&self) -> Wanted<'_> {
+        Wanted {
+            player_name: &self.player_name,
+            player_mac: self.player_mac.as_deref(),
+            name_match: self.player_match,
+        }
+    }
+
+    /// Get the hostname and port of the preferred server
+    async fn server(&self) -> Result<(String, u16, Option<String>)> {
+        self.resolve(&self.servers().await?[0]).await
+    }
+
+    /// Get the hostname and port either from the entry or by discovering the server on the
+    /// network. The version of the server comes along when the discovery reply holds it.
+    async fn resolve(&self, entry: &ServerEntry) -> Result<(String, u16, Option<String>)> {
+        match entry {
+            ServerEntry::Address { hostname, port } => {
+                Ok((hostname.clone(), port.unwrap_or(self.port), None))
+            }
+            ServerEntry::Discover => {
+                let mut replies = timeout(
+                    Duration::from_secs(self.discover_timeout),
+                    discover(
+                        Duration::from_millis(self.discover_reply_timeout),
+                        self.ip_family().unwrap_or(IpFamily::V4),
+                    ),
+                )
+                .await??;
+                let reply = if replies.len() > 1 {
+                    server_choice::choose(
+                        replies,
+                        self.wanted(),
+                        self.credentials().await?,
+                        self.server_flavor,
+                        self.ip_family(),
+                    )
+                    .await
+                } else {
+                    replies.remove(0)
+                };
+                println!("Discovered LMS at {}:{}", reply.hostname, reply.port);
+                Ok((reply.hostname, reply.port, reply.version))
+            }
+        }
+    }
+
+    async fn credentials(&self) -> Result<Option<Credentials>> {
+        let Some(ref username) = self.username else {
+            return Ok(None);
+        };
+        let password = match (&self.password, &self.password_file) {
+            (Some(password), _) => password.clone(),
+            (_, Some(path)) => {
+                let content = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow!("Unable to read the password file {:?}: {}", path, e))?;
+                Password(content.trim_end_matches(['\r', '\n']).to_string())
+            }
+            #[cfg(feature = "secret-service")]
+            _ if self.password_from_secret_service => secret::lookup_password(username).await?,
+            _ => bail!("No password given for {}", username),
+        };
+        Ok(Some(Credentials {
+            username: username.clone(),
+            password,
+        }))
+    }
+
+    /// The IP family the server is reached with, any by default
+    fn ip_family(&self) -> Option<IpFamily> {
+        match (self.ipv4, self.ipv6) {
+            (true, _) => Some(IpFamily::V4),
+            (_, true) => Some(IpFamily::V6),
+            _ => None,
+        }
+    }
+
+    /// The intervals and the metadata of the polls of the state of the player
+    fn poll_settings(&self) -> PollSettings {
+        PollSettings {
+            active: Duration::from_millis(self.poll_interval),
+            idle: Duration::from_millis(self.idle_poll_interval),
+            metadata: self.metadata(),
+            concurrency: self.poll_concurrency as usize,
+        }
+    }
+
+    /// The metadata fetched on each poll, the minimum with `--lite` unless given
+    fn metadata(&self) -> MetadataProfile {
+        self.metadata.unwrap_or(if self.lite {
+            MetadataProfile::Minimal
+        } else {
+            MetadataProfile::Standard
+        })
+    }
+
+    /// The resource controls of the player process
+    fn resources(&self) -> Resources {
+        Resources {
+            nice: self.nice,
+            io_class: self.ionice,
+            cpus: self.cpu_affinity.clone(),
+            slice: self.slice.clone(),
+        }
+    }
+
+    /// The flags used to request the bus name
+    fn name_flags(&self) -> Vec<RequestNameFlags> {
+        [
+            (self.allow_replacement, RequestNameFlags::AllowReplacement),
+            (!self.no_replace, RequestNameFlags::ReplaceExisting),
+            (!self.queue, RequestNameFlags::DoNotQueue),
+        ]
+        .iter()
+        .filter_map(|&(enabled, flag)| enabled.then_some(flag))
+        .collect()
+    }
+
+    #[cfg(feature = "mqtt")]
+    fn mqtt(&self) -> Option<mqtt::Options> {
+        self.mqtt_host.as_ref().map(|hostname| mqtt::Options {
+            hostname: hostname.clone(),
+            port: self.mqtt_port,
+            topic: self.mqtt_topic.clone(),
+            commands: self.mqtt_commands,
+        })
+    }
+}
+
+/// The players known by the server
+async fn players(client: &LmsClient) -> Result<Vec<Player>> {
+    if client.get_player_count().await? == 0 {
+        return Ok(vec![]);
+    }
+    client.get_players().await
+}
+
+/// Let the user choose the player among the ones known to the preferred server. Unless asked
+/// explicitly, the choice is only offered when there are several players.
+async fn select_player(
+    options: &mut Options,
+    credentials: Option<Credentials>,
+    timeouts: Timeouts,
+) -> Result<()> {
+    if !select::is_interactive() {
+        bail!("Choosing the player with --select-player requires a terminal");
+    }
+    let (hostname, port, _) = options.server().await?;
+    let (client, mut recv) = LmsClient::new(
+        hostname,
+        port,
+        credentials,
+        options.server_flavor,
+        timeouts,
+        options.ip_family(),
+        None,
+    );
+    // the actual cause of a failure is sent to the error channel
+    let players = players(&client)
+        .await
+        .map_err(|error| recv.try_recv().unwrap_or(error))?;
+    // the bridge can't take the name of a connected player, see `resolve_name_conflict`
+    let bridge = options.action.is_none();
+    let selectable = players
+        .iter()
+        .filter(|player| !bridge || !player.connected)
+        .count();
+    if selectable < 2 && !options.select_player {
+        return Ok(());
+    }
+    if selectable == 0 {
+        warn!(
+            "No player available on the server, keeping the name {}",
+            options.player_name
+        );
+        return Ok(());
+    }
+    if let Some(name) = select::choose(players, bridge, options.player_name.clone()).await? {
+        options.player_name = name;
+    }
+    Ok(())
+}
+
+/// Make sure that no other connected player has the same name, renaming ours if allowed. A player
+/// found by its MAC address can't be mistaken for another one.
+async fn resolve_name_conflict(client: &LmsClient, options: &mut Options) -> Result<()> {
+    if options.player_mac.is_some() {
+        return Ok(());
+    }
+    let players = players(client).await?;
+    // the player started would be mistaken for the one already connected
+    let wanted = options.wanted();
+    if !players
+        .iter()
+        .any(|player| player.connected && wanted.matches(player))
+    {
+        return Ok(());
+    }
+    if !options.rename_on_conflict {
+        bail!(
+            "Another player named {} is connected to the server, choose another name or use \
+             --rename-on-conflict",
+            options.player_name
+        );
+    }
+    let name = (2..)
+        .map(|suffix| format!("{}-{}", options.player_name, suffix))
+        .find(|name| !players.iter().any(|player| &player.name == name))
+        .unwrap();
+    warn!(
+        "Another player named {} is connected to the server, using the name {}",
+        options.player_name, name
+    );
+    options.player_name = name;
+    Ok(())
+}
+
+/// Run a `control` subcommand against the preferred server
+async fn control(
+    options: &Options,
+    credentials: Option<Credentials>,
+    timeouts: Timeouts,
+    action: &ControlAction,
+) -> Result<()> {
+    let (hostname, port, _) = options.server().await?;
+    let (client, mut recv) = LmsClient::new(
+        hostname,
+        port,
+        credentials,
+        options.server_flavor,
+        timeouts,
+        options.ip_family(),
+        None,
+    );
+    // the actual cause of a failure is sent to the error channel
+    async {
+        let player =
+            wait_for_player(&client, options.wanted(), options.player_timeout, None).await?;
+        match action {
+            ControlAction::Volume {
+                volume: VolumeChange::Set(volume),
+            } => client.set_volume(player.id, *volume).await,
+            ControlAction::Volume {
+                volume: VolumeChange::Relative(change),
+            } => client.change_volume(player.id, *change).await,
+            ControlAction::Mute => client.set_muting(player.id, true).await,
+            ControlAction::Unmute => client.set_muting(player.id, false).await,
+        }
+    }
+    .await
+    .map_err(|error| recv.try_recv().unwrap_or(error))
+}
+
+/// Run a `queue` subcommand against the preferred server
+async fn queue(
+    options: &Options,
+    credentials: Option<Credentials>,
+    timeouts: Timeouts,
+    action: &QueueAction,
+) -> Result<()> {
+    let (hostname, port, _) = options.server().await?;
+    let (client, mut recv) = LmsClient::new(
+        hostname,
+        port,
+        credentials,
+        options.server_flavor,
+        timeouts,
+        options.ip_family(),
+        None,
+    );
+    // the actual cause of a failure is sent to the error channel
+    async {
+        let player =
+            wait_for_player(&client, options.wanted(), options.player_timeout, None).await?;
+        match action {
+            QueueAction::Export { file } => {
+                let count = playlist_file::export(&client, &player.id, file).await?;
+                println!("Exported {} tracks to {}", count, file.display());
+            }
+            QueueAction::Import { file, replace } => {
+                let count = playlist_file::import(&client, &player.id, file, *replace).await?;
+                println!("Imported {} tracks from {}", count, file.display());
+            }
+            QueueAction::Status => {
+                let queue_time = QueueTime::fetch(&client, &player.id).await?;
+                if queue_time.length == 0 {
+                    println!("The playlist is empty");
+                } else {
+                    println!("Track {} of {}", queue_time.position, queue_time.length);
+                    println!("Total: {}", queue_time::format_duration(queue_time.total));
+                    println!(
+                        "Remaining: {}",
+                        queue_time::format_duration(queue_time.remaining)
+                    );
+                    println!("Ends at: {}", queue_time::format_utc(queue_time.end()));
+                }
+            }
+        }
+        Ok(())
+    }
+    .await
+    .map_err(|error| recv.try_recv().unwrap_or(error))
+}
+
+/// Run the `watch` subcommand against the preferred server: the state of the player is polled,
+/// without a session bus, and its changes are printed until an error occurs
+async fn watch_player(
+    options: &Options,
+    credentials: Option<Credentials>,
+    timeouts: Timeouts,
+    json: bool,
+) -> Result<()> {
+    let (hostname, port, _) = options.server().await?;
+    let (client, mut recv) = LmsClient::new(
+        hostname.clone(),
+        port,
+        credentials.clone(),
+        options.server_flavor,
+        timeouts,
+        options.ip_family(),
+        options.circuit_breaker,
+    );
+    let client = Arc::new(client);
+    // the actual cause of a failure is sent to the error channel
+    async {
+        client.detect_capabilities().await;
+        let subscribe = subscribes(options, &client);
+        let (events_sender, _) = broadcast::channel(16);
+        let registrations = subscribe.then(|| events_sender.subscribe());
+        let mut services = JoinSet::<Result<()>>::new();
+        if subscribe {
+            // the server is not followed when it moves, unlike when running the player
+            let (_, hostname_receiver) = watch::channel(hostname);
+            services.spawn(events::subscribe(
+                hostname_receiver,
+                options.cli_port,
+                credentials,
+                events_sender.clone(),
+            ));
+        }
+        let player = wait_for_player(
+            &client,
+            options.wanted(),
+            options.player_timeout,
+            registrations,
+        )
+        .await?;
+        let (state_sender, state_receiver) = watch::channel(PlayerState::default());
+        let poller = poll(
+            client.clone(),
+            player.id,
+            None,
+            options.poll_settings(),
+            Arc::new(Activity::default()),
+            state_sender,
+            subscribe.then(|| events_sender.subscribe()),
+        );
+        services.spawn(changes::print(state_receiver, json));
+        select! {
+            result = poller => result,
+            Some(result) = services.join_next() => result?,
+        }
+    }
+    .await
+    .map_err(|error| recv.try_recv().unwrap_or(error))
+}
+
+/// Wait for the player to register on the server, looking it up by its MAC address when given, by
+/// its name otherwise, see `--player-match`. A disconnected player with the same name may still be
+/// known by the server, the connected one is preferred. With the notifications of the server, the
+/// players are looked up again when one registers, otherwise they are polled.
+async fn wait_for_player(
+    client: &LmsClient,
+    wanted: Wanted<'_>,
+    timeout: u64,
+    mut events: Option<broadcast::Receiver<Event>>,
+) -> Result<Player> {
+    info!(
+        "Waiting for player {} to be available",
+        wanted.player_mac.unwrap_or(wanted.player_name)
+    );
     let sleep = sleep(Duration::from_secs(timeout));
     pin!(sleep);
     loop {
         select! {
             _ = &mut sleep => bail!("Player not available after {} seconds", timeout),
-            count = client.get_player_count() =>
-            {
-                if let Result::Ok(true) = count.as_ref().map(|count| *count != 0) {
-                    let players = client.get_players().await?;
-                    if players.iter().any(|player| player.name == player_name) {
-                        info!("Player {} is available", player_name);
-                        break Ok(());
+            player = find_player(client, &wanted) => {
+                if let Some(player) = player? {
+                    info!("Player {} is available with the id {}", player.name, player.id);
+                    break Ok(player);
+                }
+            }
+        }
+        if let Some(receiver) = events.as_mut() {
+            select! {
+                _ = &mut sleep => bail!("Player not available after {} seconds", timeout),
+                closed = wait_for_registration(receiver) => {
+                    if closed {
+                        events = None;
                     }
                 }
-                count.map(|_| ())?
             }
         }
     }
 }
 
-/// Start the `squeezelite` process
-fn start_squeezelite(options: &Options, server: &String) -> Result<Child> {
-    let (player_command, player_args) = match options.player_command[..] {
-        [] => bail!("No player command given"),
-        [ref player_command, ref player_args @ ..] => Ok((player_command, player_args)),
-    }?;
+/// The player among the ones known by the server, `None` when it hasn't registered yet
+async fn find_player(client: &LmsClient, wanted: &Wanted<'_>) -> Result<Option<Player>> {
+    if client.get_player_count().await? == 0 {
+        return Ok(None);
+    }
+    Ok(wanted.find(client.get_players().await?))
+}
 
-    if !player_args.iter().any(|arg| arg.contains("{name}")) {
-        bail!("Player args must contain the string {{name}} to be replaced with the player name");
+/// Wait for a player to register or to reconnect. A missed notification may be about the player,
+/// as well as the ones sent before the connection to the server. A fallback delay covers the
+/// notifications which never come, for example when the CLI port is wrong. Return whether the
+/// channel is closed.
+async fn wait_for_registration(receiver: &mut broadcast::Receiver<Event>) -> bool {
+    let fallback = sleep(REGISTRATION_FALLBACK);
+    pin!(fallback);
+    loop {
+        let event = select! {
+            _ = &mut fallback => return false,
+            event = receiver.recv() => event,
+        };
+        match event {
+            Result::Ok(Event::Notification { player_id, params })
+                if params.first().is_some_and(|param| param == "client")
+                    && params
+                        .get(1)
+                        .is_some_and(|param| param == "new" || param == "reconnect") =>
+            {
+                debug!("Player {:?} registered", player_id);
+                return false;
+            }
+            Result::Ok(Event::Connected) | Err(RecvError::Lagged(_)) => return false,
+            Result::Ok(_) => (),
+            Err(RecvError::Closed) => return true,
+        }
     }
-    if !player_args.iter().any(|arg| arg.contains("{server}")) {
-        bail!("Player args must contain the string {{server}} to be replaced with the server name");
+}
+
+/// Reload the configuration file and the credentials on SIGHUP or when requested over D-Bus: the
+/// hooks and the timeouts of the requests are applied right away. On error, the previous settings
+/// are kept.
+async fn reload(
+    options: Options,
+    client: Arc<LmsClient>,
+    hooks: watch::Sender<Hooks>,
+    mut requests: mpsc::UnboundedReceiver<()>,
+) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    loop {
+        select! {
+            Some(()) = hangup.recv() => info!("Reloading the configuration on SIGHUP"),
+            Some(()) = requests.recv() => info!("Reloading the configuration on request"),
+            else => bail!("Unable to wait for the reload requests"),
+        }
+        match Config::load(options.config.as_deref()) {
+            Result::Ok(config) => {
+                debug!("Config: {:?}", config);
+                client.set_timeouts(config.timeouts);
+                hooks.send_replace(config.hooks);
+            }
+            Err(error) => warn!("Unable to reload the configuration: {:#}", error),
+        }
+        match options.credentials().await {
+            Result::Ok(credentials) => client.set_credentials(credentials),
+            Err(error) => warn!("Unable to reload the credentials: {:#}", error),
+        }
     }
+}
+
+/// Whether the notifications of the server are listened to: they are asked for with
+/// `--subscribe`, and the server has to send them
+fn subscribes(options: &Options, client: &LmsClient) -> bool {
+    if options.subscribe && !client.supports(Capability::Notifications) {
+        warn!(
+            "The server doesn't send notifications, its CLI plugin may be disabled: polling only"
+        );
+        return false;
+    }
+    options.subscribe
+}
+
+/// Set the shuffle mode last set over D-Bus back on the player, the server may have forgotten it
+/// or another controller changed it meanwhile
+async fn restore_shuffle(
+    client: &LmsClient,
+    player_id: &str,
+    preferences: &Preferences,
+) -> Result<()> {
+    let Some(shuffle) = preferences.values().shuffle else {
+        return Ok(());
+    };
+    if client.get_shuffle(player_id.to_string()).await? != shuffle {
+        info!("Restoring the shuffle mode {}", shuffle.name());
+        client.set_shuffle(player_id.to_string(), shuffle).await?;
+    }
+    Ok(())
+}
+
+/// Set the repeat mode last set over D-Bus back on the player, as with the shuffle mode
+async fn restore_repeat(
+    client: &LmsClient,
+    player_id: &str,
+    preferences: &Preferences,
+) -> Result<()> {
+    let Some(repeat) = preferences.values().repeat else {
+        return Ok(());
+    };
+    if client.get_repeat(player_id.to_string()).await? != repeat {
+        info!("Restoring the repeat mode {}", repeat.name());
+        client.set_repeat(player_id.to_string(), repeat).await?;
+    }
+    Ok(())
+}
+
+/// Log the server version, warning when it is older than the supported ones
+fn check_version(version: &str) {
+    match version.parse::<ServerVersion>() {
+        Result::Ok(version) if version < ServerVersion::OLDEST_SUPPORTED => warn!(
+            "{} {} is older than {}, some requests may fail",
+            version.product(),
+            version,
+            ServerVersion::OLDEST_SUPPORTED
+        ),
+        Result::Ok(version) => info!("Connected to {} {}", version.product(), version),
+        Err(error) => warn!("{}", error),
+    }
+}
+
+/// Start the `squeezelite` process
+fn start_squeezelite(options: &Options, server: &str, port: u16) -> Result<PlayerProcess> {
+    let (player_command, player_args) = options.split_player_command()?;
+    // the values are quoted when they are interpreted by the shell
+    let value = |value: &str| {
+        if options.shell {
+            format!("'{}'", value.replace('\'', "'\\''"))
+        } else {
+            value.to_string()
+        }
+    };
+    // an IPv6 address is enclosed in brackets when followed by a port
+    let host = if server.contains(':') {
+        format!("[{}]", server)
+    } else {
+        server.to_string()
+    };
+    let host_port = format!("{}:{}", host, port);
+    let url = format!("http://{}", host_port);
     let player_args_with_name = player_args
         .iter()
-        .map(|arg| arg.replace("{name}", &options.player_name))
-        .map(|arg| arg.replace("{server}", &server))
+        .map(|arg| arg.replace("{name}", &value(&options.player_name)))
+        .map(|arg| arg.replace("{server}", &value(server)))
+        .map(|arg| arg.replace("{server_port}", &value(&port.to_string())))
+        .map(|arg| arg.replace("{server_host_port}", &value(&host_port)))
+        .map(|arg| arg.replace("{server_url}", &value(&url)))
         .collect::<Vec<_>>();
 
+    let (player_command, player_args_with_name) = options
+        .resources()
+        .wrap(player_command, player_args_with_name);
+
     info!(
         "Starting player: {} {:?}",
         player_command, player_args_with_name
     );
-    Command::new(player_command)
-        .args(player_args_with_name)
-        .spawn()
+    PlayerProcess::spawn(Command::new(&player_command).args(player_args_with_name))
         .map_err(|e| anyhow!("Failed to start player command {}: {}", player_command, e))
 }
 
+/// A server found on the network may move to another address, for example when its lease changes.
+/// It is discovered again and, when it has moved, the player is restarted against the new address.
+/// The server keeps the playlist of the player, it is saved and resumed anyway in case it is lost
+/// meanwhile. Return the new address, `None` when the server hasn't moved.
+async fn follow_server(
+    options: &Options,
+    client: &LmsClient,
+    player_id: &str,
+    player_process: &mut Option<PlayerProcess>,
+    (hostname, port): (&str, u16),
+    playing: bool,
+) -> Result<Option<(String, u16)>> {
+    let (new_hostname, new_port, _) = options.resolve(&ServerEntry::Discover).await?;
+    if new_hostname == hostname && new_port == port {
+        return Ok(None);
+    }
+    info!(
+        "The server moved from {}:{} to {}:{}, restarting the player",
+        hostname, port, new_hostname, new_port
+    );
+    client.set_server(&new_hostname, new_port);
+    let name = format!("mprisqueeze-{}", player_id);
+    client
+        .save_playlist(player_id.to_string(), name.clone())
+        .await?;
+    if let Some(ref mut process) = player_process {
+        process.kill().await?;
+        *process = start_squeezelite(options, &new_hostname, new_port)?;
+    }
+    let wanted = Wanted {
+        player_mac: Some(player_id),
+        ..options.wanted()
+    };
+    wait_for_player(client, wanted, options.player_timeout, None).await?;
+    client
+        .resume_playlist(player_id.to_string(), name, playing)
+        .await?;
+    Ok(Some((new_hostname, new_port)))
+}
+
+/// Wait for the player process to exit, forever when there is none
+async fn wait_for_exit(process: &mut Option<PlayerProcess>) -> io::Result<ExitStatus> {
+    match process {
+        Some(process) => process.wait().await,
+        None => pending().await,
+    }
+}
+
+/// Run an optional service, such as the sleep inhibitor. It stops on error with a warning, the
+/// bridge keeps running without it.
+async fn optional_service(name: &str, service: impl Future<Output = Result<()>>) -> Result<()> {
+    if let Err(error) = service.await {
+        warn!("Stopping the {}: {:#}", name, error);
+    }
+    pending().await
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
-
+    debug::start_clock();
     // parse the command line options
-    let options = Options::parse();
+    let matches = Options::command().get_matches();
+    let mut options = Options::from_arg_matches(&matches)?;
+    // the intervals given explicitly take precedence
+    if options.lite {
+        if matches.value_source("poll_interval") != Some(ValueSource::CommandLine) {
+            options.poll_interval = LITE_POLL_INTERVAL;
+        }
+        if matches.value_source("idle_poll_interval") != Some(ValueSource::CommandLine) {
+            options.idle_poll_interval = LITE_IDLE_POLL_INTERVAL;
+        }
+    }
+    // the preset replaces the default player command, not the one given explicitly
+    if let Some(backend) = options.backend {
+        if matches.value_source("player_command") != Some(ValueSource::CommandLine) {
+            options.player_command = backend.player_command();
+        }
+    }
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(instance) = options.instance.clone() {
+        logger.format(move |buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                instance,
+                record.target(),
+                record.args()
+            )
+        });
+    }
+    if options.debug_dbus {
+        logger.filter_module(DBUS_LOG_TARGET, LevelFilter::Debug);
+    }
+    logger.init();
     debug!("Options: {:?}", options);
+    let config = Config::load(options.config.as_deref())?;
+    debug!("Config: {:?}", config);
+    let mut player_given = matches.value_source("player_name") == Some(ValueSource::CommandLine)
+        || options.player_mac.is_some();
+    if let Some(name) = options.profile.clone() {
+        let profile = config
+            .profiles
+            .get(&name)
+            .ok_or_else(|| anyhow!("No profile {} in the configuration file", name))?
+            .clone();
+        player_given |= profile.player_name.is_some() || profile.player_mac.is_some();
+        options.apply_profile(profile, &matches);
+        debug!("Options with the profile {}: {:?}", name, options);
+    }
 
-    // get the hostname and port either from the command line or by discovering the server on the
-    // network
-    let (hostname, port) = match options {
-        Options {
-            hostname: Some(ref hostname),
-            port,
-            ..
-        } => (hostname.clone(), port),
-        _ => {
-            let reply = timeout(
-                Duration::from_secs(options.discover_timeout),
-                discover(Duration::from_millis(options.discover_reply_timeout)),
-            )
-            .await??;
-            println!("Discovered LMS at {}:{}", reply.hostname, reply.port);
-            (reply.hostname, reply.port)
+    if let Some(Action::Check { ref player_command }) = options.action {
+        if !player_command.is_empty() {
+            options.player_command = player_command.clone();
+        }
+        return check::run(&options).await;
+    }
+    if let Some(Action::Outputs {
+        ref list_flag,
+        ref player_command,
+    }) = options.action
+    {
+        if !player_command.is_empty() {
+            options.player_command = player_command.clone();
         }
+        return outputs::run(&options, list_flag).await;
+    }
+    if let Some(Action::InstallDesktopEntry { ref icon }) = options.action {
+        let path = desktop_entry::install(icon)?;
+        println!("Installed the desktop entry to {}", path.display());
+        return Ok(());
+    }
+    if let Some(Action::StopAfterCurrent { cancel }) = options.action {
+        let connection = zbus::Connection::session().await?;
+        let proxy = CustomProxy::builder(&connection)
+            .destination(bus_name(&options.player_name, options.instance.as_deref()))?
+            .build()
+            .await?;
+        return proxy
+            .stop_after_current(!cancel)
+            .await
+            .map_err(|error| anyhow!("Unable to reach the running instance: {}", error));
+    }
+
+    if options.demo {
+        let port = demo::start(options.player_name.clone()).await?;
+        options.servers = vec![ServerEntry::Address {
+            hostname: "127.0.0.1".to_string(),
+            port: Some(port),
+        }];
+        options.player_mac = Some(demo::PLAYER_ID.to_string());
+    }
+
+    let credentials = options.credentials().await?;
+    if options.select_player || (!player_given && !options.demo && select::is_interactive()) {
+        select_player(&mut options, credentials.clone(), config.timeouts).await?;
+    }
+    if let Some(Action::Control { ref action }) = options.action {
+        return control(&options, credentials, config.timeouts, action).await;
+    }
+    if let Some(Action::Queue { ref action }) = options.action {
+        return queue(&options, credentials, config.timeouts, action).await;
+    }
+    if let Some(Action::Watch { json }) = options.action {
+        return watch_player(&options, credentials, config.timeouts, json).await;
+    }
+    // the settings changed over D-Bus in a previous run
+    let preferences = Arc::new(Preferences::load(options.instance.as_deref()));
+    options.apply_preferences(&preferences, &matches);
+    #[cfg(feature = "metrics")]
+    let restarts = Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let servers = options.servers().await?;
+    let failover_after = Duration::from_secs(options.failover_after);
+    let mut index = 0;
+    // the number of servers found unreachable in a row
+    let mut unreachable = 0;
+    loop {
+        let entry = &servers[index];
+        let started = Instant::now();
+        let result = match options.resolve(entry).await {
+            Result::Ok((hostname, port, version)) => {
+                let result = run(
+                    options.clone(),
+                    &config,
+                    (hostname.clone(), port, version),
+                    // only a server found on the network is looked for again when it moves
+                    *entry == ServerEntry::Discover,
+                    credentials.clone(),
+                    preferences.clone(),
+                    #[cfg(feature = "metrics")]
+                    restarts.clone(),
+                )
+                .await;
+                match result {
+                    Err(error) if servers.len() > 1 && lms::is_unreachable(&error) => {
+                        warn!(
+                            "The server {}:{} is unreachable: {:#}",
+                            hostname, port, error
+                        );
+                        if started.elapsed() > failover_after {
+                            unreachable = 0;
+                        }
+                        let back = failover::wait_until_back(
+                            hostname,
+                            port,
+                            credentials.clone(),
+                            options.server_flavor,
+                            options.ip_family(),
+                            failover_after,
+                        )
+                        .await;
+                        if back {
+                            info!("The server is back, reconnecting");
+                            continue;
+                        }
+                        Err(error)
+                    }
+                    result => return result,
+                }
+            }
+            Err(error) if servers.len() > 1 => {
+                warn!("Unable to use the server {}: {:#}", entry, error);
+                Err(error)
+            }
+            Err(error) => return Err(error),
+        };
+
+        unreachable += 1;
+        if unreachable == servers.len() {
+            return result.context("None of the servers is reachable");
+        }
+        index = (index + 1) % servers.len();
+        info!("Failing over to the server {}", servers[index]);
+        #[cfg(feature = "metrics")]
+        restarts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// Run the player and the MPRIS server against a server, until the player is asked to quit or an
+/// error occurs
+async fn run(
+    mut options: Options,
+    config: &Config,
+    (mut hostname, mut port, version): (String, u16, Option<String>),
+    discovered: bool,
+    credentials: Option<Credentials>,
+    preferences: Arc<Preferences>,
+    #[cfg(feature = "metrics")] restarts: Arc<std::sync::atomic::AtomicU64>,
+) -> Result<()> {
+    let (client, mut recv) = LmsClient::new(
+        hostname.clone(),
+        port,
+        credentials.clone(),
+        options.server_flavor,
+        config.timeouts,
+        options.ip_family(),
+        options.circuit_breaker,
+    );
+    // the actual cause of a failure is sent to the error channel
+    resolve_name_conflict(&client, &mut options)
+        .await
+        .map_err(|error| recv.try_recv().unwrap_or(error))?;
+
+    // start squeezelite, the demo server pretends to play on its own
+    let mut player_process = if options.demo {
+        None
+    } else {
+        Some(start_squeezelite(&options, &hostname, port)?)
     };
 
-    // start squeezelite
-    let mut player_process = start_squeezelite(&options, &hostname)?;
+    let result: Result<()> = async {
+        client.detect_capabilities().await;
+        options.subscribe = subscribes(&options, &client);
+        let (events_sender, _) = broadcast::channel(16);
+        let (hostname_sender, hostname_receiver) = watch::channel(hostname.clone());
+        // subscribed before the connection to the server so that the player is looked up again
+        // once connected
+        let registrations = options.subscribe.then(|| events_sender.subscribe());
+        let mut services = JoinSet::<Result<()>>::new();
+        if options.subscribe {
+            services.spawn(events::subscribe(
+                hostname_receiver,
+                options.cli_port,
+                credentials.clone(),
+                events_sender.clone(),
+            ));
+        }
 
-    let result: Result<()> = (|| async {
         // wait for the player to be available
-        let (client, mut recv) = LmsClient::new(hostname, port);
-        wait_for_player(&client, &options.player_name, options.player_timeout).await?;
+        let mut player = wait_for_player(
+            &client,
+            options.wanted(),
+            options.player_timeout,
+            registrations,
+        )
+        .await
+        .map_err(|error| recv.try_recv().unwrap_or(error))?;
+        // the name on the server is the one exposed on the bus
+        options.player_name = player.name.clone();
+        if let Some(name) = options.rename_player.clone() {
+            info!("Renaming player {} to {}", player.name, name);
+            client.set_name(player.id.clone(), name.clone()).await?;
+            player.name = name.clone();
+            options.player_name = name;
+        }
+        let version = match version {
+            Some(version) => version,
+            None => client.get_version().await?,
+        };
+        check_version(&version);
+        restore_shuffle(&client, &player.id, &preferences).await?;
+        restore_repeat(&client, &player.id, &preferences).await?;
 
         // start the MPRIS server
-        let _connection = start_dbus_server(client, options.player_name).await?;
+        let client = Arc::new(client);
+        let activity = Arc::new(Activity::default());
+        // the clients can't download the covers from a server requiring authentication
+        let covers = options.metadata() != MetadataProfile::Minimal;
+        if credentials.is_some() && !options.art_cache && covers {
+            info!("Serving the covers from the cache as the server requires authentication");
+        }
+        let art_cache = if (options.art_cache || credentials.is_some()) && covers {
+            Some(ArtCache::new(
+                ArtCache::default_directory(options.instance.as_deref())?,
+                options.art_cache_size * 1024 * 1024,
+            ))
+        } else {
+            None
+        };
+        let (state_sender, state_receiver) = watch::channel(PlayerState::default());
+        let (name_sender, name_receiver) = watch::channel(player.name.clone());
+        let (reload_sender, reload_receiver) = mpsc::unbounded_channel();
+        let (stop_sender, stop_receiver) = mpsc::unbounded_channel();
+        let (quit_sender, mut quit_receiver) = mpsc::unbounded_channel();
+        // there is no process to suspend in demo mode
+        let suspend_after = options.suspend_when_stopped.filter(|_| !options.demo);
+        let (resume_sender, resume_receiver) = mpsc::unbounded_channel();
+        let settings = Settings {
+            art_cache,
+            volume_curve: options.volume_curve,
+            preferences: preferences.clone(),
+            power_on: options.power_on,
+            instance: options.instance.clone(),
+            name_flags: options.name_flags(),
+            skip_forward: options.skip_forward,
+            skip_backward: options.skip_backward,
+            metadata: options.metadata(),
+            server_order: options.server_order,
+            player_name: name_sender,
+        };
+        let server = Server {
+            client: client.clone(),
+            player: player.clone(),
+            activity: activity.clone(),
+            state: state_receiver.clone(),
+            settings,
+            requests: Requests {
+                reload: reload_sender,
+                stop_after_current: stop_sender,
+                quit: quit_sender,
+                resume: suspend_after.map(|_| resume_sender),
+            },
+        };
+        let (connection, connection_receiver) = watch::channel(server.start().await?);
 
-        select! {
-            Some (error) = recv.recv() => bail!("Error from LMS: {:?}", error),
-            _ = player_process.wait() =>
-            {
-                let exit_status = player_process.wait().await?;
-                match exit_status.code() {
-                    Some(code) => bail!("Player exited with code {}", code),
-                    None => bail!("Player exited without code"),
+        // keep the MPRIS clients up to date
+        let poll_settings = options.poll_settings();
+        let start_poller = || {
+            poll(
+                client.clone(),
+                player.id.clone(),
+                Some(connection_receiver.clone()),
+                poll_settings,
+                activity.clone(),
+                state_sender.clone(),
+                options.subscribe.then(|| events_sender.subscribe()),
+            )
+        };
+        let poller = start_poller();
+
+        // the optional services consuming the state of the player
+        if options.subscribe {
+            // the notifications can be turned on and off over D-Bus
+            services.spawn(server_events::run(
+                connection_receiver.clone(),
+                events_sender.subscribe(),
+                preferences.clone(),
+            ));
+            services.spawn(playback_errors::run(
+                connection_receiver.clone(),
+                player.id.clone(),
+                events_sender.subscribe(),
+                preferences.clone(),
+            ));
+        }
+        services.spawn(session_bus::supervise(server, connection));
+        let (hooks_sender, hooks_receiver) = watch::channel(config.hooks.clone());
+        services.spawn(reload(
+            options.clone(),
+            client.clone(),
+            hooks_sender,
+            reload_receiver,
+        ));
+        services.spawn(stop_after::run(
+            client.clone(),
+            player.id.clone(),
+            state_receiver.clone(),
+            stop_receiver,
+        ));
+        services.spawn(hooks::run(
+            hooks_receiver,
+            name_receiver.clone(),
+            state_receiver.clone(),
+        ));
+        if options.inhibit_sleep {
+            services.spawn(optional_service(
+                "sleep inhibitor",
+                inhibit::run(state_receiver.clone()),
+            ));
+        }
+        let (suspend_sender, mut suspend_receiver) = mpsc::unbounded_channel();
+        if let Some(minutes) = suspend_after {
+            services.spawn(power_save::run(
+                state_receiver.clone(),
+                Duration::from_secs(minutes * 60),
+                resume_receiver,
+                suspend_sender,
+            ));
+        }
+        let (sink_sender, mut sink_receiver) = mpsc::unbounded_channel();
+        if options.restart_on_sink_change {
+            services.spawn(optional_service(
+                "watch of the default sink",
+                default_sink::watch(sink_sender),
+            ));
+        }
+        if let Some(ref path) = options.now_playing_file {
+            services.spawn(optional_service(
+                "now playing file",
+                now_playing::run(
+                    path.clone(),
+                    options.now_playing_format.clone(),
+                    state_receiver.clone(),
+                ),
+            ));
+        }
+        if let Some(address) = options.http_status {
+            services.spawn(status::serve(address, state_receiver.clone()));
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(address) = options.metrics_address {
+            services.spawn(metrics::serve(
+                address,
+                client.clone(),
+                state_receiver.clone(),
+                restarts.clone(),
+            ));
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt_options) = options.mqtt() {
+            services.spawn(mqtt::run(
+                mqtt_options,
+                client.clone(),
+                player.clone(),
+                name_receiver.clone(),
+                state_receiver.clone(),
+            ));
+        }
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        pin!(poller);
+        let result = async {
+            loop {
+                let error = select! {
+                    Some(error) = recv.recv() => error.context("Error from LMS"),
+                    result = &mut poller => match result {
+                        // the actual cause is sent to the error channel
+                        Err(error) => recv
+                            .try_recv()
+                            .map(|cause| cause.context("Error from LMS"))
+                            .unwrap_or(error),
+                        Result::Ok(()) => return Ok(()),
+                    },
+                    Some(result) = services.join_next() => return result?,
+                    exit_status = wait_for_exit(&mut player_process) =>
+                    {
+                        match exit_status?.code() {
+                            Some(code) => bail!("Player exited with code {}", code),
+                            None => bail!("Player exited without code"),
+                        }
+                    }
+                    // the MPRIS interface stays up while the player restarts
+                    Some(()) = sink_receiver.recv() => {
+                        info!("Restarting the player on the new default sink");
+                        if let Some(ref mut process) = player_process {
+                            process.kill().await?;
+                            *process = start_squeezelite(&options, &hostname, port)?;
+                        }
+                        #[cfg(feature = "metrics")]
+                        restarts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        continue;
+                    }
+                    Some(suspend) = suspend_receiver.recv() => {
+                        if let Some(ref process) = player_process {
+                            if suspend {
+                                process.suspend()?;
+                            } else {
+                                process.resume()?;
+                            }
+                        }
+                        continue;
+                    }
+                    Some(()) = quit_receiver.recv() => {
+                        info!("Quitting on request");
+                        return Ok(());
+                    }
+                    Some(()) = terminate.recv() => {
+                        info!("Quitting on SIGTERM");
+                        return Ok(());
+                    }
+                    Some(()) = interrupt.recv() => {
+                        info!("Quitting on SIGINT");
+                        return Ok(());
+                    }
+                };
+
+                if !discovered || !lms::is_unreachable(&error) {
+                    return Err(error);
                 }
+                warn!(
+                    "The server {}:{} is unreachable, looking for it on the network: {:#}",
+                    hostname, port, error
+                );
+                let playing = state_receiver.borrow().mode == Mode::Play;
+                let address = (hostname.as_str(), port);
+                match follow_server(
+                    &options,
+                    &client,
+                    &player.id,
+                    &mut player_process,
+                    address,
+                    playing,
+                )
+                .await
+                {
+                    Result::Ok(Some(address)) => {
+                        (hostname, port) = address;
+                        hostname_sender.send_replace(hostname.clone());
+                        #[cfg(feature = "metrics")]
+                        restarts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    }
+                    // the error may date from before the server moved
+                    Result::Ok(None) if client.get_version().await.is_ok() => (),
+                    Result::Ok(None) => return Err(error),
+                    Err(follow_error) => {
+                        warn!("Unable to find the server again: {:#}", follow_error);
+                        return Err(error);
+                    }
+                }
+                // the errors about the previous address are stale
+                while recv.try_recv().is_ok() {}
+                poller.set(start_poller());
             }
         }
-    })()
+        .await
+        // a failed query gives up before its actual cause is received
+        .map_err(|error| {
+            recv.try_recv()
+                .map(|cause| cause.context("Error from LMS"))
+                .unwrap_or(error)
+        });
+
+        // leave the player in a deliberate state, whatever the reason to exit is
+        if let Err(error) = options
+            .on_exit
+            .apply(&client, player.id.clone())
+            .await
+            .map_err(|error| recv.try_recv().unwrap_or(error))
+        {
+            warn!(
+                "Unable to leave the player in the requested state: {:#}",
+                error
+            );
+        }
+        result
+    }
     .await;
 
-    // kill the player process if it is still running
-    if player_process.id().is_some() {
+    // kill the player process, and the processes it started which may have outlived it
+    if let Some(mut process) = player_process {
         info!("Killing player process");
-        player_process.kill().await?;
+        process.kill().await?;
     }
 
     result
@@ -0,0 +1,92 @@
+//! Expose metrics about the bridge in the Prometheus text format on `/metrics`
+use crate::{
+    http::{self, Response},
+    lms::{LmsClient, Mode},
+    mpris::playback_status,
+    state::PlayerState,
+};
+use anyhow::Result;
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::watch;
+
+pub async fn serve(
+    address: SocketAddr,
+    client: Arc<LmsClient>,
+    state: watch::Receiver<PlayerState>,
+    restarts: Arc<AtomicU64>,
+) -> Result<()> {
+    http::serve(address, move |path| match path {
+        "/metrics" => Response::ok(
+            "text/plain; version=0.0.4",
+            render(&client, &state.borrow(), restarts.load(Ordering::Relaxed)),
+        ),
+        _ => Response::not_found(),
+    })
+    .await
+}
+
+fn render(client: &LmsClient, state: &PlayerState, restarts: u64) -> String {
+    let mut output = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, values: &[(String, String)]| {
+        let _ = writeln!(output, "# HELP mprisqueeze_{} {}", name, help);
+        let _ = writeln!(output, "# TYPE mprisqueeze_{} {}", name, kind);
+        for (labels, value) in values {
+            let _ = writeln!(output, "mprisqueeze_{}{} {}", name, labels, value);
+        }
+    };
+
+    let status = [Mode::Play, Mode::Pause, Mode::Stop].map(|mode| {
+        (
+            format!("{{status=\"{}\"}}", playback_status(mode)),
+            u8::from(mode == state.mode).to_string(),
+        )
+    });
+    metric(
+        "playback_status",
+        "gauge",
+        "Playback status of the player",
+        &status,
+    );
+    metric(
+        "volume",
+        "gauge",
+        "Volume of the player, negative when muted",
+        &[(String::new(), state.volume.to_string())],
+    );
+    metric(
+        "lms_requests_total",
+        "counter",
+        "Requests sent to LMS",
+        &[(
+            String::new(),
+            client
+                .counters()
+                .requests
+                .load(Ordering::Relaxed)
+                .to_string(),
+        )],
+    );
+    metric(
+        "lms_errors_total",
+        "counter",
+        "Requests to LMS that failed",
+        &[(
+            String::new(),
+            client.counters().errors.load(Ordering::Relaxed).to_string(),
+        )],
+    );
+    metric(
+        "player_restarts_total",
+        "counter",
+        "Restarts of the player process",
+        &[(String::new(), restarts.to_string())],
+    );
+    output
+}
@@ -1,282 +1,1046 @@
-use crate::lms::{LmsClient, Mode, Shuffle};
-use log::{debug, info};
-use std::{collections::HashMap, convert::TryFrom, result};
+use crate::{
+    art::ArtCache,
+    custom::{CustomPlayer, Requests},
+    debug::Diagnostics,
+    desktop_entry,
+    lms::{Enqueue, LmsClient, Mode, Player, Repeat, Shuffle, SyncedPlayer},
+    playlist_file,
+    playlists::MprisPlaylists,
+    preferences::Preferences,
+    server_info::ServerInfo,
+    state::{Activity, Limiter, MetadataProfile, PlayerState, Track},
+    volume::VolumeCurve,
+};
+use anyhow::bail;
+use log::{debug, info, warn};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{Debug, Display},
+    future::Future,
+    result,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{mpsc, watch},
+    time::timeout,
+};
 use zbus::{
-    connection, fdo, interface,
+    connection,
+    fdo::{self, RequestNameFlags, RequestNameReply},
+    interface,
+    names::InterfaceName,
+    object_server::{Interface, SignalEmitter},
     zvariant::{ObjectPath, Value},
     Connection,
 };
 
+pub const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// The schemes of the URIs accepted by `OpenUri`
+const URI_SCHEMES: [&str; 3] = ["file", "http", "https"];
+
+/// How the MPRIS interface behaves
+#[derive(Clone)]
+pub struct Settings {
+    /// When set, the covers are downloaded and exposed as local files
+    pub art_cache: Option<ArtCache>,
+    pub volume_curve: VolumeCurve,
+    /// The settings which can be changed over D-Bus, such as the ceiling of the LMS volume
+    pub preferences: Arc<Preferences>,
+    /// Power on the player before playing, and power it off on quit
+    pub power_on: bool,
+    /// Appended to the bus name to run several instances for the same player
+    pub instance: Option<String>,
+    /// How the bus name is requested, see `RequestName` in the D-Bus specification
+    pub name_flags: Vec<RequestNameFlags>,
+    /// The default skips of the custom interface, in seconds
+    pub skip_forward: u32,
+    pub skip_backward: u32,
+    /// The metadata fetched about the current track, see `--metadata`
+    pub metadata: MetadataProfile,
+    /// Keep the order and the spelling of the names given by the server, see `--server-order`
+    pub server_order: bool,
+    /// The current name of the player, changed with `SetPlayerName`
+    pub player_name: watch::Sender<String>,
+}
+
 /// Start the DBus server for a given player and expose an MPRIS interface for it. This interface
 /// is specified in [the MPRIS
 /// documentation](https://specifications.freedesktop.org/mpris-spec/latest/).
 pub async fn start_dbus_server(
-    client: LmsClient,
-    player_name: String,
+    client: Arc<LmsClient>,
+    player: Player,
+    activity: Arc<Activity>,
+    state: watch::Receiver<PlayerState>,
+    settings: Settings,
+    requests: Requests,
 ) -> anyhow::Result<Connection> {
+    let Player {
+        name: player_name,
+        id: player_id,
+        ..
+    } = player;
     info!("Starting DBus server for player {}", player_name);
+    let root = MprisRoot {
+        client: client.clone(),
+        player_name: player_name.clone(),
+        player_id: player_id.clone(),
+        power_on: settings.power_on,
+        quit: requests.quit.clone(),
+    };
+    let resume = requests.resume.clone();
+    let custom = CustomPlayer {
+        client: client.clone(),
+        player_id: player_id.clone(),
+        player_name: settings.player_name.clone(),
+        instance: settings.instance.clone(),
+        name_flags: settings.name_flags.clone(),
+        requests,
+        skip_forward: settings.skip_forward,
+        skip_backward: settings.skip_backward,
+        preferences: settings.preferences.clone(),
+        server_order: settings.server_order,
+        state: state.clone(),
+        last_stream_info: Mutex::default(),
+    };
+    let diagnostics = Diagnostics {
+        client: client.clone(),
+        activity: activity.clone(),
+        state: state.clone(),
+    };
+    let server_info = ServerInfo {
+        client: client.clone(),
+    };
+    let playlists = MprisPlaylists {
+        client: client.clone(),
+        player_id: player_id.clone(),
+        server_order: settings.server_order,
+    };
+    let bus_name = bus_name(&player_name, settings.instance.as_deref());
+    let name_flags = settings.name_flags.clone();
     let player = MprisPlayer {
         client,
         player_name: player_name.clone(),
+        player_id,
+        activity,
+        state,
+        settings,
+        last_seek: Mutex::new(None),
+        resume,
     };
 
     let connection = connection::Builder::session()?
-        .name(format!("org.mpris.MediaPlayer2.{}", player_name))?
-        .serve_at("/org/mpris/MediaPlayer2", MprisRoot {})?
-        .serve_at("/org/mpris/MediaPlayer2", player)?
+        .serve_at(MPRIS_PATH, root)?
+        .serve_at(MPRIS_PATH, player)?
+        .serve_at(MPRIS_PATH, playlists)?
+        .serve_at(MPRIS_PATH, custom)?
+        .serve_at(MPRIS_PATH, diagnostics)?
+        .serve_at(MPRIS_PATH, server_info)?
         .build()
         .await?;
+    request_name(&connection, bus_name, &name_flags).await?;
 
     info!("DBus server started for player {}", player_name);
     Ok(connection)
 }
 
-struct MprisRoot {}
+/// MPRIS gives the times in microseconds
+pub fn to_mpris_time(duration: Duration) -> i64 {
+    i64::try_from(duration.as_micros()).unwrap_or(i64::MAX)
+}
+
+/// The negative times are clamped to zero
+pub fn from_mpris_time(time: i64) -> Duration {
+    Duration::from_micros(u64::try_from(time).unwrap_or_default())
+}
+
+/// The well-known name of the instance on the session bus
+pub fn bus_name(player_name: &str, instance: Option<&str>) -> String {
+    match instance {
+        Some(instance) => format!("org.mpris.MediaPlayer2.{}.{}", player_name, instance),
+        None => format!("org.mpris.MediaPlayer2.{}", player_name),
+    }
+}
+
+/// Request the bus name with the given flags, it fails when the name is taken and the request is
+/// not queued
+async fn request_name(
+    connection: &Connection,
+    bus_name: String,
+    flags: &[RequestNameFlags],
+) -> anyhow::Result<()> {
+    let reply = connection
+        .request_name_with_flags(bus_name.as_str(), flags.iter().copied().collect())
+        .await?;
+    match reply {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(()),
+        RequestNameReply::InQueue => {
+            warn!("The bus name {} is taken, waiting for it", bus_name);
+            Ok(())
+        }
+        RequestNameReply::Exists => bail!("The bus name {} is already taken", bus_name),
+    }
+}
+
+/// Follow a rename of the player: the identity, the track ids and the bus name are updated
+pub async fn rename_player(
+    connection: &Connection,
+    instance: Option<&str>,
+    name_flags: &[RequestNameFlags],
+    old_name: &str,
+    new_name: &str,
+) -> anyhow::Result<()> {
+    let object_server = connection.object_server();
+    let root = object_server.interface::<_, MprisRoot>(MPRIS_PATH).await?;
+    root.get_mut().await.player_name = new_name.to_string();
+    root.get()
+        .await
+        .identity_changed(root.signal_emitter())
+        .await?;
+    let player = object_server
+        .interface::<_, MprisPlayer>(MPRIS_PATH)
+        .await?;
+    player.get_mut().await.player_name = new_name.to_string();
+
+    // take the new name before releasing the old one, so that the player is always reachable
+    request_name(connection, bus_name(new_name, instance), name_flags).await?;
+    connection
+        .release_name(bus_name(old_name, instance))
+        .await?;
+    info!("Player {} renamed to {}", old_name, new_name);
+    Ok(())
+}
+
+/// Emit the `PropertiesChanged` signals for the properties that differ between both states, a
+/// single one per interface so that the clients refresh once
+pub async fn properties_changed(
+    connection: &Connection,
+    old: &PlayerState,
+    new: &PlayerState,
+) -> anyhow::Result<()> {
+    let player = connection
+        .object_server()
+        .interface::<_, MprisPlayer>(MPRIS_PATH)
+        .await?;
+    let emitter = player.signal_emitter();
+    let player = player.get().await;
+    let mut changed = HashMap::new();
+    if old.mode != new.mode || old.buffering != new.buffering {
+        changed.insert(
+            "PlaybackStatus",
+            Value::from(reported_status(new.mode, new.buffering)),
+        );
+    }
+    if old.repeat != new.repeat {
+        changed.insert("LoopStatus", Value::from(loop_status(new.repeat)));
+    }
+    if (old.shuffle == Shuffle::Off) != (new.shuffle == Shuffle::Off) {
+        changed.insert("Shuffle", Value::from(new.shuffle != Shuffle::Off));
+    }
+    if old.volume != new.volume {
+        changed.insert("Volume", Value::from(player.mpris_volume(new.volume)));
+    }
+    if old.track != new.track {
+        changed.insert(
+            "Metadata",
+            Value::from(player.track_metadata(new.track.clone()).await?),
+        );
+    }
+    emit_changes(emitter, MprisPlayer::name(), changed, &[]).await?;
+
+    let custom = connection
+        .object_server()
+        .interface::<_, CustomPlayer>(MPRIS_PATH)
+        .await?;
+    let emitter = custom.signal_emitter();
+    let mut changed = HashMap::new();
+    // the stream details are not polled, the clients read them again when they need them
+    let mut invalidated = Vec::new();
+    if old.shuffle != new.shuffle {
+        changed.insert("ShuffleMode", Value::from(new.shuffle.name()));
+    }
+    if old.track != new.track {
+        invalidated.extend(["Codec", "SampleRate", "BitDepth", "Bitrate"]);
+    }
+    if old.buffering != new.buffering {
+        changed.insert("Buffering", Value::from(new.buffering));
+    }
+    if old.playlist_name != new.playlist_name {
+        changed.insert(
+            "PlaylistName",
+            Value::from(new.playlist_name.clone().unwrap_or_default()),
+        );
+    }
+    if old.sync_group != new.sync_group {
+        changed.insert(
+            "SyncGroup",
+            Value::from(sync_group_entries(new.sync_group.clone())),
+        );
+    }
+    emit_changes(emitter, CustomPlayer::name(), changed, &invalidated).await?;
+    Ok(())
+}
+
+async fn emit_changes(
+    emitter: &SignalEmitter<'_>,
+    interface: InterfaceName<'_>,
+    changed: HashMap<&str, Value<'_>>,
+    invalidated: &[&str],
+) -> zbus::Result<()> {
+    if changed.is_empty() && invalidated.is_empty() {
+        return Ok(());
+    }
+    fdo::Properties::properties_changed(emitter, interface, changed, Cow::Borrowed(invalidated))
+        .await
+}
+
+/// The state as last polled while the circuit breaker is open, the properties are served from it
+/// rather than failing
+pub fn cached_state(
+    client: &LmsClient,
+    state: &watch::Receiver<PlayerState>,
+) -> Option<PlayerState> {
+    client.is_circuit_open().then(|| state.borrow().clone())
+}
+
+/// The value of the `PlaybackStatus` property. A buffering player is about to play, it is not
+/// stalled.
+fn reported_status(mode: Mode, buffering: bool) -> &'static str {
+    playback_status(if buffering { Mode::Play } else { mode })
+}
+
+/// The value of the `SyncGroup` property of the custom interface
+pub fn sync_group_entries(players: Vec<SyncedPlayer>) -> Vec<(String, String)> {
+    players
+        .into_iter()
+        .map(|player| (player.id, player.name))
+        .collect()
+}
+
+/// The value of the `PlaybackStatus` property for a given mode
+pub fn playback_status(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Play => "Playing",
+        Mode::Pause => "Paused",
+        Mode::Stop => "Stopped",
+    }
+}
+
+/// The value of the `LoopStatus` property for a given repeat mode
+fn loop_status(repeat: Repeat) -> &'static str {
+    match repeat {
+        Repeat::Off => "None",
+        Repeat::Track => "Track",
+        Repeat::Playlist => "Playlist",
+    }
+}
+
+/// The log target of the D-Bus calls, enabled with `--debug-dbus`
+pub const DBUS_LOG_TARGET: &str = "mprisqueeze::dbus";
+
+/// Log a D-Bus call when it is received, then along with its result and duration once handled
+pub async fn trace<T: Debug>(call: impl Display, handler: impl Future<Output = T>) -> T {
+    debug!(target: DBUS_LOG_TARGET, "{}", call);
+    let start = Instant::now();
+    let result = handler.await;
+    debug!(
+        target: DBUS_LOG_TARGET,
+        "{} -> {:?} in {:?}",
+        call,
+        result,
+        start.elapsed()
+    );
+    result
+}
+
+/// The longest a D-Bus call waits for LMS. The callers, such as the sound panels of the desktops,
+/// are blocked meanwhile.
+const HANDLER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Trace a D-Bus call which waits for LMS. It fails with `fdo::Error::Timeout` when LMS doesn't
+/// reply in time, and the state is marked as degraded until the next response.
+pub async fn bounded<T: Debug, E: Debug + From<fdo::Error>>(
+    client: &LmsClient,
+    call: impl Display,
+    handler: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let call = call.to_string();
+    trace(&call, async {
+        timeout(HANDLER_TIMEOUT, handler).await.unwrap_or_else(|_| {
+            warn!(
+                "{} timed out after {:?} waiting for LMS",
+                call, HANDLER_TIMEOUT
+            );
+            client.counters().degraded.store(true, Ordering::Relaxed);
+            Err(
+                fdo::Error::Timeout(format!("LMS didn't reply within {:?}", HANDLER_TIMEOUT))
+                    .into(),
+            )
+        })
+    })
+    .await
+}
+
+struct MprisRoot {
+    client: Arc<LmsClient>,
+    player_name: String,
+    /// The requests are sent with the id of the player, its name may not be unique
+    player_id: String,
+    power_on: bool,
+    quit: mpsc::UnboundedSender<()>,
+}
 
 #[interface(name = "org.mpris.MediaPlayer2")]
 impl MprisRoot {
     async fn raise(&self) {
-        debug!("MprisRoot::raise");
+        trace("Raise()", async {}).await
     }
 
-    async fn quit(&self) {
-        debug!("MprisRoot::quit");
+    /// Shut mprisqueeze down, the player is powered off first if enabled in the settings
+    async fn quit(&self) -> Result<(), fdo::Error> {
+        bounded(&self.client, "Quit()", async {
+            if self.power_on {
+                self.client
+                    .power(self.player_id.clone(), false)
+                    .await
+                    .map_err(to_fdo_error)?;
+            }
+            self.quit
+                .send(())
+                .map_err(|error| fdo::Error::Failed(error.to_string()))
+        })
+        .await
     }
 
     #[zbus(property)]
     async fn can_quit(&self) -> bool {
-        debug!("MprisRoot::can_quit");
-        false
+        trace("CanQuit", async { true }).await
     }
     #[zbus(property)]
     async fn can_raise(&self) -> bool {
-        debug!("MprisRoot::can_raise");
-        false
+        trace("CanRaise", async { false }).await
+    }
+
+    /// Empty until the desktop entry is installed with `install-desktop-entry`
+    #[zbus(property)]
+    async fn desktop_entry(&self) -> String {
+        trace("DesktopEntry", async {
+            if desktop_entry::is_installed() {
+                desktop_entry::NAME.to_string()
+            } else {
+                String::new()
+            }
+        })
+        .await
     }
 
     #[zbus(property)]
     async fn has_track_list(&self) -> bool {
-        debug!("MprisRoot::has_track_list");
-        false
+        trace("HasTrackList", async { false }).await
     }
 
     #[zbus(property)]
     async fn identity(&self) -> String {
-        debug!("MprisRoot::identity");
-        "squeezelite".to_string()
+        trace("Identity", async { self.player_name.clone() }).await
     }
 
     #[zbus(property)]
     async fn supported_uri_schemes(&self) -> Vec<String> {
-        debug!("MprisRoot::supported_uri_schemes");
-        vec![]
+        trace("SupportedUriSchemes", async {
+            URI_SCHEMES.iter().map(ToString::to_string).collect()
+        })
+        .await
     }
 
+    /// The playlist files and the common audio files, see `OpenUri`
     #[zbus(property)]
     async fn supported_mime_types(&self) -> Vec<String> {
-        debug!("MprisRoot::supported_mime_types");
-        vec![]
+        trace("SupportedMimeTypes", async {
+            [
+                "audio/x-mpegurl",
+                "audio/x-scpls",
+                "audio/mpeg",
+                "audio/flac",
+                "audio/ogg",
+                "audio/mp4",
+                "audio/x-wav",
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+        })
+        .await
     }
 }
 
 struct MprisPlayer {
-    client: LmsClient,
+    client: Arc<LmsClient>,
     player_name: String,
+    player_id: String,
+    /// Updated when a client reads a property fetched from LMS
+    activity: Arc<Activity>,
+    /// The state as last polled
+    state: watch::Receiver<PlayerState>,
+    settings: Settings,
+    last_seek: Mutex<Option<Seek>>,
+    /// See `Requests::resume`
+    resume: Option<mpsc::UnboundedSender<()>>,
+}
+
+/// A position requested by a client
+struct Seek {
+    /// Identifies the last request of a scrubbing
+    id: u64,
+    position: Duration,
+    at: Instant,
+}
+
+/// While scrubbing, the relative seeks start from the last requested position as the polled state
+/// lags behind
+const SCRUB_WINDOW: Duration = Duration::from_secs(1);
+
+impl MprisPlayer {
+    /// Continue the player process if it has been suspended, and power on the player if enabled
+    /// in the settings
+    async fn wake_up(&self) -> Result<(), fdo::Error> {
+        if let Some(ref resume) = self.resume {
+            let _ = resume.send(());
+        }
+        if self.settings.power_on {
+            self.client
+                .power(self.player_id.clone(), true)
+                .await
+                .map_err(to_fdo_error)?;
+        }
+        Ok(())
+    }
+
+    fn cached_state(&self) -> Option<PlayerState> {
+        cached_state(&self.client, &self.state)
+    }
+
+    /// The id of the current track in the metadata
+    fn track_id(&self, index: u64) -> Result<ObjectPath<'static>, fdo::Error> {
+        // only the alphanumeric characters and underscores are allowed in an object path
+        let element: String = self
+            .player_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        ObjectPath::try_from(format!(
+            "/org/mpris/MediaPlayer2/{0}/track/{index}",
+            element
+        ))
+        .map_err(|error| to_fdo_error(error.into()))
+    }
+
+    /// The position a relative seek starts from
+    fn seek_origin(&self) -> Duration {
+        match *self.last_seek.lock().unwrap() {
+            Some(ref seek) if seek.at.elapsed() < SCRUB_WINDOW => seek.position,
+            _ => self.state.borrow().elapsed().unwrap_or_default(),
+        }
+    }
+
+    /// Move to the position, `Seeked` is emitted once for the seeks merged together
+    async fn seek_to(
+        &self,
+        emitter: &SignalEmitter<'_>,
+        position: Duration,
+    ) -> Result<(), fdo::Error> {
+        let id = {
+            let mut last_seek = self.last_seek.lock().unwrap();
+            let id = last_seek.as_ref().map_or(0, |seek| seek.id + 1);
+            *last_seek = Some(Seek {
+                id,
+                position,
+                at: Instant::now(),
+            });
+            id
+        };
+        self.client
+            .seek(self.player_id.clone(), position)
+            .await
+            .map_err(to_fdo_error)?;
+        let last = self.last_seek.lock().unwrap().as_ref().map(|seek| seek.id) == Some(id);
+        if last {
+            Self::seeked(emitter, to_mpris_time(position)).await?;
+        }
+        Ok(())
+    }
+
+    /// The value of the `Volume` property for a volume of LMS
+    fn mpris_volume(&self, volume: i64) -> f64 {
+        // a negative volume means the player is muted
+        self.settings.volume_curve.to_mpris(volume.max(0) as u64)
+    }
+
+    /// The value of the `Metadata` property for the current track
+    async fn track_metadata(
+        &self,
+        track: Option<Track>,
+    ) -> Result<HashMap<String, Value<'static>>, fdo::Error> {
+        let Some(Track {
+            index,
+            artist,
+            album,
+            title,
+            cover_id,
+            genre,
+            track_number,
+            duration,
+        }) = track
+        else {
+            return Ok(HashMap::new());
+        };
+        let mut hm = HashMap::new();
+        hm.insert("mpris:trackid".to_string(), self.track_id(index)?.into());
+        if let Some(artist) = artist {
+            hm.insert("xesam:artist".to_string(), vec![artist].into());
+        }
+        if let Some(album) = album {
+            hm.insert("xesam:album".to_string(), album.into());
+        }
+        if let Some(title) = title {
+            hm.insert("xesam:title".to_string(), title.into());
+        }
+        if let Some(genre) = genre.filter(|genre| !genre.is_empty()) {
+            hm.insert("xesam:genre".to_string(), vec![genre].into());
+        }
+        if let Some(track_number) = track_number {
+            hm.insert(
+                "xesam:trackNumber".to_string(),
+                (track_number as i32).into(),
+            );
+        }
+        // the invalid durations, such as the negative ones, are left out as the missing ones
+        if let Some(duration) = duration
+            .and_then(|duration| Duration::try_from_secs_f64(duration).ok())
+            .filter(|duration| !duration.is_zero())
+        {
+            hm.insert("mpris:length".to_string(), to_mpris_time(duration).into());
+        }
+        if let Some(art_url) = self.art_url(cover_id).await {
+            hm.insert("mpris:artUrl".to_string(), art_url.into());
+        }
+        Ok(hm)
+    }
+
+    async fn art_url(&self, cover_id: Option<String>) -> Option<String> {
+        let cover_id = cover_id?;
+        match self.settings.art_cache {
+            Some(ref art_cache) => match art_cache.get(&self.client, &cover_id).await {
+                Ok(path) => Some(format!("file://{}", path.display())),
+                Err(error) => {
+                    warn!("Unable to cache the cover {}: {}", cover_id, error);
+                    None
+                }
+            },
+            None => Some(self.client.cover_url(&cover_id)),
+        }
+    }
 }
 
-fn to_fdo_error(err: anyhow::Error) -> fdo::Error {
+pub fn to_fdo_error(err: anyhow::Error) -> fdo::Error {
     fdo::Error::Failed(err.to_string())
 }
 
 #[interface(name = "org.mpris.MediaPlayer2.Player")]
 impl MprisPlayer {
     async fn next(&self) -> Result<(), fdo::Error> {
-        debug!("MprisPlayer::next");
-        self.client
-            .next(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)
+        bounded(&self.client, "Next()", async {
+            self.client
+                .next(self.player_id.clone())
+                .await
+                .map_err(to_fdo_error)
+        })
+        .await
     }
     async fn previous(&self) -> Result<(), fdo::Error> {
-        debug!("MprisPlayer::previous");
-        self.client
-            .previous(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)
+        bounded(&self.client, "Previous()", async {
+            self.client
+                .previous(self.player_id.clone())
+                .await
+                .map_err(to_fdo_error)
+        })
+        .await
     }
     async fn pause(&self) -> Result<(), fdo::Error> {
-        debug!("MprisPlayer::pause");
-        self.client
-            .pause(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)
+        bounded(&self.client, "Pause()", async {
+            self.client
+                .pause(self.player_id.clone())
+                .await
+                .map_err(to_fdo_error)
+        })
+        .await
     }
     async fn play_pause(&self) -> Result<(), fdo::Error> {
-        debug!("MprisPlayer::play_pause");
-        self.client
-            .play_pause(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)
+        bounded(&self.client, "PlayPause()", async {
+            self.wake_up().await?;
+            self.client
+                .play_pause(self.player_id.clone())
+                .await
+                .map_err(to_fdo_error)
+        })
+        .await
     }
     async fn stop(&self) -> Result<(), fdo::Error> {
-        debug!("MprisPlayer::stop");
-        self.client
-            .stop(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)
+        bounded(&self.client, "Stop()", async {
+            self.client
+                .stop(self.player_id.clone())
+                .await
+                .map_err(to_fdo_error)
+        })
+        .await
     }
     async fn play(&self) -> Result<(), fdo::Error> {
-        debug!("MprisPlayer::play");
-        let res = self
-            .client
-            .play(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error);
-        res
+        bounded(&self.client, "Play()", async {
+            self.wake_up().await?;
+            self.client
+                .play(self.player_id.clone())
+                .await
+                .map_err(to_fdo_error)
+        })
+        .await
     }
-    async fn seek(&self, offset: i64) {
-        debug!("MprisPlayer::seek {}", offset);
+    async fn seek(
+        &self,
+        offset: i64,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> Result<(), fdo::Error> {
+        bounded(&self.client, format!("Seek({})", offset), async {
+            if self.state.borrow().track.is_none() {
+                return Ok(());
+            }
+            let origin = self.seek_origin();
+            let distance = from_mpris_time(offset.saturating_abs());
+            let position = if offset < 0 {
+                origin.saturating_sub(distance)
+            } else {
+                origin + distance
+            };
+            self.seek_to(&emitter, position).await
+        })
+        .await
     }
-    async fn set_position(&self, track_id: String, position: i64) {
-        debug!("MprisPlayer::set_position {} {}", track_id, position);
+    async fn set_position(
+        &self,
+        track_id: ObjectPath<'_>,
+        position: i64,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> Result<(), fdo::Error> {
+        bounded(
+            &self.client,
+            format!("SetPosition({}, {})", track_id, position),
+            async {
+                // the request is ignored when the track has changed in the meantime
+                let index = self.state.borrow().track.as_ref().map(|track| track.index);
+                match index {
+                    Some(index) if position >= 0 && self.track_id(index)? == track_id => {
+                        self.seek_to(&emitter, from_mpris_time(position)).await
+                    }
+                    _ => Ok(()),
+                }
+            },
+        )
+        .await
     }
-    async fn open_uri(&self, uri: String) {
-        debug!("MprisPlayer::open_uri {}", uri);
+    /// Emitted when the position has changed other than by playing
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+    /// The playlist files replace the playlist of the player, their entries are queued in the
+    /// background as there may be many of them. The other files, which have to be in the library
+    /// of the server, and the streams replace the playlist and play.
+    async fn open_uri(&self, uri: String) -> Result<(), fdo::Error> {
+        bounded(&self.client, format!("OpenUri({})", uri), async {
+            let Some(path) = playlist_file::from_uri(&uri) else {
+                let supported = uri.split_once("://").is_some_and(|(scheme, _)| {
+                    URI_SCHEMES
+                        .iter()
+                        .any(|supported| scheme.eq_ignore_ascii_case(supported))
+                });
+                if !supported {
+                    debug!("Unsupported URI {}", uri);
+                    return Ok(());
+                }
+                self.wake_up().await?;
+                return self
+                    .client
+                    .enqueue_url(self.player_id.clone(), uri, Enqueue::Load)
+                    .await
+                    .map_err(to_fdo_error);
+            };
+            let urls = playlist_file::read(&path)
+                .map_err(|error| fdo::Error::InvalidArgs(format!("{:#}", error)))?;
+            self.wake_up().await?;
+            let client = self.client.clone();
+            let player_id = self.player_id.clone();
+            tokio::spawn(async move {
+                if let Err(error) = playlist_file::enqueue(&client, &player_id, &urls, true).await {
+                    warn!("Unable to open {}: {:#}", path.display(), error);
+                }
+            });
+            Ok(())
+        })
+        .await
     }
 
     #[zbus(property)]
     async fn playback_status(&self) -> result::Result<String, fdo::Error> {
-        debug!("MprisPlayer::playback_status");
-        let mode = self
-            .client
-            .get_mode(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)?;
-        Ok(match mode {
-            Mode::Play => "Playing",
-            Mode::Pause => "Paused",
-            Mode::Stop => "Stopped",
-        }
-        .to_string())
+        bounded(&self.client, "PlaybackStatus", async {
+            self.activity.touch();
+            let (mode, buffering) = match self.cached_state() {
+                Some(state) => (state.mode, state.buffering),
+                None => (
+                    self.client
+                        .get_mode(self.player_id.clone())
+                        .await
+                        .map_err(to_fdo_error)?,
+                    self.client
+                        .get_buffering(self.player_id.clone())
+                        .await
+                        .map_err(to_fdo_error)?,
+                ),
+            };
+            Ok(reported_status(mode, buffering).to_string())
+        })
+        .await
     }
     #[zbus(property)]
-    async fn loop_status(&self) -> String {
-        debug!("MprisPlayer::loop_status");
-        "None".to_string()
+    async fn loop_status(&self) -> result::Result<String, fdo::Error> {
+        bounded(&self.client, "LoopStatus", async {
+            self.activity.touch();
+            let repeat = match self.cached_state() {
+                Some(state) => state.repeat,
+                None => self
+                    .client
+                    .get_repeat(self.player_id.clone())
+                    .await
+                    .map_err(to_fdo_error)?,
+            };
+            Ok(loop_status(repeat).to_string())
+        })
+        .await
+    }
+    #[zbus(property)]
+    async fn set_loop_status(&self, value: String) -> zbus::Result<()> {
+        bounded(&self.client, format!("LoopStatus = {}", value), async {
+            let repeat = match value.as_str() {
+                "None" => Repeat::Off,
+                "Track" => Repeat::Track,
+                "Playlist" => Repeat::Playlist,
+                _ => {
+                    return Err(fdo::Error::InvalidArgs(format!(
+                        "Expected None, Track or Playlist, got {}",
+                        value
+                    ))
+                    .into())
+                }
+            };
+            self.client
+                .set_repeat(self.player_id.clone(), repeat)
+                .await
+                .map_err(to_fdo_error)?;
+            self.settings
+                .preferences
+                .update(|values| values.repeat = Some(repeat));
+            Ok(())
+        })
+        .await
     }
     #[zbus(property)]
     async fn rate(&self) -> f64 {
-        1.0
+        trace("Rate", async { 1.0 }).await
     }
     #[zbus(property)]
     async fn shuffle(&self) -> result::Result<bool, fdo::Error> {
-        debug!("MprisPlayer::shuffle");
-        let shuffle = self
-            .client
-            .get_shuffle(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)?;
+        bounded(&self.client, "Shuffle", async {
+            self.activity.touch();
+            let shuffle = match self.cached_state() {
+                Some(state) => state.shuffle,
+                None => self
+                    .client
+                    .get_shuffle(self.player_id.clone())
+                    .await
+                    .map_err(to_fdo_error)?,
+            };
 
-        Ok(shuffle == Shuffle::Songs)
+            // shuffling by album doesn't play the tracks in order either
+            Ok(shuffle != Shuffle::Off)
+        })
+        .await
     }
     #[zbus(property)]
-    async fn metadata(&self) -> result::Result<HashMap<String, Value>, fdo::Error> {
-        debug!("MprisPlayer::metadata");
-        let track_count = self
-            .client
-            .get_track_count(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)?;
-        if track_count == 0 {
-            debug!("MprisPlayer::metadata no track");
-            return Ok(HashMap::new());
-        }
-        let artist = self
-            .client
-            .get_artist(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)?;
-        let album = self
-            .client
-            .get_album(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)?;
-        let title = self
-            .client
-            .get_title(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)?;
-        let index = self
-            .client
-            .get_index(self.player_name.clone())
-            .await
-            .map_err(to_fdo_error)?;
-        let mut hm = HashMap::new();
-        let op = ObjectPath::try_from(format!(
-            "/org/mpris/MediaPlayer2/{0}/track/{index}",
-            self.player_name
-        ))
-        .unwrap();
-        hm.insert("mpris:trackid".to_string(), op.into());
-        artist.map(|artist| {
-            hm.insert("xesam:artist".to_string(), vec![artist].into());
-        });
-        album.map(|album| {
-            hm.insert("xesam:album".to_string(), album.into());
-        });
-        title.map(|title| {
-            hm.insert("xesam:title".to_string(), title.into());
-        });
-        Ok(hm)
+    async fn set_shuffle(&self, shuffle: bool) -> zbus::Result<()> {
+        bounded(&self.client, format!("Shuffle = {}", shuffle), async {
+            let current = self
+                .client
+                .get_shuffle(self.player_id.clone())
+                .await
+                .map_err(to_fdo_error)?;
+            // keep shuffling by album, see the ShuffleMode property of the custom interface
+            let shuffle = match (shuffle, current) {
+                (false, _) => Shuffle::Off,
+                (true, Shuffle::Off) => Shuffle::Songs,
+                (true, current) => current,
+            };
+            if shuffle != current {
+                self.client
+                    .set_shuffle(self.player_id.clone(), shuffle)
+                    .await
+                    .map_err(to_fdo_error)?;
+            }
+            self.settings
+                .preferences
+                .update(|values| values.shuffle = Some(shuffle));
+            Ok(())
+        })
+        .await
+    }
+    #[zbus(property)]
+    async fn metadata(&self) -> result::Result<HashMap<String, Value<'_>>, fdo::Error> {
+        bounded(&self.client, "Metadata", async {
+            self.activity.touch();
+            let track = match self.cached_state() {
+                Some(state) => state.track,
+                None => Track::fetch(
+                    &self.client,
+                    &self.player_id,
+                    self.settings.metadata,
+                    &Limiter::new(1),
+                )
+                .await
+                .map_err(to_fdo_error)?,
+            };
+            self.track_metadata(track).await
+        })
+        .await
     }
     #[zbus(property)]
-    async fn volume(&self) -> f64 {
-        debug!("MprisPlayer::volume");
-        1.0
+    async fn volume(&self) -> result::Result<f64, fdo::Error> {
+        bounded(&self.client, "Volume", async {
+            self.activity.touch();
+            let volume = match self.cached_state() {
+                Some(state) => state.volume,
+                None => self
+                    .client
+                    .get_volume(self.player_id.clone())
+                    .await
+                    .map_err(to_fdo_error)?,
+            };
+            Ok(self.mpris_volume(volume))
+        })
+        .await
+    }
+    #[zbus(property)]
+    async fn set_volume(&self, volume: f64) -> zbus::Result<()> {
+        bounded(&self.client, format!("Volume = {}", volume), async {
+            let volume = self.settings.volume_curve.to_lms(volume);
+            // the volumes set are clamped, the one reported is left as is
+            let max_volume = self.settings.preferences.max_volume();
+            if volume > max_volume {
+                debug!("Volume {} clamped to {}", volume, max_volume);
+            }
+            self.client
+                .set_volume(self.player_id.clone(), volume.min(max_volume))
+                .await
+                .map_err(|error| to_fdo_error(error).into())
+        })
+        .await
     }
     #[zbus(property)]
     async fn position(&self) -> i64 {
-        debug!("MprisPlayer::position");
-        0
+        trace("Position", async {
+            self.activity.touch();
+            self.state.borrow().elapsed().map_or(0, to_mpris_time)
+        })
+        .await
     }
     #[zbus(property)]
     async fn minimum_rate(&self) -> f64 {
-        debug!("MprisPlayer::minimum_rate");
-        1.0
+        trace("MinimumRate", async { 1.0 }).await
     }
     #[zbus(property)]
     async fn maximum_rate(&self) -> f64 {
-        debug!("MprisPlayer::maximum_rate");
-        1.0
+        trace("MaximumRate", async { 1.0 }).await
     }
     #[zbus(property)]
     async fn can_go_next(&self) -> bool {
-        debug!("MprisPlayer::can_go_next");
-        true
+        trace("CanGoNext", async { true }).await
     }
     #[zbus(property)]
     async fn can_go_previous(&self) -> bool {
-        debug!("MprisPlayer::can_go_previous");
-        true
+        trace("CanGoPrevious", async { true }).await
     }
     #[zbus(property)]
     async fn can_play(&self) -> bool {
-        debug!("MprisPlayer::can_play");
-        true
+        trace("CanPlay", async { true }).await
     }
     #[zbus(property)]
     async fn can_pause(&self) -> bool {
-        debug!("MprisPlayer::can_pause");
-        true
+        trace("CanPause", async { true }).await
     }
     #[zbus(property)]
     async fn can_seek(&self) -> bool {
-        debug!("MprisPlayer::can_seek");
-        false
+        trace("CanSeek", async { true }).await
     }
     #[zbus(property)]
     async fn can_control(&self) -> bool {
-        debug!("MprisPlayer::can_control");
-        true
+        trace("CanControl", async { true }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The length of a track of 200 seconds, in microseconds
+    const LENGTH: i64 = 200_000_000;
+
+    #[test]
+    fn to_mpris_time_of_zero() {
+        assert_eq!(to_mpris_time(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn to_mpris_time_truncates_the_fractions_of_microsecond() {
+        assert_eq!(to_mpris_time(Duration::from_nanos(999)), 0);
+        assert_eq!(to_mpris_time(Duration::from_nanos(1_999)), 1);
+    }
+
+    #[test]
+    fn to_mpris_time_at_the_end_of_the_track() {
+        assert_eq!(to_mpris_time(Duration::from_secs(200)), LENGTH);
+        // just before the end, the position doesn't round up to the length
+        assert_eq!(
+            to_mpris_time(Duration::from_secs(200) - Duration::from_nanos(1)),
+            LENGTH - 1
+        );
+    }
+
+    #[test]
+    fn to_mpris_time_past_the_end_of_the_track() {
+        // the position extrapolated while playing may go past the length, it is not clamped
+        assert_eq!(
+            to_mpris_time(Duration::from_secs(200) + Duration::from_micros(1)),
+            LENGTH + 1
+        );
+        assert_eq!(to_mpris_time(Duration::MAX), i64::MAX);
+    }
+
+    #[test]
+    fn from_mpris_time_round_trip() {
+        for time in [0, 1, LENGTH - 1, LENGTH, LENGTH + 1] {
+            assert_eq!(to_mpris_time(from_mpris_time(time)), time);
+        }
+    }
+
+    #[test]
+    fn from_mpris_time_clamps_the_negative_times() {
+        assert_eq!(from_mpris_time(-1), Duration::ZERO);
+        assert_eq!(from_mpris_time(i64::MIN), Duration::ZERO);
     }
 }
@@ -0,0 +1,152 @@
+//! Publish the state of the player to an MQTT broker. The messages are retained so that a client
+//! connecting later gets the current state right away. The following topics are used, relative to
+//! the topic prefix:
+//! - `state`: `Playing`, `Paused` or `Stopped`
+//! - `metadata`: the current track as a JSON object, `null` if the playlist is empty
+//! - `volume`: the volume between 0 and 100, negative when muted
+//!
+//! Optionally, the player can be controlled by publishing `play`, `pause`, `play_pause`, `stop`,
+//! `next` or `previous` on the `command` topic.
+use crate::{
+    lms::{LmsClient, Player},
+    mpris::playback_status,
+    state::PlayerState,
+};
+use anyhow::{bail, Result};
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::{sync::Arc, time::Duration};
+use tokio::{select, sync::watch, time::sleep};
+
+#[derive(Debug)]
+pub struct Options {
+    pub hostname: String,
+    pub port: u16,
+    /// The topic prefix, `mprisqueeze/<player name>` when not given
+    pub topic: Option<String>,
+    /// Subscribe to the command topic
+    pub commands: bool,
+}
+
+pub async fn run(
+    options: Options,
+    client: Arc<LmsClient>,
+    player: Player,
+    mut player_name: watch::Receiver<String>,
+    mut state: watch::Receiver<PlayerState>,
+) -> Result<()> {
+    // the default topic follows the renames of the player
+    let topic_of = |name: &str| {
+        options
+            .topic
+            .clone()
+            .unwrap_or_else(|| format!("mprisqueeze/{}", name))
+    };
+    let mut topic = topic_of(&player_name.borrow_and_update());
+    info!(
+        "Publishing to MQTT broker {}:{} on {}",
+        options.hostname, options.port, topic
+    );
+    let mut mqtt_options = MqttOptions::new(
+        format!("mprisqueeze-{}", player.name),
+        &options.hostname,
+        options.port,
+    );
+    mqtt_options.set_keep_alive(Duration::from_secs(30));
+    let (mqtt, mut event_loop) = AsyncClient::new(mqtt_options, 16);
+    let mut command_topic = format!("{}/command", topic);
+
+    loop {
+        select! {
+            event = event_loop.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    debug!("Connected to MQTT broker");
+                    if options.commands {
+                        subscribe(&mqtt, &command_topic);
+                    }
+                    publish(&mqtt, &topic, &state.borrow());
+                }
+                Ok(Event::Incoming(Packet::Publish(message))) if message.topic == command_topic => {
+                    let command = String::from_utf8_lossy(&message.payload).trim().to_string();
+                    if let Err(error) = run_command(&client, &player.id, &command).await {
+                        warn!("Unable to run MQTT command {}: {}", command, error);
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    // the event loop reconnects on the next poll
+                    warn!("MQTT connection error: {}", error);
+                    sleep(Duration::from_secs(5)).await;
+                }
+            },
+            changed = state.changed() => {
+                changed?;
+                publish(&mqtt, &topic, &state.borrow_and_update());
+            }
+            changed = player_name.changed() => {
+                changed?;
+                let new_topic = topic_of(&player_name.borrow_and_update());
+                if new_topic == topic {
+                    continue;
+                }
+                info!("Publishing to MQTT on {}", new_topic);
+                if options.commands {
+                    if let Err(error) = mqtt.try_unsubscribe(&command_topic) {
+                        warn!("Unable to unsubscribe from {}: {}", command_topic, error);
+                    }
+                }
+                topic = new_topic;
+                command_topic = format!("{}/command", topic);
+                if options.commands {
+                    subscribe(&mqtt, &command_topic);
+                }
+                publish(&mqtt, &topic, &state.borrow());
+            }
+        }
+    }
+}
+
+/// Subscribe to `topic`. The request is dropped when the queue of the client is full, as it is when
+/// the broker is unreachable, the subscription is made again on the next connection.
+fn subscribe(mqtt: &AsyncClient, topic: &str) {
+    if let Err(error) = mqtt.try_subscribe(topic, QoS::AtLeastOnce) {
+        warn!("Unable to subscribe to {}: {}", topic, error);
+    }
+}
+
+/// Publish the state under `topic`. As for the subscription, the messages are dropped when the
+/// queue of the client is full, the current state is published again on the next connection.
+fn publish(mqtt: &AsyncClient, topic: &str, state: &PlayerState) {
+    let metadata = match serde_json::to_string(&state.track) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            warn!("Unable to serialize the metadata: {}", error);
+            return;
+        }
+    };
+    let messages = [
+        ("state", playback_status(state.mode).to_string()),
+        ("metadata", metadata),
+        ("volume", state.volume.to_string()),
+    ];
+    for (subtopic, payload) in messages {
+        let topic = format!("{}/{}", topic, subtopic);
+        if let Err(error) = mqtt.try_publish(&topic, QoS::AtLeastOnce, true, payload) {
+            warn!("Unable to publish to {}: {}", topic, error);
+        }
+    }
+}
+
+async fn run_command(client: &LmsClient, player_id: &str, command: &str) -> Result<()> {
+    debug!("MQTT command: {}", command);
+    let player_id = player_id.to_string();
+    match command {
+        "play" => client.play(player_id).await,
+        "pause" => client.pause(player_id).await,
+        "play_pause" => client.play_pause(player_id).await,
+        "stop" => client.stop(player_id).await,
+        "next" => client.next(player_id).await,
+        "previous" => client.previous(player_id).await,
+        _ => bail!("Unknown command"),
+    }
+}
@@ -0,0 +1,42 @@
+//! Desktop notifications, sent through the `org.freedesktop.Notifications` service
+use anyhow::Result;
+use std::collections::HashMap;
+use zbus::{proxy, zvariant::Value, Connection};
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, &Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Show a notification with the default timeout of the notification server
+pub async fn notify(connection: &Connection, summary: &str, body: &str) -> Result<()> {
+    let notifications = NotificationsProxy::new(connection).await?;
+    notifications
+        .notify(
+            "mprisqueeze",
+            0,
+            "audio-x-generic",
+            summary,
+            body,
+            &[],
+            HashMap::new(),
+            -1,
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! Write the current track to a file each time it changes, for the tools reading their input from
+//! a file such as OBS or some status bars
+use crate::state::{PlayerState, Track};
+use anyhow::Result;
+use log::debug;
+use std::{ffi::OsString, path::PathBuf};
+use tokio::{fs, sync::watch};
+
+pub async fn run(
+    path: PathBuf,
+    format: String,
+    mut state: watch::Receiver<PlayerState>,
+) -> Result<()> {
+    let mut last_track = None;
+    loop {
+        let track = state.borrow_and_update().track.clone();
+        if last_track.as_ref() != Some(&track) {
+            let content = track
+                .as_ref()
+                .map(|track| render(&format, track))
+                .unwrap_or_default();
+            write(&path, &content).await?;
+            last_track = Some(track);
+        }
+        state.changed().await?;
+    }
+}
+
+/// Replace the placeholders `{artist}`, `{album}` and `{title}` with the values of the track
+fn render(format: &str, track: &Track) -> String {
+    let value = |field: &Option<String>| field.clone().unwrap_or_default();
+    format
+        .replace("{artist}", &value(&track.artist))
+        .replace("{album}", &value(&track.album))
+        .replace("{title}", &value(&track.title))
+}
+
+// The content is written to a temporary file which is then renamed, so that the readers never see
+// a partially written file
+async fn write(path: &PathBuf, content: &str) -> Result<()> {
+    debug!("Writing now playing file {:?}: {}", path, content);
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(path.file_name().unwrap_or_default());
+    temporary_name.push(".tmp");
+    let temporary_path = path.with_file_name(temporary_name);
+    fs::write(&temporary_path, format!("{}\n", content)).await?;
+    fs::rename(&temporary_path, path).await?;
+    Ok(())
+}
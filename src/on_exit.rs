@@ -0,0 +1,44 @@
+//! The state the player is left in when mprisqueeze shuts down, so that LMS doesn't keep streaming
+//! to a client that is gone.
+use crate::lms::LmsClient;
+use anyhow::{anyhow, Error, Result};
+use log::info;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnExit {
+    /// The player is left as is
+    #[default]
+    Nothing,
+    Stop,
+    PowerOff,
+}
+
+impl FromStr for OnExit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nothing" => Ok(OnExit::Nothing),
+            "stop" => Ok(OnExit::Stop),
+            "power-off" => Ok(OnExit::PowerOff),
+            _ => Err(anyhow!("Expected nothing, stop or power-off, got {}", s)),
+        }
+    }
+}
+
+impl OnExit {
+    pub async fn apply(self, client: &LmsClient, player_id: String) -> Result<()> {
+        match self {
+            OnExit::Nothing => Ok(()),
+            OnExit::Stop => {
+                info!("Stopping the player before exiting");
+                client.stop(player_id).await
+            }
+            OnExit::PowerOff => {
+                info!("Powering the player off before exiting");
+                client.power(player_id, false).await
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! The `outputs` subcommand. It lists the audio outputs of the player, as printed by `squeezelite
+//! -l`, so that one of them can be given to its `-o` argument.
+use crate::Options;
+use anyhow::{bail, Context, Result};
+use tokio::process::Command;
+
+/// An output as listed by the player
+struct Output {
+    name: String,
+    description: String,
+}
+
+pub async fn run(options: &Options, list_flag: &str) -> Result<()> {
+    let binary = player_binary(&options.player_command)?;
+    let output = Command::new(&binary)
+        .arg(list_flag)
+        .output()
+        .await
+        .with_context(|| format!("Unable to run {}", binary))?;
+    // squeezelite exits with a non zero code after listing the outputs
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let outputs = parse(&stdout);
+    if outputs.is_empty() {
+        bail!(
+            "No output listed by {} {}:\n{}{}",
+            binary,
+            list_flag,
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let width = outputs
+        .iter()
+        .map(|output| output.name.len())
+        .max()
+        .unwrap_or(0);
+    for output in outputs {
+        println!(
+            "{:width$}  {}",
+            output.name,
+            output.description,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// The binary of the player command, the first word of the command when given to the shell
+fn player_binary(player_command: &[String]) -> Result<String> {
+    match player_command
+        .first()
+        .and_then(|command| command.split_whitespace().next())
+    {
+        Some(binary) => Ok(binary.to_string()),
+        None => bail!("No player command given"),
+    }
+}
+
+// The outputs are listed one per line, indented, as `name - description`
+fn parse(stdout: &str) -> Vec<Output> {
+    stdout
+        .lines()
+        .filter(|line| line.starts_with(char::is_whitespace))
+        .filter_map(|line| {
+            let (name, description) = line.trim().split_once(" - ")?;
+            Some(Output {
+                name: name.trim().to_string(),
+                description: description.trim().to_string(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,54 @@
+//! The errors of the server when it can't play a track, for example a dead stream or an
+//! unsupported codec. They are logged, emitted as the `PlaybackError` signal of the custom
+//! interface and optionally shown as desktop notifications.
+use crate::{custom::playback_error, lms::events::Event, notify::notify, preferences::Preferences};
+use anyhow::Result;
+use log::warn;
+use std::sync::Arc;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    watch,
+};
+use zbus::Connection;
+
+pub async fn run(
+    connection: watch::Receiver<Connection>,
+    player_id: String,
+    mut events: broadcast::Receiver<Event>,
+    preferences: Arc<Preferences>,
+) -> Result<()> {
+    loop {
+        let (id, params) = match events.recv().await {
+            Ok(Event::Notification {
+                player_id: Some(id),
+                params,
+            }) => (id, params),
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        if !id.eq_ignore_ascii_case(&player_id) {
+            continue;
+        }
+        let params: Vec<&str> = params.iter().map(String::as_str).collect();
+        let (url, reason) = match &params[..] {
+            ["playlist", "cant_open", url, reason @ ..] => (
+                url.to_string(),
+                Some(reason.join(" "))
+                    .filter(|reason| !reason.is_empty())
+                    .unwrap_or_else(|| "unknown error".to_string()),
+            ),
+            _ => continue,
+        };
+
+        warn!("The server can't play {}: {}", url, reason);
+        let connection = connection.borrow().clone();
+        if let Err(error) = playback_error(&connection, &url, &reason).await {
+            warn!("Unable to emit the PlaybackError signal: {}", error);
+        }
+        if preferences.notify_playback_errors() {
+            if let Err(error) = notify(&connection, "Unable to play", &url).await {
+                warn!("Unable to show the notification: {}", error);
+            }
+        }
+    }
+}
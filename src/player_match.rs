@@ -0,0 +1,62 @@
+//! How the player is found among the ones known by the server. The server may change the name
+//! given to the player, for example its case, or add a suffix when renaming it, see
+//! `--player-match`.
+use crate::lms::Player;
+use anyhow::{anyhow, Error, Result};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NameMatch {
+    #[default]
+    Exact,
+    /// The same name, whatever its case
+    IgnoreCase,
+    /// A name starting with the given one, whatever its case
+    Prefix,
+}
+
+impl FromStr for NameMatch {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(NameMatch::Exact),
+            "ignore-case" => Ok(NameMatch::IgnoreCase),
+            "prefix" => Ok(NameMatch::Prefix),
+            _ => Err(anyhow!("Expected exact, ignore-case or prefix, got {}", s)),
+        }
+    }
+}
+
+/// The player looked for on the servers, by its MAC address when given, by its name otherwise
+#[derive(Clone, Copy, Debug)]
+pub struct Wanted<'a> {
+    pub player_name: &'a str,
+    pub player_mac: Option<&'a str>,
+    pub name_match: NameMatch,
+}
+
+impl Wanted<'_> {
+    pub fn matches(&self, player: &Player) -> bool {
+        if let Some(mac) = self.player_mac {
+            return player.id.eq_ignore_ascii_case(mac);
+        }
+        match self.name_match {
+            NameMatch::Exact => player.name == self.player_name,
+            NameMatch::IgnoreCase => player.name.to_lowercase() == self.player_name.to_lowercase(),
+            NameMatch::Prefix => player
+                .name
+                .to_lowercase()
+                .starts_with(&self.player_name.to_lowercase()),
+        }
+    }
+
+    /// The best of the matching players: the connected ones are preferred, then the ones with
+    /// the exact name
+    pub fn find(&self, players: Vec<Player>) -> Option<Player> {
+        players
+            .into_iter()
+            .filter(|player| self.matches(player))
+            .max_by_key(|player| (player.connected, player.name == self.player_name))
+    }
+}
@@ -0,0 +1,71 @@
+//! The player runs in its own process group, so that the processes it starts, for example when
+//! the player command is a wrapper script, are stopped along with it rather than left playing.
+use anyhow::Result;
+use log::warn;
+use nix::{
+    errno::Errno,
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use std::{io, process::ExitStatus, time::Duration};
+use tokio::{
+    process::{Child, Command},
+    time::timeout,
+};
+
+/// The time given to the player to exit before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct PlayerProcess {
+    child: Child,
+    /// The id of the process group, the one of the player
+    group: Pid,
+}
+
+impl PlayerProcess {
+    pub fn spawn(command: &mut Command) -> io::Result<Self> {
+        let child = command.process_group(0).spawn()?;
+        let id = child
+            .id()
+            .ok_or_else(|| io::Error::other("The player exited straight away"))?;
+        Ok(Self {
+            child,
+            group: Pid::from_raw(id as i32),
+        })
+    }
+
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait().await
+    }
+
+    /// Stop the whole group until it is continued, see `--suspend-when-stopped`
+    pub fn suspend(&self) -> Result<()> {
+        self.signal(Signal::SIGSTOP)
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.signal(Signal::SIGCONT)
+    }
+
+    /// Ask the whole group to terminate, and kill it if the player doesn't exit in time. The
+    /// group may outlive the player, in which case the remaining processes are killed too.
+    pub async fn kill(&mut self) -> Result<()> {
+        self.signal(Signal::SIGTERM)?;
+        // a suspended player would only handle the signal once continued
+        self.resume()?;
+        if timeout(STOP_TIMEOUT, self.child.wait()).await.is_err() {
+            warn!("The player didn't exit in time, killing it");
+        }
+        self.signal(Signal::SIGKILL)?;
+        self.child.wait().await?;
+        Ok(())
+    }
+
+    // The group is gone once all of its processes have exited
+    fn signal(&self, signal: Signal) -> Result<()> {
+        match killpg(self.group, signal) {
+            Ok(()) | Err(Errno::ESRCH) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! The `queue` subcommands. They work with the playlist of the player on the server directly,
+//! without a running instance: `export` writes it to a playlist file, `import` reads a playlist
+//! file into it. The files can be opened over MPRIS as well.
+use crate::lms::{all_pages, Enqueue, LmsClient, QueuedTrack};
+use anyhow::{bail, Context, Result};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// The number of tracks fetched at once
+const PAGE: u64 = 100;
+
+/// The characters escaped in the path of a file URL
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Write the playlist of the player to an extended M3U file, with the title and the duration of
+/// each track. Return the number of tracks written.
+pub async fn export(client: &LmsClient, player_id: &str, path: &Path) -> Result<usize> {
+    let tracks = all_pages(|start| client.get_queue(player_id.to_string(), start, PAGE)).await?;
+    let file =
+        File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    write_m3u(&mut writer, &tracks)
+        .and_then(|_| writer.flush())
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    Ok(tracks.len())
+}
+
+/// The file is written in UTF-8, whatever its extension is
+fn write_m3u(writer: &mut impl Write, tracks: &[QueuedTrack]) -> io::Result<()> {
+    writeln!(writer, "#EXTM3U")?;
+    for track in tracks {
+        // -1 stands for an unknown duration, such as the one of a stream
+        let duration = track
+            .duration
+            .filter(|duration| *duration > 0.0)
+            .map_or(-1, |duration| duration.round() as i64);
+        let name = if track.artist.is_empty() {
+            track.title.clone()
+        } else {
+            format!("{} - {}", track.artist, track.title)
+        };
+        writeln!(writer, "#EXTINF:{},{}", duration, name.replace('\n', " "))?;
+        writeln!(writer, "{}", track.url)?;
+    }
+    Ok(())
+}
+
+/// Add the entries of a playlist file to the playlist of the player, or replace the playlist with
+/// them and play. Return the number of entries.
+pub async fn import(
+    client: &LmsClient,
+    player_id: &str,
+    path: &Path,
+    replace: bool,
+) -> Result<usize> {
+    let urls = read(path)?;
+    enqueue(client, player_id, &urls, replace).await?;
+    Ok(urls.len())
+}
+
+/// Put the URLs in the playlist of the player, one command each
+pub async fn enqueue(
+    client: &LmsClient,
+    player_id: &str,
+    urls: &[String],
+    replace: bool,
+) -> Result<()> {
+    for (index, url) in urls.iter().enumerate() {
+        let enqueue = if replace && index == 0 {
+            Enqueue::Load
+        } else {
+            Enqueue::Add
+        };
+        client
+            .enqueue_url(player_id.to_string(), url.clone(), enqueue)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Read the entries of an M3U or a PLS file as URLs. The paths are relative to the directory of
+/// the file, and sent as file URLs: they have to exist on the server as well.
+pub fn read(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    // the older M3U files are not in UTF-8, the names with accents are mangled
+    let content = String::from_utf8_lossy(&content);
+    let entries = if is_pls(path) {
+        parse_pls(&content)
+    } else {
+        parse_m3u(&content)
+    };
+    if entries.is_empty() {
+        bail!("No entry in {}", path.display());
+    }
+    let path = path.canonicalize()?;
+    let directory = path.parent().unwrap_or(Path::new("/"));
+    Ok(entries
+        .into_iter()
+        .map(|entry| to_url(entry, directory))
+        .collect())
+}
+
+/// The path of a playlist file given as a file URI, such as the ones given to `OpenUri`
+pub fn from_uri(uri: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(
+        percent_decode_str(uri.strip_prefix("file://")?)
+            .decode_utf8_lossy()
+            .into_owned(),
+    );
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    matches!(extension.as_str(), "m3u" | "m3u8" | "pls").then_some(path)
+}
+
+fn is_pls(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pls"))
+}
+
+/// The lines which are not comments nor directives such as `#EXTINF`
+fn parse_m3u(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// The `FileN` keys, in the order of their number
+fn parse_pls(content: &str) -> Vec<&str> {
+    let mut entries: Vec<(u32, &str)> = content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let number = key.strip_prefix("File")?.parse().ok()?;
+            Some((number, value.trim()))
+        })
+        .collect();
+    entries.sort_by_key(|(number, _)| *number);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+fn to_url(entry: &str, directory: &Path) -> String {
+    if entry.contains("://") {
+        return entry.to_string();
+    }
+    // an absolute path replaces the directory
+    let path = directory.join(entry);
+    format!(
+        "file://{}",
+        utf8_percent_encode(&path.to_string_lossy(), PATH)
+    )
+}
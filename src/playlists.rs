@@ -0,0 +1,175 @@
+//! The `org.mpris.MediaPlayer2.Playlists` D-Bus interface. It exposes the playlists saved on the
+//! server, which can be loaded on the player.
+use crate::{
+    collate,
+    lms::{all_pages, LmsClient, SavedPlaylist},
+    mpris::{bounded, to_fdo_error, trace},
+};
+use std::{convert::TryFrom, sync::Arc};
+use zbus::{
+    fdo, interface,
+    zvariant::{ObjectPath, OwnedObjectPath},
+};
+
+/// The prefix of the object paths identifying the playlists, followed by their id on the server
+const PLAYLIST_PATH: &str = "/org/mprisqueeze/Playlist";
+
+/// LMS only sorts the playlists by name, and doesn't tell when they were modified
+const ALPHABETICAL: &str = "Alphabetical";
+
+/// The id, the name and the icon of a playlist
+type Playlist = (OwnedObjectPath, String, String);
+
+pub struct MprisPlaylists {
+    pub client: Arc<LmsClient>,
+    pub player_id: String,
+    /// Keep the order and the spelling of the names given by the server
+    pub server_order: bool,
+}
+
+/// The number of playlists fetched at once when they are sorted here
+const PAGE: u64 = 500;
+
+impl MprisPlaylists {
+    /// A page of the playlists sorted by name. The reversed pages are taken from the end of the
+    /// list.
+    async fn page(
+        &self,
+        index: u32,
+        max_count: u32,
+        reverse: bool,
+    ) -> anyhow::Result<Vec<SavedPlaylist>> {
+        let (index, max_count) = (u64::from(index), u64::from(max_count));
+        if !self.server_order {
+            return self.sorted_page(index, max_count, reverse).await;
+        }
+        if !reverse {
+            return Ok(self.client.get_playlists(index, max_count).await?.1);
+        }
+        let (total, _) = self.client.get_playlists(0, 0).await?;
+        let end = total.saturating_sub(index);
+        let start = end.saturating_sub(max_count);
+        let mut playlists = self.client.get_playlists(start, end - start).await?.1;
+        playlists.reverse();
+        Ok(playlists)
+    }
+
+    /// A page of the playlists sorted here, which needs all of them
+    async fn sorted_page(
+        &self,
+        index: u64,
+        max_count: u64,
+        reverse: bool,
+    ) -> anyhow::Result<Vec<SavedPlaylist>> {
+        let mut playlists = all_pages(|start| self.client.get_playlists(start, PAGE)).await?;
+        for playlist in playlists.iter_mut() {
+            playlist.name = collate::normalize(&playlist.name);
+        }
+        collate::sort_by_name(&mut playlists, |playlist| &playlist.name);
+        if reverse {
+            playlists.reverse();
+        }
+        Ok(playlists
+            .into_iter()
+            .skip(index as usize)
+            .take(max_count as usize)
+            .collect())
+    }
+}
+
+fn to_playlist(playlist: SavedPlaylist) -> fdo::Result<Playlist> {
+    let path = ObjectPath::try_from(format!("{}/{}", PLAYLIST_PATH, playlist.id))
+        .map_err(|error| fdo::Error::Failed(error.to_string()))?;
+    Ok((path.into(), playlist.name, String::new()))
+}
+
+fn to_playlist_id(path: &ObjectPath<'_>) -> fdo::Result<u64> {
+    path.as_str()
+        .strip_prefix(PLAYLIST_PATH)
+        .and_then(|id| id.strip_prefix('/'))
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown playlist {}", path)))
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Playlists")]
+impl MprisPlaylists {
+    async fn activate_playlist(&self, playlist_id: ObjectPath<'_>) -> fdo::Result<()> {
+        bounded(
+            &self.client,
+            format!("ActivatePlaylist({})", playlist_id),
+            async {
+                let id = to_playlist_id(&playlist_id)?;
+                self.client
+                    .load_playlist(self.player_id.clone(), id)
+                    .await
+                    .map_err(to_fdo_error)
+            },
+        )
+        .await
+    }
+
+    async fn get_playlists(
+        &self,
+        index: u32,
+        max_count: u32,
+        order: String,
+        reverse_order: bool,
+    ) -> fdo::Result<Vec<Playlist>> {
+        bounded(
+            &self.client,
+            format!(
+                "GetPlaylists({}, {}, {}, {})",
+                index, max_count, order, reverse_order
+            ),
+            async {
+                if order != ALPHABETICAL {
+                    return Err(fdo::Error::NotSupported(format!(
+                        "Unsupported ordering {}, only {} is",
+                        order, ALPHABETICAL
+                    )));
+                }
+                self.page(index, max_count, reverse_order)
+                    .await
+                    .map_err(to_fdo_error)?
+                    .into_iter()
+                    .map(to_playlist)
+                    .collect()
+            },
+        )
+        .await
+    }
+
+    #[zbus(property)]
+    async fn playlist_count(&self) -> fdo::Result<u32> {
+        bounded(&self.client, "PlaylistCount", async {
+            let (total, _) = self
+                .client
+                .get_playlists(0, 0)
+                .await
+                .map_err(to_fdo_error)?;
+            Ok(u32::try_from(total).unwrap_or(u32::MAX))
+        })
+        .await
+    }
+
+    #[zbus(property)]
+    async fn orderings(&self) -> Vec<String> {
+        trace("Orderings", async { vec![ALPHABETICAL.to_string()] }).await
+    }
+
+    /// The playlist loaded on the player is not tracked
+    #[zbus(property)]
+    async fn active_playlist(&self) -> (bool, Playlist) {
+        trace("ActivePlaylist", async {
+            (
+                false,
+                (
+                    ObjectPath::from_static_str_unchecked("/").into(),
+                    String::new(),
+                    String::new(),
+                ),
+            )
+        })
+        .await
+    }
+}
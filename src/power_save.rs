@@ -0,0 +1,44 @@
+//! Suspend the player process while the player is stopped, with `--suspend-when-stopped`, to cut
+//! the idle CPU usage on battery-powered machines. The process is stopped with `SIGSTOP` once the
+//! player has been stopped for a while, and continued with `SIGCONT` as soon as the playback is
+//! requested over MPRIS or the player leaves the stopped mode. The MPRIS interfaces stay responsive
+//! meanwhile as they only talk to LMS.
+use crate::{lms::Mode, state::PlayerState};
+use anyhow::Result;
+use log::info;
+use std::time::Duration;
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+    time::sleep,
+};
+
+/// Send `true` to suspend the player process, and `false` to continue it
+pub async fn run(
+    mut state: watch::Receiver<PlayerState>,
+    after: Duration,
+    mut resume: mpsc::UnboundedReceiver<()>,
+    suspend: mpsc::UnboundedSender<bool>,
+) -> Result<()> {
+    loop {
+        state.wait_for(|state| state.mode == Mode::Stop).await?;
+        select! {
+            _ = sleep(after) => (),
+            result = state.wait_for(|state| state.mode != Mode::Stop) => {
+                result.map(|_| ())?;
+                continue;
+            }
+            // the playback is about to start
+            Some(()) = resume.recv() => continue,
+        }
+
+        info!("Suspending the player, stopped for {:?}", after);
+        suspend.send(true)?;
+        select! {
+            result = state.wait_for(|state| state.mode != Mode::Stop) => result.map(|_| ())?,
+            Some(()) = resume.recv() => (),
+        }
+        info!("Continuing the player");
+        suspend.send(false)?;
+    }
+}
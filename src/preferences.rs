@@ -0,0 +1,130 @@
+//! The settings of the bridge which can be changed over D-Bus: the ceiling of the volume, the
+//! notifications, and the shuffle and repeat modes last set by a client. They are saved to
+//! `$XDG_STATE_HOME/mprisqueeze/preferences.toml` when changed, and restored on the next start
+//! unless they are given on the command line.
+use crate::lms::{Repeat, Shuffle};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The volume is not capped by default
+const MAX_VOLUME: u64 = 100;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Values {
+    pub max_volume: Option<u64>,
+    pub notify_server_events: Option<bool>,
+    pub notify_playback_errors: Option<bool>,
+    /// Set back on the player at startup
+    pub shuffle: Option<Shuffle>,
+    /// Set back on the player at startup
+    pub repeat: Option<Repeat>,
+}
+
+#[derive(Debug)]
+pub struct Preferences {
+    /// `None` when the state directory can't be found, the changes are then lost on exit
+    path: Option<PathBuf>,
+    values: Mutex<Values>,
+}
+
+impl Preferences {
+    /// Read the preferences saved by a previous run. A missing or invalid file gives the default
+    /// ones.
+    pub fn load(instance: Option<&str>) -> Self {
+        let path = default_path(instance);
+        let values = match path.as_deref().map(read) {
+            Some(Ok(Some(values))) => {
+                info!("Restoring the preferences {:?}", values);
+                values
+            }
+            Some(Ok(None)) | None => Values::default(),
+            Some(Err(error)) => {
+                warn!("Ignoring the saved preferences: {:#}", error);
+                Values::default()
+            }
+        };
+        Self {
+            path,
+            values: Mutex::new(values),
+        }
+    }
+
+    pub fn values(&self) -> Values {
+        self.values.lock().unwrap().clone()
+    }
+
+    /// Fill in the settings in effect at startup, without saving them
+    pub fn resolve(&self, resolve: impl FnOnce(&mut Values)) {
+        resolve(&mut self.values.lock().unwrap());
+    }
+
+    /// Change the preferences and save them for the next run
+    pub fn update(&self, change: impl FnOnce(&mut Values)) {
+        let values = {
+            let mut values = self.values.lock().unwrap();
+            change(&mut values);
+            values.clone()
+        };
+        let Some(ref path) = self.path else {
+            return;
+        };
+        match write(path, &values) {
+            Ok(()) => debug!("Saved the preferences to {:?}", path),
+            Err(error) => warn!("Unable to save the preferences: {:#}", error),
+        }
+    }
+
+    pub fn max_volume(&self) -> u64 {
+        self.values().max_volume.unwrap_or(MAX_VOLUME)
+    }
+
+    pub fn notify_server_events(&self) -> bool {
+        self.values().notify_server_events.unwrap_or_default()
+    }
+
+    pub fn notify_playback_errors(&self) -> bool {
+        self.values().notify_playback_errors.unwrap_or_default()
+    }
+}
+
+/// `$XDG_STATE_HOME/mprisqueeze/preferences.toml`, falling back to `~/.local/state`. The directory
+/// of an instance is suffixed with its name.
+fn default_path(instance: Option<&str>) -> Option<PathBuf> {
+    let name = match instance {
+        Some(instance) => format!("mprisqueeze-{}", instance),
+        None => "mprisqueeze".to_string(),
+    };
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .map(|state| state.join(name).join("preferences.toml"))
+}
+
+fn read(path: &Path) -> Result<Option<Values>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path).with_context(|| format!("Unable to read {:?}", path))?;
+    toml::from_str(&content)
+        .map(Some)
+        .map_err(|error| anyhow!("Invalid preferences file {:?}: {}", path, error))
+}
+
+/// The file is replaced at once, so that it is never left half written
+fn write(path: &Path, values: &Values) -> Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Unable to create {:?}", directory))?;
+    }
+    let temporary = path.with_extension("toml.tmp");
+    fs::write(&temporary, toml::to_string(values)?)
+        .with_context(|| format!("Unable to write {:?}", temporary))?;
+    fs::rename(&temporary, path).with_context(|| format!("Unable to write {:?}", path))
+}
@@ -0,0 +1,109 @@
+//! The duration of the playlist of the player and the time it ends at, for the `queue status`
+//! subcommand and the queue properties of the custom interface. The streams count for nothing, as
+//! their duration is unknown.
+use crate::lms::LmsClient;
+use anyhow::Result;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The number of tracks fetched at once
+const PAGE: u64 = 100;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueueTime {
+    /// The number of tracks in the playlist
+    pub length: u64,
+    /// The position of the current track, starting at 1, 0 when the playlist is empty
+    pub position: u64,
+    /// The duration of the whole playlist
+    pub total: Duration,
+    /// The time left until the end of the playlist, from the current position in the track
+    pub remaining: Duration,
+}
+
+impl QueueTime {
+    pub async fn fetch(client: &LmsClient, player_id: &str) -> Result<Self> {
+        let length = client.get_track_count(player_id.to_string()).await?;
+        if length == 0 {
+            return Ok(Self::default());
+        }
+        let index = client.get_index(player_id.to_string()).await?;
+        let mut durations = Vec::new();
+        while (durations.len() as u64) < length {
+            let page = client
+                .get_durations(player_id.to_string(), durations.len() as u64, PAGE)
+                .await?;
+            if page.is_empty() {
+                break;
+            }
+            durations.extend(page);
+        }
+        let total = durations.iter().flatten().sum();
+        let elapsed = client.get_time(player_id.to_string()).await?;
+        let remaining = durations
+            .iter()
+            .skip(index as usize)
+            .flatten()
+            .sum::<Duration>()
+            .saturating_sub(elapsed);
+        Ok(Self {
+            length,
+            position: index + 1,
+            total,
+            remaining,
+        })
+    }
+
+    /// The time the playlist ends at when it plays from now on without interruption
+    pub fn end(&self) -> SystemTime {
+        SystemTime::now() + self.remaining
+    }
+}
+
+/// A duration as `h:mm:ss`
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// An instant as an ISO 8601 date and time in UTC, such as `2024-12-31T23:59:59Z`
+pub fn format_utc(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The date of a number of days since the Unix epoch, in the proleptic Gregorian calendar. See
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,84 @@
+//! The resources given to the player process, so that decoding the audio doesn't compete with the
+//! foreground work on small machines. The player command is run through the usual tools: `nice`,
+//! `ionice`, `taskset` and `systemd-run`.
+use anyhow::{anyhow, Error};
+use std::str::FromStr;
+
+/// The I/O scheduling class, see `ionice(1)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    /// Only served when no other process needs the disk
+    Idle,
+}
+
+impl FromStr for IoClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "realtime" => Ok(IoClass::Realtime),
+            "best-effort" => Ok(IoClass::BestEffort),
+            "idle" => Ok(IoClass::Idle),
+            _ => Err(anyhow!("Expected realtime, best-effort or idle, got {}", s)),
+        }
+    }
+}
+
+impl IoClass {
+    fn number(self) -> u8 {
+        match self {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Resources {
+    /// Between -20, the highest priority, and 19
+    pub nice: Option<i8>,
+    pub io_class: Option<IoClass>,
+    /// A list of CPUs in the format of `taskset(1)`, such as `0,2-3`
+    pub cpus: Option<String>,
+    /// The systemd slice of the user manager to run the player in
+    pub slice: Option<String>,
+}
+
+impl Resources {
+    /// Prefix the player command with the tools applying the resource controls
+    pub fn wrap(&self, command: String, args: Vec<String>) -> (String, Vec<String>) {
+        let mut wrappers: Vec<Vec<String>> = Vec::new();
+        if let Some(ref slice) = self.slice {
+            wrappers.push(vec![
+                "systemd-run".to_string(),
+                "--user".to_string(),
+                "--scope".to_string(),
+                "--quiet".to_string(),
+                format!("--slice={}", slice),
+                "--".to_string(),
+            ]);
+        }
+        if let Some(ref cpus) = self.cpus {
+            wrappers.push(vec!["taskset".to_string(), "-c".to_string(), cpus.clone()]);
+        }
+        if let Some(io_class) = self.io_class {
+            wrappers.push(vec![
+                "ionice".to_string(),
+                "-c".to_string(),
+                io_class.number().to_string(),
+            ]);
+        }
+        if let Some(nice) = self.nice {
+            wrappers.push(vec!["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        }
+
+        let mut command_line: Vec<String> = wrappers.into_iter().flatten().collect();
+        command_line.push(command);
+        command_line.extend(args);
+        let command = command_line.remove(0);
+        (command, command_line)
+    }
+}
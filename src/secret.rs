@@ -0,0 +1,79 @@
+//! Look up the password of LMS in the freedesktop Secret Service, as provided by GNOME Keyring or
+//! KWallet. The password is expected to be stored with the attributes `service` set to
+//! `mprisqueeze` and `username` set to the LMS username, for example with:
+//!
+//! ```bash
+//! $ secret-tool store --label=mprisqueeze service mprisqueeze username <username>
+//! ```
+use crate::lms::Password;
+use anyhow::{anyhow, bail, Result};
+use log::info;
+use std::collections::HashMap;
+use zbus::{
+    proxy,
+    zvariant::{OwnedObjectPath, OwnedValue, Type, Value},
+    Connection,
+};
+
+#[derive(Debug, serde::Deserialize, Type)]
+struct Secret {
+    #[allow(dead_code)]
+    session: OwnedObjectPath,
+    #[allow(dead_code)]
+    parameters: Vec<u8>,
+    value: Vec<u8>,
+    #[allow(dead_code)]
+    content_type: String,
+}
+
+#[proxy(
+    interface = "org.freedesktop.Secret.Service",
+    default_service = "org.freedesktop.secrets",
+    default_path = "/org/freedesktop/secrets"
+)]
+trait Service {
+    fn open_session(
+        &self,
+        algorithm: &str,
+        input: &Value<'_>,
+    ) -> zbus::Result<(OwnedValue, OwnedObjectPath)>;
+
+    fn search_items(
+        &self,
+        attributes: HashMap<&str, &str>,
+    ) -> zbus::Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>)>;
+
+    fn get_secrets(
+        &self,
+        items: &[OwnedObjectPath],
+        session: &OwnedObjectPath,
+    ) -> zbus::Result<HashMap<OwnedObjectPath, Secret>>;
+}
+
+pub async fn lookup_password(username: &str) -> Result<Password> {
+    info!(
+        "Looking up the password of {} in the Secret Service",
+        username
+    );
+    let connection = Connection::session().await?;
+    let service = ServiceProxy::new(&connection).await?;
+
+    let attributes = HashMap::from([("service", "mprisqueeze"), ("username", username)]);
+    let (unlocked, locked) = service.search_items(attributes).await?;
+    let Some(item) = unlocked.into_iter().next() else {
+        if locked.is_empty() {
+            bail!("No password found in the Secret Service for {}", username);
+        }
+        bail!("The password for {} is in a locked keyring", username);
+    };
+
+    // the secret is transferred in plain text, which is fine on the session bus
+    let (_, session) = service.open_session("plain", &Value::from("")).await?;
+    let mut secrets = service
+        .get_secrets(std::slice::from_ref(&item), &session)
+        .await?;
+    let secret = secrets
+        .remove(&item)
+        .ok_or_else(|| anyhow!("The Secret Service didn't return the password"))?;
+    Ok(Password(String::from_utf8(secret.value)?))
+}
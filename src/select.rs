@@ -0,0 +1,71 @@
+//! The choice of the player at startup among the ones known to the server, on the terminal. It is
+//! offered with `--select-player`, or when no player name is given and the server knows several
+//! players.
+use crate::lms::Player;
+use anyhow::Result;
+use std::io::{self, BufRead, IsTerminal, Write};
+use tokio::task::spawn_blocking;
+
+/// Whether the user can be asked, mprisqueeze may run as a service
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Ask the user to choose a player, `None` to keep the default name. The connected players are
+/// listed but can't be chosen when `disconnected_only` is set, as when starting the bridge.
+pub async fn choose(
+    players: Vec<Player>,
+    disconnected_only: bool,
+    default: String,
+) -> Result<Option<String>> {
+    spawn_blocking(move || prompt(&players, disconnected_only, &default)).await?
+}
+
+fn prompt(players: &[Player], disconnected_only: bool, default: &str) -> Result<Option<String>> {
+    let mut stderr = io::stderr();
+    writeln!(stderr, "Players known to the server:")?;
+    for (index, player) in players.iter().enumerate() {
+        let connected = match (player.connected, disconnected_only) {
+            (true, true) => ", connected, not available",
+            (true, false) => ", connected",
+            (false, _) => "",
+        };
+        writeln!(
+            stderr,
+            "  {}) {} ({}{})",
+            index + 1,
+            player.name,
+            player.id,
+            connected
+        )?;
+    }
+    loop {
+        write!(
+            stderr,
+            "Choose a player [1-{}], or press enter for {}: ",
+            players.len(),
+            default
+        )?;
+        stderr.flush()?;
+        let mut line = String::new();
+        // end of input
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        match line.parse::<usize>() {
+            Ok(choice) if (1..=players.len()).contains(&choice) => {
+                let player = &players[choice - 1];
+                if disconnected_only && player.connected {
+                    writeln!(stderr, "Player {} is already connected", player.name)?;
+                } else {
+                    return Ok(Some(player.name.clone()));
+                }
+            }
+            _ => writeln!(stderr, "Invalid choice {}", line)?,
+        }
+    }
+}
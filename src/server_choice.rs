@@ -0,0 +1,120 @@
+//! The choice of the server when several of them reply to the discovery, in homes with more than
+//! one. Rather than the first reply, the servers already knowing the player are preferred, then
+//! the fastest to answer. The remaining ties are broken on the address, so that the same server is
+//! chosen from one start to the next.
+use crate::{
+    discover::Reply,
+    ip_family::IpFamily,
+    lms::{flavor::ServerFlavor, Credentials, LmsClient, Timeouts},
+    player_match::Wanted,
+};
+use futures_util::future::join_all;
+use log::{info, warn};
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+/// The time a server has to answer the probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a server answered to the probe, `None` when it didn't
+struct Probe {
+    knows_player: bool,
+    latency: Duration,
+}
+
+/// Probe the servers at once and return the preferred one
+pub async fn choose(
+    replies: Vec<Reply>,
+    wanted: Wanted<'_>,
+    credentials: Option<Credentials>,
+    flavor: ServerFlavor,
+    family: Option<IpFamily>,
+) -> Reply {
+    let probes = join_all(
+        replies
+            .iter()
+            .map(|reply| probe(reply, &wanted, credentials.clone(), flavor, family)),
+    )
+    .await;
+    let mut candidates: Vec<(Reply, Option<Probe>)> = replies.into_iter().zip(probes).collect();
+    candidates.sort_by(|(reply, probe), (other_reply, other_probe)| {
+        let key = |probe: &Option<Probe>| {
+            probe
+                .as_ref()
+                .map(|probe| (!probe.knows_player, probe.latency))
+        };
+        // the servers which didn't answer come last
+        (probe.is_none(), key(probe), &reply.hostname, reply.port).cmp(&(
+            other_probe.is_none(),
+            key(other_probe),
+            &other_reply.hostname,
+            other_reply.port,
+        ))
+    });
+    let (reply, probe) = candidates.swap_remove(0);
+    match probe {
+        Some(probe) => info!(
+            "Choosing the server {}:{} among the discovered ones, it answered in {:?} and {} the player",
+            reply.hostname,
+            reply.port,
+            probe.latency,
+            if probe.knows_player { "knows" } else { "doesn't know" }
+        ),
+        None => warn!(
+            "None of the discovered servers answered, choosing {}:{}",
+            reply.hostname, reply.port
+        ),
+    }
+    reply
+}
+
+async fn probe(
+    reply: &Reply,
+    wanted: &Wanted<'_>,
+    credentials: Option<Credentials>,
+    flavor: ServerFlavor,
+    family: Option<IpFamily>,
+) -> Option<Probe> {
+    let (client, mut recv) = LmsClient::new(
+        reply.hostname.clone(),
+        reply.port,
+        credentials,
+        flavor,
+        Timeouts::default(),
+        family,
+        None,
+    );
+    let started = Instant::now();
+    let players = match timeout(PROBE_TIMEOUT, client.get_players()).await {
+        Ok(Ok(players)) => players,
+        Ok(Err(error)) => {
+            // the actual cause of the failure is sent to the error channel
+            let error = recv.try_recv().unwrap_or(error);
+            warn!(
+                "The server {}:{} can't be probed: {:#}",
+                reply.hostname, reply.port, error
+            );
+            return None;
+        }
+        Err(_) => {
+            warn!(
+                "The server {}:{} didn't answer the probe within {:?}",
+                reply.hostname, reply.port, PROBE_TIMEOUT
+            );
+            return None;
+        }
+    };
+    let latency = started.elapsed();
+    let knows_player = players.iter().any(|player| wanted.matches(player));
+    info!(
+        "The server {}:{} answered in {:?}, with {} players",
+        reply.hostname,
+        reply.port,
+        latency,
+        players.len()
+    );
+    Some(Probe {
+        knows_player,
+        latency,
+    })
+}
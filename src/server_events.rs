@@ -0,0 +1,65 @@
+//! Show the events of the server as desktop notifications: the end of a library rescan, the
+//! changes of the sync groups and the loss of the connection to the server. They can be turned on
+//! and off over D-Bus.
+use crate::{lms::events::Event, notify::notify, preferences::Preferences};
+use anyhow::Result;
+use log::warn;
+use std::sync::Arc;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    watch,
+};
+use zbus::Connection;
+
+pub async fn run(
+    connection: watch::Receiver<Connection>,
+    mut events: broadcast::Receiver<Event>,
+    preferences: Arc<Preferences>,
+) -> Result<()> {
+    // only the reconnections are worth a notification
+    let mut disconnected = false;
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let message = match event {
+            Event::Connected if disconnected => {
+                disconnected = false;
+                Some(("Server available".to_string(), String::new()))
+            }
+            Event::Connected => None,
+            Event::Disconnected if !disconnected => {
+                disconnected = true;
+                Some((
+                    "Server unavailable".to_string(),
+                    "The server is restarting or unreachable".to_string(),
+                ))
+            }
+            Event::Disconnected => None,
+            Event::Notification { player_id, params } => {
+                let params: Vec<&str> = params.iter().map(String::as_str).collect();
+                match (player_id, &params[..]) {
+                    (None, ["rescan", "done", ..]) => {
+                        Some(("Library rescan finished".to_string(), String::new()))
+                    }
+                    (Some(player_id), ["sync", "-"]) => {
+                        Some(("Player unsynced".to_string(), player_id))
+                    }
+                    (Some(player_id), ["sync", other, ..]) => Some((
+                        "Players synced".to_string(),
+                        format!("{} with {}", player_id, other),
+                    )),
+                    _ => None,
+                }
+            }
+        };
+        if let Some((summary, body)) = message.filter(|_| preferences.notify_server_events()) {
+            let connection = connection.borrow().clone();
+            if let Err(error) = notify(&connection, &summary, &body).await {
+                warn!("Unable to show the notification: {}", error);
+            }
+        }
+    }
+}
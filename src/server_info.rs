@@ -0,0 +1,58 @@
+//! The `org.mprisqueeze.Server1` D-Bus interface. It tells which LMS the player is connected to,
+//! for the users running several instances against different servers.
+use crate::{
+    lms::LmsClient,
+    mpris::{bounded, to_fdo_error, trace},
+};
+use reqwest::Url;
+use std::sync::Arc;
+use zbus::{fdo, interface};
+
+pub struct ServerInfo {
+    pub client: Arc<LmsClient>,
+}
+
+#[interface(name = "org.mprisqueeze.Server1")]
+impl ServerInfo {
+    /// The name of the library set on the server, its hostname otherwise
+    #[zbus(property)]
+    async fn name(&self) -> fdo::Result<String> {
+        bounded(&self.client, "Name", async {
+            let name = self.client.get_library_name().await.map_err(to_fdo_error)?;
+            Ok(name.unwrap_or_else(|| {
+                Url::parse(&self.client.base_url())
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .unwrap_or_default()
+            }))
+        })
+        .await
+    }
+
+    #[zbus(property)]
+    async fn version(&self) -> fdo::Result<String> {
+        bounded(&self.client, "Version", async {
+            self.client.get_version().await.map_err(to_fdo_error)
+        })
+        .await
+    }
+
+    /// Empty when the server doesn't tell, such as Music Assistant
+    #[zbus(property)]
+    async fn uuid(&self) -> fdo::Result<String> {
+        bounded(&self.client, "Uuid", async {
+            self.client
+                .get_uuid()
+                .await
+                .map(Option::unwrap_or_default)
+                .map_err(to_fdo_error)
+        })
+        .await
+    }
+
+    /// The address of the web interface, which follows the server when it moves
+    #[zbus(property)]
+    async fn url(&self) -> String {
+        trace("Url", async { format!("{}/", self.client.base_url()) }).await
+    }
+}
@@ -0,0 +1,88 @@
+//! Keep the interfaces served when the session bus restarts. The connection is checked
+//! periodically, and a new one is made with a backoff when it is lost. The consumers of the
+//! connection get the new one through a watch channel.
+use crate::{
+    custom::Requests,
+    lms::{LmsClient, Player},
+    mpris::{start_dbus_server, Settings},
+    state::{Activity, PlayerState},
+};
+use anyhow::Result;
+use log::{info, warn};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::watch, time::sleep};
+use zbus::{fdo::DBusProxy, Connection};
+
+/// How often the connection is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The delay between two attempts to reconnect, doubled after each failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// What is needed to serve the interfaces on a new connection
+pub struct Server {
+    pub client: Arc<LmsClient>,
+    pub player: Player,
+    pub activity: Arc<Activity>,
+    pub state: watch::Receiver<PlayerState>,
+    pub settings: Settings,
+    pub requests: Requests,
+}
+
+impl Server {
+    pub async fn start(&self) -> Result<Connection> {
+        // the player may have been renamed since it was found
+        let player = Player {
+            name: self.settings.player_name.borrow().clone(),
+            ..self.player.clone()
+        };
+        start_dbus_server(
+            self.client.clone(),
+            player,
+            self.activity.clone(),
+            self.state.clone(),
+            self.settings.clone(),
+            self.requests.clone(),
+        )
+        .await
+    }
+}
+
+/// Check the connection and replace it when it is lost
+pub async fn supervise(server: Server, connection: watch::Sender<Connection>) -> Result<()> {
+    loop {
+        sleep(CHECK_INTERVAL).await;
+        let current = connection.borrow().clone();
+        if let Err(error) = ping(&current).await {
+            warn!("Lost the session bus: {}", error);
+            // release the names in case the connection is still up after all
+            let _ = current.close().await;
+            let lost = connection.send_replace(reconnect(&server).await);
+            drop(lost);
+            info!("Reconnected to the session bus");
+        }
+    }
+}
+
+async fn ping(connection: &Connection) -> zbus::Result<()> {
+    DBusProxy::new(connection).await?.get_id().await?;
+    Ok(())
+}
+
+async fn reconnect(server: &Server) -> Connection {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match server.start().await {
+            Ok(connection) => return connection,
+            Err(error) => {
+                warn!(
+                    "Unable to reconnect to the session bus, retrying in {:?}: {:#}",
+                    backoff, error
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! The state of the player as last seen on LMS. It is refreshed by a background poller which
+//! notifies the MPRIS clients of the changes. The poller slows down when nobody is listening, and
+//! refreshes the state right away when the server notifies a change made by another controller.
+use crate::{
+    custom::{server_recovered, stale_changed},
+    lms::{events::Event, LmsClient, Mode, Repeat, Shuffle, SyncedPlayer, TrackDetails},
+    mpris::properties_changed,
+};
+use anyhow::{anyhow, Error, Result};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::{
+    future::Future,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch, Semaphore,
+    },
+    time::sleep,
+    try_join,
+};
+use zbus::Connection;
+
+/// A client that queried a property within this window is considered active
+const ACTIVITY_WINDOW: Duration = Duration::from_secs(30);
+
+/// The changes following an emission within this window, such as when skipping tracks quickly or
+/// dragging the volume, are gathered into the next one
+const COALESCE_WINDOW: Duration = Duration::from_millis(250);
+
+/// The delay before probing a server which doesn't answer, doubled after each probe up to the
+/// maximum
+const PROBE_DELAY: Duration = Duration::from_secs(1);
+const MAX_PROBE_DELAY: Duration = Duration::from_secs(60);
+
+/// How much metadata is fetched about the current track on each poll, see `--metadata`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MetadataProfile {
+    /// The artist and the title
+    Minimal,
+    /// The album and the cover too
+    #[default]
+    Standard,
+    /// The genre, the track number and the duration too
+    Full,
+}
+
+impl FromStr for MetadataProfile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minimal" => Ok(MetadataProfile::Minimal),
+            "standard" => Ok(MetadataProfile::Standard),
+            "full" => Ok(MetadataProfile::Full),
+            _ => Err(anyhow!("Expected minimal, standard or full, got {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PlayerState {
+    pub mode: Mode,
+    pub shuffle: Shuffle,
+    pub repeat: Repeat,
+    /// Between 0 and 100, negative when muted
+    pub volume: i64,
+    pub track: Option<Track>,
+    /// The name of the saved playlist loaded on the player
+    pub playlist_name: Option<String>,
+    /// Filling the buffer before playing, for example when a stream starts
+    pub buffering: bool,
+    /// The other players synced with the player
+    pub sync_group: Vec<SyncedPlayer>,
+    #[serde(skip)]
+    pub position: Option<Position>,
+}
+
+// The position moves constantly while playing, it is not considered as a change of the state
+impl PartialEq for PlayerState {
+    fn eq(&self, other: &Self) -> bool {
+        self.mode == other.mode
+            && self.shuffle == other.shuffle
+            && self.repeat == other.repeat
+            && self.volume == other.volume
+            && self.track == other.track
+            && self.playlist_name == other.playlist_name
+            && self.buffering == other.buffering
+            && self.sync_group == other.sync_group
+    }
+}
+
+/// The elapsed time in the current track, as reported by LMS at a given instant
+#[derive(Clone, Copy, Debug)]
+pub struct Position {
+    pub elapsed: Duration,
+    pub at: Instant,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Track {
+    /// The index of the track in the playlist
+    pub index: u64,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    pub cover_id: Option<String>,
+    pub genre: Option<String>,
+    pub track_number: Option<u32>,
+    /// In seconds, missing for the streams
+    pub duration: Option<f64>,
+}
+
+impl PlayerState {
+    pub async fn fetch(
+        client: &LmsClient,
+        player_id: &str,
+        settings: PollSettings,
+    ) -> Result<Self> {
+        let limiter = Limiter::new(settings.concurrency);
+        let (mode, shuffle, repeat, volume, track, playlist_name, buffering, sync_group) = try_join!(
+            limiter.run(client.get_mode(player_id.to_string())),
+            limiter.run(client.get_shuffle(player_id.to_string())),
+            limiter.run(client.get_repeat(player_id.to_string())),
+            limiter.run(client.get_volume(player_id.to_string())),
+            Track::fetch(client, player_id, settings.metadata, &limiter),
+            limiter.run(client.get_playlist_name(player_id.to_string())),
+            limiter.run(client.get_buffering(player_id.to_string())),
+            limiter.run(client.get_sync_group(player_id.to_string())),
+        )?;
+        let position = match track {
+            Some(_) => Some(Position {
+                elapsed: client.get_time(player_id.to_string()).await?,
+                at: Instant::now(),
+            }),
+            None => None,
+        };
+        Ok(Self {
+            mode,
+            shuffle,
+            repeat,
+            volume,
+            track,
+            playlist_name,
+            buffering,
+            sync_group,
+            position,
+        })
+    }
+
+    /// The elapsed time in the current track, extrapolated from the last poll while playing
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.position.map(|position| match self.mode {
+            Mode::Play => position.elapsed + position.at.elapsed(),
+            Mode::Pause | Mode::Stop => position.elapsed,
+        })
+    }
+}
+
+impl Track {
+    /// Get the current track, `None` when the playlist is empty. The profile tells which
+    /// metadata is fetched besides the artist and the title.
+    pub async fn fetch(
+        client: &LmsClient,
+        player_id: &str,
+        metadata: MetadataProfile,
+        limiter: &Limiter,
+    ) -> Result<Option<Self>> {
+        let track_count = limiter
+            .run(client.get_track_count(player_id.to_string()))
+            .await?;
+        if track_count == 0 {
+            return Ok(None);
+        }
+        let (artist, album, title, index, cover_id, details) = try_join!(
+            limiter.run(client.get_artist(player_id.to_string())),
+            limiter.run(async {
+                if metadata == MetadataProfile::Minimal {
+                    Ok(None)
+                } else {
+                    client.get_album(player_id.to_string()).await
+                }
+            }),
+            limiter.run(client.get_title(player_id.to_string())),
+            limiter.run(client.get_index(player_id.to_string())),
+            limiter.run(async {
+                if metadata == MetadataProfile::Minimal {
+                    Ok(None)
+                } else {
+                    client.get_cover_id(player_id.to_string()).await
+                }
+            }),
+            limiter.run(async {
+                if metadata == MetadataProfile::Full {
+                    client.get_track_details(player_id.to_string()).await
+                } else {
+                    Ok(TrackDetails::default())
+                }
+            }),
+        )?;
+        Ok(Some(Self {
+            index,
+            artist,
+            album,
+            title,
+            cover_id,
+            genre: details.genre,
+            track_number: details.track_number,
+            duration: details.duration,
+        }))
+    }
+}
+
+/// Bound the number of queries of a poll sent at once, see `--poll-concurrency`. With a limit of
+/// one, they are sent one after the other.
+pub struct Limiter(Semaphore);
+
+impl Limiter {
+    pub fn new(limit: usize) -> Self {
+        Self(Semaphore::new(limit.max(1)))
+    }
+
+    async fn run<T>(&self, query: impl Future<Output = T>) -> T {
+        // the semaphore is never closed
+        let _permit = self.0.acquire().await.ok();
+        query.await
+    }
+}
+
+/// Keep track of the last time a D-Bus client queried the player
+#[derive(Debug, Default)]
+pub struct Activity {
+    last: Mutex<Option<Instant>>,
+}
+
+impl Activity {
+    pub fn touch(&self) {
+        *self.last.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn is_recent(&self) -> bool {
+        self.last
+            .lock()
+            .unwrap()
+            .is_some_and(|last| last.elapsed() < ACTIVITY_WINDOW)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PollSettings {
+    /// Used while playing or while a client is active
+    pub active: Duration,
+    /// Used otherwise
+    pub idle: Duration,
+    pub metadata: MetadataProfile,
+    /// The number of queries of a poll sent at once
+    pub concurrency: usize,
+}
+
+/// Whether the short interval is used, while playing or while a client is active
+pub fn polls_actively(state: &PlayerState, activity: &Activity) -> bool {
+    state.mode == Mode::Play || activity.is_recent()
+}
+
+/// Poll LMS for the state of the player and emit the `PropertiesChanged` signals accordingly. The
+/// state is also published in the watch channel for the other consumers. They are notified only
+/// when the state changes, but the position is resynchronized on each poll. With the notifications
+/// of the server, a notification about the player triggers a poll without waiting. The signals are
+/// rate-limited so that a burst of changes is emitted at once. When the circuit breaker opens, the
+/// last state is kept until the server answers again. Without a connection, such as with the
+/// `watch` subcommand, the state is only published in the channel.
+pub async fn poll(
+    client: Arc<LmsClient>,
+    player_id: String,
+    connection: Option<watch::Receiver<Connection>>,
+    settings: PollSettings,
+    activity: Arc<Activity>,
+    sender: watch::Sender<PlayerState>,
+    mut events: Option<broadcast::Receiver<Event>>,
+) -> Result<()> {
+    let mut state = PlayerState::fetch(&client, &player_id, settings).await?;
+    sender.send_replace(state.clone());
+    let mut last_emission: Option<Instant> = None;
+    loop {
+        if client.is_circuit_open() {
+            wait_for_server(&client, connection.as_ref()).await;
+        } else {
+            let interval = if polls_actively(&state, &activity) {
+                settings.active
+            } else {
+                settings.idle
+            };
+            match events.as_mut() {
+                Some(receiver) => select! {
+                    _ = sleep(interval) => (),
+                    closed = wait_for_notification(receiver, &player_id) => {
+                        if closed {
+                            events = None;
+                        }
+                    }
+                },
+                None => sleep(interval).await,
+            }
+        }
+
+        let Some(mut new_state) = fetch(&client, &player_id, settings).await? else {
+            continue;
+        };
+        if new_state != state {
+            if let Some(elapsed) = last_emission.map(|instant| instant.elapsed()) {
+                if elapsed < COALESCE_WINDOW {
+                    // wait for the end of the burst
+                    sleep(COALESCE_WINDOW - elapsed).await;
+                    let Some(refetched) = fetch(&client, &player_id, settings).await? else {
+                        continue;
+                    };
+                    new_state = refetched;
+                }
+            }
+        }
+        if new_state != state {
+            debug!("State changed: {:?}", new_state);
+            // the connection is replaced when the session bus restarts
+            if let Some(connection) = connection
+                .as_ref()
+                .map(|receiver| receiver.borrow().clone())
+            {
+                if let Err(error) = properties_changed(&connection, &state, &new_state).await {
+                    warn!("Unable to notify the MPRIS clients: {}", error);
+                }
+            }
+            sender.send_replace(new_state.clone());
+            last_emission = Some(Instant::now());
+        } else {
+            sender.send_if_modified(|current| {
+                current.position = new_state.position;
+                false
+            });
+        }
+        state = new_state;
+    }
+}
+
+/// Fetch the state, `None` when the failure is tolerated such as with the circuit breaker
+async fn fetch(
+    client: &LmsClient,
+    player_id: &str,
+    settings: PollSettings,
+) -> Result<Option<PlayerState>> {
+    match PlayerState::fetch(client, player_id, settings).await {
+        Ok(state) => Ok(Some(state)),
+        Err(error) if client.tolerates(&error) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Probe the server until it answers, less and less often. The clients are told that the state is
+/// stale meanwhile, and when the server is back.
+async fn wait_for_server(client: &LmsClient, connection: Option<&watch::Receiver<Connection>>) {
+    let started = Instant::now();
+    if let Some(current) = connection.map(|receiver| receiver.borrow().clone()) {
+        if let Err(error) = stale_changed(&current).await {
+            warn!("Unable to notify the MPRIS clients: {}", error);
+        }
+    }
+    let mut delay = PROBE_DELAY;
+    loop {
+        sleep(delay).await;
+        match client.probe().await {
+            Ok(()) => break,
+            Err(error) => debug!("The server doesn't answer yet: {:#}", error),
+        }
+        delay = (delay * 2).min(MAX_PROBE_DELAY);
+    }
+    let outage = started.elapsed();
+    info!("The server is back after {:?}", outage);
+    if let Some(current) = connection.map(|receiver| receiver.borrow().clone()) {
+        if let Err(error) = server_recovered(&current, outage).await {
+            warn!("Unable to notify the MPRIS clients: {}", error);
+        }
+    }
+}
+
+/// Wait for a notification about the player or about the sync groups, the ones received meanwhile
+/// are skipped as a single poll covers them all. Return whether the channel is closed.
+async fn wait_for_notification(receiver: &mut broadcast::Receiver<Event>, player_id: &str) -> bool {
+    loop {
+        match receiver.recv().await {
+            Ok(Event::Notification {
+                player_id: Some(id),
+                params,
+            }) if id.eq_ignore_ascii_case(player_id) => {
+                debug!("Notification about the player: {:?}", params);
+                break;
+            }
+            // another player may join or leave the sync group of the player
+            Ok(Event::Notification { params, .. })
+                if params.first().is_some_and(|param| param == "sync") =>
+            {
+                debug!("Sync notification: {:?}", params);
+                break;
+            }
+            Ok(_) => (),
+            // some notifications have been missed, they may be about the player
+            Err(RecvError::Lagged(_)) => break,
+            Err(RecvError::Closed) => return true,
+        }
+    }
+    while let Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) = receiver.try_recv() {}
+    false
+}
@@ -0,0 +1,20 @@
+//! Serve the state of the player as JSON on `/`, and `/healthz` for health checks
+use crate::{
+    http::{self, Response},
+    state::PlayerState,
+};
+use anyhow::Result;
+use std::net::SocketAddr;
+use tokio::sync::watch;
+
+pub async fn serve(address: SocketAddr, state: watch::Receiver<PlayerState>) -> Result<()> {
+    http::serve(address, move |path| match path {
+        "/" => match serde_json::to_vec(&*state.borrow()) {
+            Ok(body) => Response::ok("application/json", body),
+            Err(error) => Response::internal_error(error.to_string()),
+        },
+        "/healthz" => Response::ok("text/plain", "ok\n"),
+        _ => Response::not_found(),
+    })
+    .await
+}
@@ -0,0 +1,89 @@
+//! Stop the player at the end of the current track. It is armed on demand, through the custom
+//! D-Bus interface, and disarms itself once the player has been stopped.
+use crate::{
+    lms::{LmsClient, Mode},
+    state::PlayerState,
+};
+use anyhow::{bail, Result};
+use log::info;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+    time::sleep,
+};
+
+/// Stop slightly before the end of the track, so that the next one doesn't start
+const MARGIN: Duration = Duration::from_millis(500);
+
+/// The remaining time is checked again at least this often, in case of a seek
+const RECHECK: Duration = Duration::from_secs(10);
+
+/// Stop the player at the end of the current track when a `true` request is received, `false`
+/// cancels it
+pub async fn run(
+    client: Arc<LmsClient>,
+    player_id: String,
+    mut state: watch::Receiver<PlayerState>,
+    mut requests: mpsc::UnboundedReceiver<bool>,
+) -> Result<()> {
+    // the index of the track to stop after
+    let mut armed: Option<u64> = None;
+    loop {
+        let remaining = match armed {
+            Some(_) if state.borrow().mode == Mode::Play => remaining(&client, &player_id).await?,
+            _ => None,
+        };
+        // without a duration, e.g. for a stream, wait for the track to change
+        let wait = remaining.map(|remaining| remaining.saturating_sub(MARGIN));
+
+        select! {
+            request = requests.recv() => match request {
+                Some(true) => {
+                    armed = state.borrow().track.as_ref().map(|track| track.index);
+                    match armed {
+                        Some(index) => info!("Stopping after the track {}", index),
+                        None => info!("The playlist is empty, nothing to stop after"),
+                    }
+                }
+                Some(false) => {
+                    if armed.take().is_some() {
+                        info!("Not stopping after the current track anymore");
+                    }
+                }
+                None => bail!("Unable to wait for the stop requests"),
+            },
+            changed = state.changed() => {
+                changed?;
+                let state = state.borrow_and_update().clone();
+                if let Some(index) = armed {
+                    if state.mode == Mode::Stop {
+                        info!("The player has been stopped, disarming");
+                        armed = None;
+                    } else if state.track.map(|track| track.index) != Some(index) {
+                        stop(&client, &player_id).await?;
+                        armed = None;
+                    }
+                }
+            },
+            _ = sleep(wait.unwrap_or_default().min(RECHECK)), if wait.is_some() => {
+                if wait.is_some_and(|wait| wait <= RECHECK) {
+                    stop(&client, &player_id).await?;
+                    armed = None;
+                }
+            },
+        }
+    }
+}
+
+/// The time left in the current track, `None` when it has no duration
+async fn remaining(client: &LmsClient, player_id: &str) -> Result<Option<Duration>> {
+    let duration = client.get_duration(player_id.to_string()).await?;
+    let time = client.get_time(player_id.to_string()).await?;
+    Ok(duration.map(|duration| duration.saturating_sub(time)))
+}
+
+async fn stop(client: &LmsClient, player_id: &str) -> Result<()> {
+    info!("End of the track, stopping the player");
+    client.stop(player_id.to_string()).await
+}
@@ -0,0 +1,78 @@
+//! The mapping between the MPRIS volume, between 0.0 and 1.0, and the LMS volume, between 0 and
+//! 100. A linear mapping makes most of the range of the slider too loud on many DACs.
+use anyhow::{anyhow, Error};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeCurve {
+    Linear,
+    /// Most of the slider is used for the lower volumes
+    Logarithmic,
+    /// `lms = 100 * mpris ^ exponent`
+    Exponent(f64),
+}
+
+impl VolumeCurve {
+    pub fn to_lms(self, volume: f64) -> u64 {
+        let volume = volume.clamp(0.0, 1.0);
+        let lms = match self {
+            VolumeCurve::Linear => volume,
+            VolumeCurve::Logarithmic => (10f64.powf(2.0 * volume) - 1.0) / 99.0,
+            VolumeCurve::Exponent(exponent) => volume.powf(exponent),
+        };
+        (lms * 100.0).round() as u64
+    }
+
+    pub fn to_mpris(self, volume: u64) -> f64 {
+        let volume = (volume.min(100) as f64) / 100.0;
+        match self {
+            VolumeCurve::Linear => volume,
+            VolumeCurve::Logarithmic => (volume * 99.0 + 1.0).log10() / 2.0,
+            VolumeCurve::Exponent(exponent) => volume.powf(1.0 / exponent),
+        }
+    }
+}
+
+/// The volume given to the `control volume` subcommand
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VolumeChange {
+    /// Between 0 and 100
+    Set(u64),
+    /// Up or down by the given amount, from a signed number
+    Relative(i64),
+}
+
+impl FromStr for VolumeChange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || anyhow!("Expected a volume between 0 and 100, +N or -N, got {}", s);
+        if s.starts_with(['+', '-']) {
+            s.parse().map(VolumeChange::Relative).map_err(|_| error())
+        } else {
+            match s.parse() {
+                Ok(volume) if volume <= 100 => Ok(VolumeChange::Set(volume)),
+                _ => Err(error()),
+            }
+        }
+    }
+}
+
+impl FromStr for VolumeCurve {
+    type Err = Error;
+
+    /// `linear`, `logarithmic` or the exponent as a positive number
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(VolumeCurve::Linear),
+            "logarithmic" => Ok(VolumeCurve::Logarithmic),
+            _ => match s.parse::<f64>() {
+                Ok(exponent) if exponent > 0.0 => Ok(VolumeCurve::Exponent(exponent)),
+                _ => Err(anyhow!(
+                    "Expected linear, logarithmic or a positive exponent, got {}",
+                    s
+                )),
+            },
+        }
+    }
+}